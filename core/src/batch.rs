@@ -0,0 +1,71 @@
+//! Transforming many Markdown files at once
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::{
+    textproc::{Images, TextProcState},
+    transform_with, Error, TransformOptions,
+};
+
+/// The result of transforming a file by [`transform_paths`].
+#[derive(Debug)]
+pub struct FileResult {
+    pub path: PathBuf,
+    /// The transformed document, or the problems that prevented the file from
+    /// being read or transformed
+    pub output: Result<String, Error>,
+}
+
+/// Transform the Markdown files at `paths` like doc comments wrapped by
+/// `svgbobdoc::transform!`. The results are returned in the order of `paths`.
+///
+/// A diagram appearing in more than one file is usually rendered only once.
+/// With the `parallel` Cargo feature, the files are transformed on as many
+/// threads as there are CPUs, in which case the files transformed at the same
+/// time may render the same diagram.
+///
+/// With `shared-labels`, a file can only reference the labels exported by the
+/// files transformed before it, i.e., those preceding it in `paths` unless
+/// the files are transformed in parallel. Likewise, `number-figures` numbers
+/// the figures across the files in the order in which they're transformed.
+pub fn transform_paths(
+    paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    options: &TransformOptions,
+) -> Vec<FileResult> {
+    let paths: Vec<PathBuf> = paths.into_iter().map(|p| p.as_ref().to_owned()).collect();
+    let defaults = match options.parse_defaults() {
+        Ok(defaults) => defaults,
+        Err(e) => {
+            return paths
+                .into_iter()
+                .map(|path| FileResult {
+                    path,
+                    output: Err(e.clone()),
+                })
+                .collect()
+        }
+    };
+
+    let cache: Mutex<Images> = Mutex::default();
+    let transform = |path: PathBuf| {
+        let output = std::fs::read_to_string(&path)
+            .map_err(|e| Error::new(None, format!("couldn't read `{}`: {}", path.display(), e)))
+            .and_then(|markdown| {
+                // Take the cache while transforming the file instead of
+                // holding the lock. A file transformed at the same time gets
+                // an empty one.
+                let images = std::mem::take(&mut *cache.lock().unwrap());
+                let mut state = TextProcState::with_images(defaults.clone(), images);
+                let output = transform_with(&mut state, &markdown);
+                cache.lock().unwrap().extend(state.into_images());
+                output
+            });
+        FileResult { path, output }
+    };
+    #[cfg(feature = "parallel")]
+    return crate::parallel::map(paths, transform);
+    #[cfg(not(feature = "parallel"))]
+    paths.into_iter().map(transform).collect()
+}
@@ -0,0 +1,114 @@
+//! An mdbook preprocessor rendering ASCII-diagram code blocks as SVG images.
+//!
+//! ```text
+//! mdbook-svgbob                      # Process the book on the standard input
+//! mdbook-svgbob supports RENDERER    # Exit with `0` if RENDERER is supported
+//! ```
+//!
+//! The default options can be specified in `book.toml`:
+//!
+//! ```toml
+//! [preprocessor.svgbob]
+//! options = "mode=img"
+//! ```
+use serde_json::Value;
+use std::process::ExitCode;
+
+use svgbobdoc_core::{transform_markdown_with, TransformOptions};
+
+/// The renderers whose output can display the images.
+const RENDERERS: &[&str] = &["html"];
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match &args[..] {
+        [] => {}
+        [command, renderer] if command == "supports" => {
+            return if RENDERERS.contains(&renderer.as_str()) {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::from(1)
+            };
+        }
+        _ => {
+            eprintln!("usage: mdbook-svgbob [supports RENDERER]");
+            return ExitCode::from(2);
+        }
+    }
+
+    let input: Value = match serde_json::from_reader(std::io::stdin().lock()) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("mdbook-svgbob: couldn't parse the input: {}", e);
+            return ExitCode::from(2);
+        }
+    };
+    let Value::Array(mut input) = input else {
+        eprintln!("mdbook-svgbob: the input must be `[context, book]`");
+        return ExitCode::from(2);
+    };
+    if input.len() != 2 {
+        eprintln!("mdbook-svgbob: the input must be `[context, book]`");
+        return ExitCode::from(2);
+    }
+    let mut book = input.pop().unwrap();
+    let context = input.pop().unwrap();
+
+    // Other renderers get the book untouched
+    let renderer = context["renderer"].as_str().unwrap_or_default();
+    let mut failed = false;
+    if RENDERERS.contains(&renderer) {
+        let options = TransformOptions {
+            defaults: context["config"]["preprocessor"]["svgbob"]["options"]
+                .as_str()
+                .unwrap_or_default()
+                .to_owned(),
+        };
+        if let Some(sections) = book["sections"].as_array_mut() {
+            for item in sections {
+                failed |= !transform_item(item, &options);
+            }
+        }
+    }
+    if failed {
+        return ExitCode::from(1);
+    }
+
+    println!("{}", book);
+    ExitCode::SUCCESS
+}
+
+/// Transform a book item (`{"Chapter": ...}`, `{"PartTitle": ...}`, or
+/// `"Separator"`) and its sub-items. Returns `false` if any of the chapters
+/// couldn't be transformed, after reporting the problems.
+fn transform_item(item: &mut Value, options: &TransformOptions) -> bool {
+    let Some(chapter) = item.get_mut("Chapter") else {
+        return true;
+    };
+    let mut ok = true;
+    if let Some(content) = chapter.get("content").and_then(Value::as_str) {
+        match transform_markdown_with(content, options) {
+            Ok(output) => chapter["content"] = Value::String(output),
+            Err(e) => {
+                let path = chapter["source_path"]
+                    .as_str()
+                    .or(chapter["path"].as_str())
+                    .or(chapter["name"].as_str())
+                    .unwrap_or_default();
+                for problem in e.problems() {
+                    match problem.line {
+                        Some(line) => eprintln!("{}:{}: {}", path, line, problem.message),
+                        None => eprintln!("{}: {}", path, problem.message),
+                    }
+                }
+                ok = false;
+            }
+        }
+    }
+    if let Some(sub_items) = chapter.get_mut("sub_items").and_then(Value::as_array_mut) {
+        for item in sub_items {
+            ok &= transform_item(item, options);
+        }
+    }
+    ok
+}
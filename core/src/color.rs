@@ -0,0 +1,276 @@
+//! Parsing of CSS colors
+
+/// An RGBA color.
+pub type Rgba = [u8; 4];
+
+/// Parse a CSS color in one of the following forms: `#rgb`, `#rgba`,
+/// `#rrggbb`, `#rrggbbaa`, a named color (e.g., `navy`), or `transparent`.
+pub fn parse_color(s: &str) -> Result<Rgba, String> {
+    let invalid = || format!("invalid color: `{}`", s);
+
+    if let Some(hex) = s.strip_prefix('#') {
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        return match hex.len() {
+            3 => Ok([digit(0) * 17, digit(1) * 17, digit(2) * 17, 255]),
+            4 => Ok([digit(0) * 17, digit(1) * 17, digit(2) * 17, digit(3) * 17]),
+            6 => Ok([byte(0), byte(2), byte(4), 255]),
+            8 => Ok([byte(0), byte(2), byte(4), byte(6)]),
+            _ => Err(invalid()),
+        };
+    }
+
+    let name = s.to_ascii_lowercase();
+    if name == "transparent" {
+        return Ok([0, 0, 0, 0]);
+    }
+
+    let i = NAMED_COLORS
+        .binary_search_by_key(&&*name, |&(name, _)| name)
+        .map_err(|_| invalid())?;
+    let [_, r, g, b] = NAMED_COLORS[i].1.to_be_bytes();
+    Ok([r, g, b, 255])
+}
+
+/// The minimum contrast ratio accepted by `check-contrast`, which is what WCAG
+/// 2.1 requires for graphical objects and large text.
+pub const MIN_CONTRAST_RATIO: f64 = 3.0;
+
+/// Format a color as `#rrggbb` or, if it's not opaque, `#rrggbbaa`.
+pub fn to_hex([r, g, b, a]: Rgba) -> String {
+    if a == 255 {
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+    }
+}
+
+/// Composite `fg` over `bg`. The result is opaque if `bg` is.
+pub fn blend(fg: Rgba, bg: Rgba) -> Rgba {
+    let alpha = fg[3] as u32;
+    let mix = |i: usize| ((fg[i] as u32 * alpha + bg[i] as u32 * (255 - alpha) + 127) / 255) as u8;
+    [
+        mix(0),
+        mix(1),
+        mix(2),
+        (alpha + bg[3] as u32 * (255 - alpha) / 255) as u8,
+    ]
+}
+
+/// Calculate the relative luminance of an opaque color as defined by WCAG 2.1.
+fn relative_luminance([r, g, b, _]: Rgba) -> f64 {
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+/// Calculate the contrast ratio (1 to 21) between two opaque colors as
+/// defined by WCAG 2.1.
+pub fn contrast_ratio(a: Rgba, b: Rgba) -> f64 {
+    let (a, b) = (relative_luminance(a), relative_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// The CSS named colors, sorted by name.
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_color("#f80"), Ok([0xff, 0x88, 0x00, 0xff]));
+        assert_eq!(parse_color("#12345678"), Ok([0x12, 0x34, 0x56, 0x78]));
+        assert_eq!(parse_color("NavY"), Ok([0x00, 0x00, 0x80, 0xff]));
+        assert_eq!(parse_color("transparent"), Ok([0, 0, 0, 0]));
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#ggg").is_err());
+        assert!(parse_color("blurple").is_err());
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(to_hex([0xd3, 0xd3, 0xd3, 0xff]), "#d3d3d3");
+        assert_eq!(to_hex([0, 0, 0, 0]), "#00000000");
+    }
+
+    #[test]
+    fn blending() {
+        let white = [255, 255, 255, 255];
+        assert_eq!(blend([0, 0, 0, 0], white), white);
+        assert_eq!(blend([0, 0, 0, 255], white), [0, 0, 0, 255]);
+        assert_eq!(blend([0, 0, 0, 128], white), [127, 127, 127, 255]);
+        assert_eq!(blend([0, 0, 0, 0], [0, 0, 0, 0]), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn contrast() {
+        let ratio =
+            |a: &str, b: &str| contrast_ratio(parse_color(a).unwrap(), parse_color(b).unwrap());
+        assert!((ratio("black", "white") - 21.0).abs() < 1e-9);
+        assert!((ratio("white", "white") - 1.0).abs() < 1e-9);
+        assert_eq!(ratio("white", "black"), ratio("black", "white"));
+        // Reference values from the WebAIM contrast checker
+        assert!((ratio("#777", "white") - 4.48).abs() < 0.01);
+        assert!((ratio("lightgray", "white") - 1.50).abs() < 0.01);
+    }
+}
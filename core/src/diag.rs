@@ -0,0 +1,108 @@
+//! Errors and non-fatal diagnostics
+use std::fmt;
+
+/// A part of the text processor's input, such as a line of a document. What
+/// the index refers to is up to the caller, which maps it back to the source
+/// code when reporting problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Span(Option<usize>);
+
+impl Span {
+    pub fn new(index: usize) -> Self {
+        Self(Some(index))
+    }
+
+    /// The span of the whole input, for the problems that aren't found in a
+    /// specific part of it.
+    pub fn call_site() -> Self {
+        Self(None)
+    }
+
+    pub fn index(self) -> Option<usize> {
+        self.0
+    }
+}
+
+/// An error consisting of one or more messages, each with a span.
+#[derive(Debug, Clone)]
+pub struct Error {
+    messages: Vec<(Span, String)>,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn new(span: Span, message: impl fmt::Display) -> Self {
+        Self {
+            messages: vec![(span, message.to_string())],
+        }
+    }
+
+    /// Get the span of the first message.
+    pub fn span(&self) -> Span {
+        self.messages[0].0
+    }
+
+    /// Add the messages of another error.
+    pub fn combine(&mut self, other: Self) {
+        self.messages.extend(other.messages);
+    }
+}
+
+impl IntoIterator for Error {
+    type Item = (Span, String);
+    type IntoIter = std::vec::IntoIter<(Span, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.messages.into_iter()
+    }
+}
+
+/// Displays the first message.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.messages[0].1)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Report a warning.
+///
+/// Stable Rust doesn't provide a way for proc macros to emit warnings, so the
+/// message is written to the standard error output, which Cargo displays as
+/// part of the compiler output.
+pub fn warn(message: impl fmt::Display) {
+    eprintln!("warning: svgbobdoc: {}", message);
+}
+
+/// Where the warnings found in an input go. By default, they're reported by
+/// [`warn`]. A caller that can point at their spans collects them instead.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    collected: Option<Vec<(Span, String)>>,
+}
+
+impl Warnings {
+    /// Collect the warnings instead of reporting them.
+    pub fn collecting() -> Self {
+        Self {
+            collected: Some(Vec::new()),
+        }
+    }
+
+    pub fn warn(&mut self, span: Span, message: impl fmt::Display) {
+        match &mut self.collected {
+            Some(warnings) => warnings.push((span, message.to_string())),
+            None => warn(message),
+        }
+    }
+
+    /// Take the warnings collected so far.
+    pub fn take(&mut self) -> Vec<(Span, String)> {
+        self.collected
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,100 @@
+//! Support for `storage=file`
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{options::Options, render::content_hash};
+
+/// Write an SVG image to a file named after its content hash and return the
+/// URL to refer to it by.
+///
+/// The file is written to the directory specified by `file-dir` (relative to
+/// `CARGO_MANIFEST_DIR`) or `$OUT_DIR/svgbobdoc` otherwise. Returns `Ok(None)`
+/// if neither is available, in which case the caller should fall back to
+/// embedding the image. The URL is built by appending the file name to
+/// `file-url`, which is required because proc macros can't know where
+/// rustdoc's output will be served from.
+pub fn write_svg_file(svg_code: &str, options: &Options) -> Result<Option<String>, String> {
+    let file_url = options.file_url.as_ref().ok_or(
+        "`storage=file` requires `file-url=URL`, the URL where the documentation \
+         can find the SVG files",
+    )?;
+    let dir: PathBuf = if let Some(file_dir) = &options.file_dir {
+        let manifest_dir =
+            std::env::var_os("CARGO_MANIFEST_DIR").ok_or("`CARGO_MANIFEST_DIR` is not set")?;
+        Path::new(&manifest_dir).join(file_dir)
+    } else if let Some(out_dir) = std::env::var_os("OUT_DIR") {
+        Path::new(&out_dir).join("svgbobdoc")
+    } else {
+        return Ok(None);
+    };
+
+    let file_name = format!("{:016x}.svg", content_hash(svg_code.as_bytes()));
+    let path = dir.join(&file_name);
+
+    // Identical diagrams share the same file
+    if !path.exists() {
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("failed to create `{}`: {}", dir.display(), e))?;
+
+        // Write to a temporary file first so that concurrent compiler
+        // processes never observe a partially-written file
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp_path = dir.join(format!(
+            "{}.{}-{}.tmp",
+            file_name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temp_path, svg_code)
+            .map_err(|e| format!("failed to write `{}`: {}", temp_path.display(), e))?;
+        std::fs::rename(&temp_path, &path).map_err(|e| {
+            let _ = std::fs::remove_file(&temp_path);
+            format!("failed to write `{}`: {}", path.display(), e)
+        })?;
+    }
+
+    let mut url = file_url.clone();
+    if !url.is_empty() && !url.ends_with('/') {
+        url.push('/');
+    }
+    url.push_str(&file_name);
+
+    Ok(Some(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_url() {
+        let dir = std::env::temp_dir().join(format!("svgbobdoc-files-{}", std::process::id()));
+        let mut options = Options {
+            file_dir: Some(dir.to_str().unwrap().to_owned()),
+            ..Options::default()
+        };
+        let e = write_svg_file("<svg/>", &options).unwrap_err();
+        assert!(
+            e.starts_with("`storage=file` requires `file-url=URL`"),
+            "{}",
+            e
+        );
+
+        for file_url in ["../static/svgbob", "https://example.com/svgbob/"] {
+            options.file_url = Some(file_url.to_owned());
+            let url = write_svg_file("<svg/>", &options).unwrap().unwrap();
+            let file_name = url.rsplit('/').next().unwrap();
+            assert_eq!(
+                url,
+                format!("{}/{}", file_url.trim_end_matches('/'), file_name)
+            );
+            assert_eq!(
+                std::fs::read_to_string(dir.join(file_name)).unwrap(),
+                "<svg/>"
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! The Markdown processor behind [svgbobdoc], which renders ASCII-diagram code
+//! blocks (`svgbob`) as SVG images. Unlike svgbobdoc's macros, it can process
+//! any Markdown document, such as a page of a static site.
+//!
+//! ```
+//! let output = svgbobdoc_core::transform_markdown("```svgbob\n-->\n```\n").unwrap();
+//! assert!(output.contains("](data:image/svg+xml;base64,"));
+//! ```
+//!
+//! Many files, such as the chapters of a book, can be transformed at once by
+//! [`batch::transform_paths`].
+//!
+//! Documents are transformed just like doc comments wrapped by
+//! `svgbobdoc::transform!`, with the same options and Cargo features. Without
+//! the `enable` feature, diagrams are rendered as plain texts.
+//!
+//! [svgbobdoc]: https://docs.rs/svgbobdoc/
+#![warn(rust_2018_idioms)]
+
+use std::fmt;
+
+pub mod batch;
+mod color;
+#[doc(hidden)]
+pub mod diag;
+#[cfg(feature = "files")]
+mod files;
+mod manifest;
+#[doc(hidden)]
+pub mod options;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod render;
+#[doc(hidden)]
+pub mod stats;
+#[doc(hidden)]
+pub mod textproc;
+mod timeout;
+
+/// Render the ASCII-diagram code blocks in a Markdown document as SVG images
+/// and return the transformed document.
+pub fn transform_markdown(markdown: &str) -> Result<String, Error> {
+    transform_markdown_with(markdown, &TransformOptions::default())
+}
+
+/// Like [`transform_markdown`] but uses the specified options.
+pub fn transform_markdown_with(
+    markdown: &str,
+    options: &TransformOptions,
+) -> Result<String, Error> {
+    let mut state = textproc::TextProcState::new(options.parse_defaults()?);
+    transform_with(&mut state, markdown)
+}
+
+/// The options of [`transform_markdown_with`] and [`batch::transform_paths`].
+#[derive(Debug, Clone, Default)]
+pub struct TransformOptions {
+    /// The options applied to the code blocks that don't override them,
+    /// written like `SVGBOBDOC_OPTIONS` (e.g., `"mode=img,dark-variant"`)
+    pub defaults: String,
+}
+
+impl TransformOptions {
+    fn parse_defaults(&self) -> Result<options::Options, Error> {
+        let mut defaults = options::Options::default();
+        defaults
+            .apply_list(&self.defaults)
+            .map_err(|e| Error::new(None, format!("invalid default options: {}", e)))?;
+        Ok(defaults)
+    }
+}
+
+/// Transform a Markdown document by `state`, giving each line a span whose
+/// index is its line number minus one.
+fn transform_with(state: &mut textproc::TextProcState, markdown: &str) -> Result<String, Error> {
+    // The text processor ends each line with a line break
+    let body = markdown.strip_suffix('\n').unwrap_or(markdown);
+    let mut output = state
+        .transform_document(
+            body.split('\n')
+                .enumerate()
+                .map(|(i, line)| Ok((line.to_owned(), vec![diag::Span::new(i)]))),
+        )
+        .map_err(Error::from_diag)?;
+    if body.len() == markdown.len() {
+        output.pop();
+    }
+    Ok(output)
+}
+
+/// The problems that prevented a Markdown document from being transformed.
+#[derive(Debug, Clone)]
+pub struct Error {
+    problems: Vec<Problem>,
+}
+
+/// A problem found in a Markdown document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// The line number (starting at 1), if the problem is in a specific line.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Error {
+    /// Get the problems, in the order in which they appear in the document.
+    pub fn problems(&self) -> &[Problem] {
+        &self.problems
+    }
+
+    fn new(line: Option<usize>, message: String) -> Self {
+        Self {
+            problems: vec![Problem { line, message }],
+        }
+    }
+
+    fn from_diag(error: diag::Error) -> Self {
+        let mut problems: Vec<Problem> = error
+            .into_iter()
+            .map(|(span, message)| Problem {
+                line: span.index().map(|i| i + 1),
+                message,
+            })
+            .collect();
+        problems.sort_by_key(|problem| problem.line);
+        Self { problems }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            if let Some(line) = problem.line {
+                write!(f, "line {}: ", line)?;
+            }
+            f.write_str(&problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,267 @@
+//! Reading the crate-wide options from `Cargo.toml`
+//!
+//! Only a small subset of TOML is recognized: the options must be written as
+//! a `[package.metadata.svgbobdoc]` table whose values are strings, numbers,
+//! or booleans on a single line each.
+
+/// The path of the table containing the options.
+const TABLE: [&str; 3] = ["package", "metadata", "svgbobdoc"];
+
+/// Get the options in the `[package.metadata.svgbobdoc]` table of a
+/// `Cargo.toml` as `(key, value)` pairs, in which underscores in keys are
+/// replaced with hyphens. Returns `None` if there's no such table.
+pub fn metadata_options(manifest: &str) -> Result<Option<Vec<(String, String)>>, String> {
+    let mut options = None;
+    let mut table = Vec::new();
+    for (i, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let with_line = |e: String| format!("line {}: {}", i + 1, e);
+
+        if let Some(header) = line.strip_prefix('[') {
+            // An array of tables (`[[bin]]`) never contains the options
+            table = match header.strip_prefix('[') {
+                Some(_) => Vec::new(),
+                None => {
+                    let end = header
+                        .find(']')
+                        .ok_or_else(|| with_line("unclosed table header".to_owned()))?;
+                    parse_key(&header[..end]).map_err(with_line)?
+                }
+            };
+            if table.iter().map(String::as_str).take(3).eq(TABLE) {
+                if table.len() > 3 {
+                    return Err(with_line(
+                        "the options must be strings, numbers, or booleans".to_owned(),
+                    ));
+                }
+                options.get_or_insert_with(Vec::new);
+            }
+            continue;
+        }
+
+        let (key, value) = match split_key_value(line) {
+            Some(key_value) => key_value,
+            // Not a key/value pair, e.g., a part of a multi-line array
+            None => continue,
+        };
+        let key = parse_key(key).map_err(with_line)?;
+        let path: Vec<&str> = table.iter().chain(&key).map(String::as_str).collect();
+        if path.starts_with(&TABLE) {
+            if path.len() != 4 {
+                return Err(with_line(
+                    "the options must be written as a `[package.metadata.svgbobdoc]` table"
+                        .to_owned(),
+                ));
+            }
+            let value = parse_value(value)
+                .map_err(|e| with_line(format!("option `{}`: {}", path[3], e)))?;
+            options
+                .get_or_insert_with(Vec::new)
+                .push((path[3].replace('_', "-"), value));
+        }
+    }
+    Ok(options)
+}
+
+/// Split `key = value` at the `=` outside quotes.
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    for (i, ch) in line.char_indices() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(q), _) if ch == q => quote = None,
+            (None, '=') => return Some((&line[..i], &line[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse a dotted key (e.g., `package.metadata."svgbobdoc"`).
+fn parse_key(s: &str) -> Result<Vec<String>, String> {
+    s.split('.')
+        .map(|part| {
+            let part = part.trim();
+            let unquoted = ['"', '\'']
+                .iter()
+                .find_map(|&q| part.strip_prefix(q)?.strip_suffix(q));
+            match unquoted {
+                Some(unquoted) => Ok(unquoted.to_owned()),
+                None if !part.is_empty()
+                    && part
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+                {
+                    Ok(part.to_owned())
+                }
+                None => Err(format!("unsupported key `{}`", s.trim())),
+            }
+        })
+        .collect()
+}
+
+/// Parse a string, number, or boolean, followed by an optional comment.
+/// Numbers and booleans are returned as written, without underscores.
+fn parse_value(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    let unsupported =
+        || "the value must be a string, number, or boolean on a single line".to_owned();
+    let (value, rest) = if let Some(content) = s.strip_prefix('\'') {
+        if content.starts_with("''") {
+            return Err(unsupported());
+        }
+        let end = content.find('\'').ok_or_else(unsupported)?;
+        (content[..end].to_owned(), &content[end + 1..])
+    } else if let Some(content) = s.strip_prefix('"') {
+        if content.starts_with("\"\"") {
+            return Err(unsupported());
+        }
+        let mut value = String::new();
+        let mut chars = content.char_indices();
+        let end = loop {
+            let (i, ch) = chars.next().ok_or_else(unsupported)?;
+            match ch {
+                '"' => break i,
+                '\\' => {
+                    let ch = match chars.next().ok_or_else(unsupported)?.1 {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        ch @ ('"' | '\\') => ch,
+                        escape @ ('u' | 'U') => {
+                            let len = if escape == 'u' { 4 } else { 8 };
+                            let digits: String = (0..len)
+                                .map(|_| chars.next().map(|(_, ch)| ch))
+                                .collect::<Option<_>>()
+                                .ok_or_else(unsupported)?;
+                            u32::from_str_radix(&digits, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| format!("invalid escape `\\{}{}`", escape, digits))?
+                        }
+                        ch => return Err(format!("invalid escape `\\{}`", ch)),
+                    };
+                    value.push(ch);
+                }
+                ch => value.push(ch),
+            }
+        };
+        (value, &content[end + 1..])
+    } else {
+        let end = s
+            .find(|c: char| c.is_whitespace() || c == '#')
+            .unwrap_or(s.len());
+        let value = &s[..end];
+        let is_number = value
+            .trim_start_matches(['+', '-'])
+            .starts_with(|c: char| c.is_ascii_digit());
+        if value != "true" && value != "false" && !is_number {
+            return Err(unsupported());
+        }
+        (value.replace('_', ""), &s[end..])
+    };
+
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(unsupported());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(options: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            options
+                .iter()
+                .map(|&(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn table() {
+        let manifest = r##"
+[package]
+name = "a"
+metadata.other = { mode = "svg" }
+metadata.svgbobdoc.class = "a"
+
+[package.metadata.svgbobdoc]
+mode = "img" # a comment
+dark_variant = true
+"font" = 'JetBrains Mono'
+size-limit = 1_000
+stroke = "#0\"a\\"
+
+[[bin]]
+name = "b"
+mode = "svg"
+"##;
+        assert_eq!(
+            metadata_options(manifest).unwrap(),
+            pairs(&[
+                ("class", "a"),
+                ("mode", "img"),
+                ("dark-variant", "true"),
+                ("font", "JetBrains Mono"),
+                ("size-limit", "1000"),
+                ("stroke", "#0\"a\\"),
+            ])
+        );
+
+        assert_eq!(
+            metadata_options("[ package . metadata . svgbobdoc ]\n").unwrap(),
+            pairs(&[])
+        );
+        assert_eq!(metadata_options("[package]\nname = \"a\"\n").unwrap(), None);
+    }
+
+    #[test]
+    fn defaults() {
+        use crate::options::{Options, OutputMode};
+
+        let dir = std::env::temp_dir().join(format!("svgbobdoc-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[package.metadata.svgbobdoc]\nmode = \"img\"\nclass = \"a\"\ntimeout = 5\n",
+        )
+        .unwrap();
+        let (options, manifest) = Options::from_vars(|name| match name {
+            "CARGO_MANIFEST_DIR" => Some(dir.to_str().unwrap().to_owned()),
+            // The environment variables take precedence
+            "SVGBOBDOC_OPTIONS" => Some("class=b".to_owned()),
+            "SVGBOBDOC_RENDER_TIMEOUT_MS" => Some("7".to_owned()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(manifest, Some(dir.join("Cargo.toml")));
+        assert_eq!(options.mode, Some(OutputMode::Img));
+        assert_eq!(options.class.as_deref(), Some("b"));
+        assert_eq!(options.timeout, Some(7));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unsupported() {
+        for manifest in [
+            "[package.metadata]\nsvgbobdoc = { mode = \"img\" }",
+            "[package.metadata.svgbobdoc.mode]",
+            "[package.metadata.svgbobdoc]\nclass = [\"a\"]",
+            "[package.metadata.svgbobdoc]\nclass = \"\"\"a\"\"\"",
+            "[package.metadata.svgbobdoc]\nclass = \"a\" b",
+            "[package.metadata.svgbobdoc]\nclass = a",
+        ] {
+            assert!(metadata_options(manifest).is_err(), "{}", manifest);
+        }
+    }
+}
@@ -0,0 +1,761 @@
+//! Options controlling the rendering and emission of diagrams
+use std::{path::PathBuf, result::Result};
+
+use crate::color::parse_color;
+
+/// Options controlling the rendering and emission of diagrams. Each field is
+/// `None` if unspecified.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    pub mode: Option<OutputMode>,
+    pub class: Option<String>,
+    /// The font family (CSS `font-family`) used for texts
+    pub font: Option<String>,
+    /// Center the image horizontally
+    pub center: Option<bool>,
+    /// Wrap the image in a link to itself
+    pub link: Option<bool>,
+    /// Keep the image's natural size and scroll it horizontally
+    pub scroll: Option<bool>,
+    /// The displayed width of the image (a CSS length)
+    pub width: Option<String>,
+    /// The displayed height of the image (a CSS length)
+    pub height: Option<String>,
+    /// The caption text, which causes the image to be wrapped in `<figure>`
+    pub caption: Option<String>,
+    /// Number the captioned diagrams in the crate for `figure_list!`
+    pub number_figures: Option<bool>,
+    /// The alternative text of the image
+    pub alt: Option<String>,
+    /// Add `role="img"` and, unless `alt` is specified, `aria-label` to `<img>`
+    /// elements
+    pub aria: Option<bool>,
+    /// The text of the SVG image's `<title>` element
+    pub title: Option<String>,
+    /// Include the diagram's source in the SVG image's `<desc>` element
+    pub desc: Option<bool>,
+    pub storage: Option<Storage>,
+    /// The encoding of `data:` URIs
+    pub encoding: Option<Encoding>,
+    /// The size of an embedded image in kilobytes above which a warning is
+    /// reported, or `0` for no limit
+    pub size_limit: Option<usize>,
+    /// Report an image exceeding `size_limit` as an error
+    pub size_limit_error: Option<bool>,
+    /// Exempt the image from `size_limit`
+    pub allow_large: Option<bool>,
+    /// The file containing the diagram's source, relative to
+    /// `CARGO_MANIFEST_DIR`
+    pub file: Option<String>,
+    /// The directory where `storage=file` writes SVG files, relative to
+    /// `CARGO_MANIFEST_DIR`
+    pub file_dir: Option<String>,
+    /// The URL prefix used by `storage=file` to refer to SVG files
+    pub file_url: Option<String>,
+    /// Whether the generated IDs are unique across the macro invocations in
+    /// the crate
+    pub unique_ids: Option<IdScope>,
+    /// The language of the code block emitted if rendering fails
+    pub fallback_lang: Option<String>,
+    /// Leave code blocks unrendered, turning them into `text` code blocks
+    pub passthrough: Option<bool>,
+    /// Render a variant for dark mode and emit `<picture>` to switch between
+    /// them
+    pub dark_variant: Option<bool>,
+    /// The font size in pixels, which also determines the cell size
+    pub font_size: Option<usize>,
+    /// The factor by which the cell size and the font size are multiplied
+    pub scale: Option<f32>,
+    /// The color of lines and texts
+    pub stroke: Option<String>,
+    /// The stroke width in pixels
+    pub stroke_width: Option<f32>,
+    /// The background color, or `none` to leave out the background
+    pub background: Option<String>,
+    /// Add `textLength` attributes to `<text>` elements
+    pub text_length: Option<bool>,
+    /// The number of decimal places of coordinates and sizes
+    pub precision: Option<usize>,
+    /// Remove the redundant whitespace and attributes from SVG images
+    pub minify: Option<bool>,
+    /// How lines and shapes are anti-aliased
+    pub shape_rendering: Option<ShapeRendering>,
+    /// Render the lines after the `#legend` line as a legend placed to the
+    /// right of the diagram
+    pub legend: Option<bool>,
+    /// The space between the diagram and the legend in pixels
+    pub legend_gap: Option<f32>,
+    pub legend_align: Option<LegendAlign>,
+    /// The time limit of rendering a diagram in milliseconds, or `0` for no
+    /// limit
+    pub timeout: Option<u64>,
+    /// Emit the diagram's source in a collapsed `<details>` element after the
+    /// image
+    pub show_source: Option<bool>,
+    /// Keep the original code block alongside the image
+    pub keep_source: Option<KeepSource>,
+    /// Emit the diagram's source in an HTML comment after the image
+    pub source_comment: Option<bool>,
+    /// Allow the diagram's label to be referenced outside the macro
+    /// invocation
+    pub export: Option<bool>,
+    /// Emit an anchor before the definition of the diagram's label
+    pub anchor: Option<bool>,
+    /// Where the definition of the diagram's label is placed
+    pub label_defs: Option<LabelDefs>,
+    /// Resolve references to the labels exported by the macro invocations
+    /// expanded earlier in the crate
+    pub shared_labels: Option<bool>,
+    /// Report a diagram label defined by more than one macro invocation in
+    /// the crate
+    pub unique_labels: Option<bool>,
+    /// Check the contrast between the diagram's colors and its background
+    pub check_contrast: Option<CheckLevel>,
+    /// Report undefined and unreferenced diagram labels. Only effective as a
+    /// crate-wide default.
+    pub check_labels: Option<CheckLevel>,
+}
+
+/// Specifies how a diagram is emitted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// Markdown image syntax (`![...](data:...)`)
+    Markdown,
+    /// An HTML `<img>` element with `loading="lazy"` and `decoding="async"`
+    Img,
+    /// Markdown image syntax referring to a link reference definition
+    /// (`![...][svgbob-1]`) at the end of the document
+    Reference,
+    /// An inline `<svg>` element
+    Svg,
+}
+
+/// Specifies where a diagram's SVG image is stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Storage {
+    /// A `data:` URI
+    DataUri,
+    /// A standalone SVG file referenced by a URL (requires the `files`
+    /// feature)
+    File,
+}
+
+/// Specifies where the IDs generated for diagrams are unique.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdScope {
+    /// In each macro invocation
+    PerInvocation,
+    /// In the crate, so that doc comments can be concatenated in one page
+    Global,
+}
+
+/// Specifies how an SVG image is encoded in a `data:` URI.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// Base64
+    Base64,
+    /// Percent-encoding, which only escapes the characters that must be
+    /// escaped
+    Percent,
+}
+
+/// Specifies whether and where the original code block is kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeepSource {
+    Off,
+    /// Before the image
+    Before,
+    /// After the image
+    After,
+}
+
+/// Specifies how the problems found by a check (e.g., low-contrast colors)
+/// are reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckLevel {
+    Off,
+    /// As warnings
+    Warn,
+    /// As errors
+    Error,
+}
+
+/// Specifies how lines and shapes are anti-aliased (`shape-rendering`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShapeRendering {
+    /// Left to the browser
+    Auto,
+    /// `crispEdges`, which turns off anti-aliasing
+    Crisp,
+    /// `geometricPrecision`
+    Smooth,
+}
+
+/// Specifies where the link reference definitions of diagram labels are
+/// placed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelDefs {
+    /// Where the code blocks are
+    InPlace,
+    /// At the end of the document
+    End,
+}
+
+/// Specifies how a diagram and its legend are aligned vertically.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LegendAlign {
+    Top,
+    Center,
+    Bottom,
+}
+
+/// A part of a comma-separated option list.
+#[derive(Debug)]
+pub enum OptionPart {
+    /// `[label]` or `[label "text"]`
+    Label(String, Option<String>),
+    /// `key` or `key=value`
+    Option(String, Option<String>),
+}
+
+/// Split a comma-separated option list (e.g., `[label],key=value,key="a, b"`)
+/// into parts.
+///
+/// A value can be enclosed in double quotes, in which case it may include
+/// commas, and a backslash escapes the next character. So can the text
+/// following a label (`[label "text"]`).
+pub fn parse_option_list(s: &str) -> Result<Vec<OptionPart>, String> {
+    let mut parts = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    macro_rules! skip_whitespace {
+        () => {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        };
+    }
+
+    // Read the rest of a quoted string after the opening quote
+    macro_rules! read_quoted {
+        ($($unclosed:tt)*) => {{
+            let mut value = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') if chars.peek().is_some() => value.push(chars.next().unwrap()),
+                    Some(c) => value.push(c),
+                    None => return Err(format!($($unclosed)*)),
+                }
+            }
+            value
+        }};
+    }
+
+    loop {
+        skip_whitespace!();
+
+        match chars.peek() {
+            None => break,
+            Some(',') => {
+                // Empty part
+                chars.next();
+                continue;
+            }
+            Some('[') => {
+                chars.next();
+                let mut label = String::new();
+                let mut text = None;
+                loop {
+                    match chars.next() {
+                        Some(']') => break,
+                        Some('"') if text.is_none() => {
+                            text = Some(read_quoted!(
+                                "unclosed quotes in link label `{}`",
+                                label.trim_end()
+                            ));
+                            skip_whitespace!();
+                            if chars.peek() != Some(&']') {
+                                return Err(format!(
+                                    "expected `]` after the text of link label `{}`",
+                                    label.trim_end()
+                                ));
+                            }
+                        }
+                        Some(c) => label.push(c),
+                        None => return Err("unclosed link label".to_owned()),
+                    }
+                }
+                if text.is_some() {
+                    label.truncate(label.trim_end().len());
+                }
+                parts.push(OptionPart::Label(label, text));
+            }
+            Some(_) => {
+                let mut key = String::new();
+                while let Some(c) = chars.next_if(|&c| c != ',' && c != '=') {
+                    key.push(c);
+                }
+                key.truncate(key.trim_end().len());
+
+                let value = if chars.next_if_eq(&'=').is_some() {
+                    skip_whitespace!();
+                    let mut value = String::new();
+                    if chars.next_if_eq(&'"').is_some() {
+                        value = read_quoted!("unclosed quotes in the value of option `{}`", key);
+                    } else {
+                        while let Some(c) = chars.next_if(|&c| c != ',') {
+                            value.push(c);
+                        }
+                        value.truncate(value.trim_end().len());
+                    }
+                    Some(value)
+                } else {
+                    None
+                };
+
+                parts.push(OptionPart::Option(key, value));
+            }
+        }
+
+        skip_whitespace!();
+
+        match chars.next() {
+            None => break,
+            Some(',') => {}
+            Some(c) => return Err(format!("unexpected character `{}` in options", c)),
+        }
+    }
+
+    Ok(parts)
+}
+
+/// Check if a string is a positive CSS length with a unit or a percentage
+/// (e.g., `20em`, `300px`, or `50%`).
+fn is_css_length(s: &str) -> bool {
+    const UNITS: &[&str] = &[
+        "%", "px", "em", "rem", "ex", "ch", "vw", "vh", "vmin", "vmax", "pt", "pc", "cm", "mm",
+        "in", "q",
+    ];
+    let Some(number_len) = s.find(|c: char| !(c.is_ascii_digit() || c == '.')) else {
+        return false;
+    };
+    let (number, unit) = s.split_at(number_len);
+    // CSS allows `.5` but not `5.`
+    let is_number = !number.is_empty()
+        && !number.ends_with('.')
+        && number.parse::<f64>().map_or(false, |x| x > 0.0);
+    is_number && UNITS.contains(&&*unit.to_ascii_lowercase())
+}
+
+impl Options {
+    /// The name of the environment variable specifying the crate-wide default
+    /// options.
+    const ENV_VAR: &'static str = "SVGBOBDOC_OPTIONS";
+
+    /// The name of the environment variable specifying the default value of
+    /// `timeout`.
+    const TIMEOUT_ENV_VAR: &'static str = "SVGBOBDOC_RENDER_TIMEOUT_MS";
+
+    /// Read the default options from the environment variables
+    /// `SVGBOBDOC_OPTIONS` and `SVGBOBDOC_RENDER_TIMEOUT_MS` and, for the
+    /// options unspecified by them, the `[package.metadata.svgbobdoc]` table
+    /// of the package being compiled (`$CARGO_MANIFEST_DIR/Cargo.toml`).
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| std::env::var(name).ok()).map(|(this, _)| this)
+    }
+
+    /// Like [`Self::from_env`] but gets the environment variables by `var`.
+    /// Also returns the path of `Cargo.toml` if it has the options, which
+    /// the compiler doesn't know to track.
+    pub fn from_vars(
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<(Self, Option<PathBuf>), String> {
+        let mut this = Self::default();
+        if let Some(value) = var(Self::ENV_VAR) {
+            this.apply_list(&value)
+                .map_err(|e| format!("`{}`: {}", Self::ENV_VAR, e))?;
+        }
+        if let Some(value) = var(Self::TIMEOUT_ENV_VAR) {
+            if this.timeout.is_none() {
+                this.apply("timeout", Some(value))
+                    .map_err(|e| format!("`{}`: {}", Self::TIMEOUT_ENV_VAR, e))?;
+            }
+        }
+
+        let Some(dir) = var("CARGO_MANIFEST_DIR") else {
+            return Ok((this, None));
+        };
+        let path = PathBuf::from(dir).join("Cargo.toml");
+        let Ok(manifest) = std::fs::read_to_string(&path) else {
+            return Ok((this, None));
+        };
+        let in_manifest = |e: String| format!("`{}`: {}", path.display(), e);
+        let Some(options) = crate::manifest::metadata_options(&manifest).map_err(in_manifest)?
+        else {
+            return Ok((this, None));
+        };
+        let mut defaults = Self::default();
+        for (key, value) in options {
+            defaults.apply(&key, Some(value)).map_err(in_manifest)?;
+        }
+        Ok((this.or(&defaults), Some(path)))
+    }
+
+    /// Apply a comma-separated option list, which must not include link
+    /// labels.
+    pub fn apply_list(&mut self, s: &str) -> Result<(), String> {
+        for part in parse_option_list(s)? {
+            match part {
+                OptionPart::Label(..) => return Err("link labels are not allowed here".to_owned()),
+                OptionPart::Option(key, value) => self.apply(&key, value)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply an option (`key=value` or `key`).
+    pub fn apply(&mut self, key: &str, value: Option<String>) -> Result<(), String> {
+        let raw_value = value.as_deref();
+        let flag = || match raw_value {
+            None | Some("true") => Ok(true),
+            Some("false") => Ok(false),
+            Some(other) => Err(format!(
+                "option `{}` must be `true` or `false`, not `{}`",
+                key, other
+            )),
+        };
+        let value = || {
+            value
+                .clone()
+                .ok_or_else(|| format!("option `{}` requires a value", key))
+        };
+        let color = || {
+            let value = value()?;
+            parse_color(&value).map_err(|e| format!("option `{}`: {}", key, e))?;
+            Ok::<_, String>(value)
+        };
+        let length = || {
+            let value = value()?;
+            if !is_css_length(&value) {
+                return Err(format!(
+                    "option `{}` must be a positive CSS length (e.g., `20em` or `50%`), not `{}`",
+                    key, value
+                ));
+            }
+            Ok(value)
+        };
+
+        match key {
+            "mode" => {
+                self.mode = Some(match &*value()? {
+                    "markdown" => OutputMode::Markdown,
+                    "img" => OutputMode::Img,
+                    "reference" => OutputMode::Reference,
+                    "svg" => OutputMode::Svg,
+                    other => return Err(format!("unknown output mode: `{}`", other)),
+                });
+            }
+            "class" => self.class = Some(value()?),
+            "font" => self.font = Some(value()?),
+            "center" => self.center = Some(flag()?),
+            "link" => self.link = Some(flag()?),
+            "scroll" => self.scroll = Some(flag()?),
+            "width" => self.width = Some(length()?),
+            "height" => self.height = Some(length()?),
+            "caption" => self.caption = Some(value()?),
+            "number-figures" => self.number_figures = Some(flag()?),
+            "alt" => self.alt = Some(value()?),
+            "aria" => self.aria = Some(flag()?),
+            "title" => self.title = Some(value()?),
+            "desc" => self.desc = Some(flag()?),
+            "storage" => {
+                self.storage = Some(match &*value()? {
+                    "data-uri" => Storage::DataUri,
+                    "file" => Storage::File,
+                    other => return Err(format!("unknown storage: `{}`", other)),
+                });
+            }
+            "encoding" => {
+                self.encoding = Some(match &*value()? {
+                    "base64" => Encoding::Base64,
+                    "percent" => Encoding::Percent,
+                    other => return Err(format!("unknown encoding: `{}`", other)),
+                });
+            }
+            "size-limit" => {
+                let value = value()?;
+                self.size_limit = Some(value.parse().map_err(|_| {
+                    format!(
+                        "option `{}` must be a number of kilobytes, not `{}`",
+                        key, value
+                    )
+                })?);
+            }
+            "size-limit-error" => self.size_limit_error = Some(flag()?),
+            "allow-large" => self.allow_large = Some(flag()?),
+            "file" => self.file = Some(value()?),
+            "file-dir" => self.file_dir = Some(value()?),
+            "file-url" => self.file_url = Some(value()?),
+            "unique-ids" => {
+                self.unique_ids = Some(match &*value()? {
+                    "per-invocation" => IdScope::PerInvocation,
+                    "global" => IdScope::Global,
+                    other => {
+                        return Err(format!(
+                            "option `{}` must be `per-invocation` or `global`, not `{}`",
+                            key, other
+                        ))
+                    }
+                });
+            }
+            "fallback-lang" => {
+                let value = value()?;
+                if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '`') {
+                    return Err(format!(
+                        "invalid language for option `{}`: `{}`",
+                        key, value
+                    ));
+                }
+                self.fallback_lang = Some(value);
+            }
+            "passthrough" => self.passthrough = Some(flag()?),
+            "dark-variant" => self.dark_variant = Some(flag()?),
+            "font-size" => {
+                let value = value()?;
+                self.font_size = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|&size: &usize| size > 0)
+                        .ok_or_else(|| {
+                            format!(
+                                "option `{}` must be a positive number of pixels, not `{}`",
+                                key, value
+                            )
+                        })?,
+                );
+            }
+            "scale" => {
+                let value = value()?;
+                self.scale = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|scale: &f32| scale.is_finite() && *scale > 0.0)
+                        .ok_or_else(|| {
+                            format!(
+                                "option `{}` must be a positive number, not `{}`",
+                                key, value
+                            )
+                        })?,
+                );
+            }
+            "stroke" => self.stroke = Some(color()?),
+            "stroke-width" => {
+                let value = value()?;
+                self.stroke_width = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|width: &f32| width.is_finite() && *width > 0.0)
+                        .ok_or_else(|| {
+                            format!(
+                                "option `{}` must be a positive number of pixels, not `{}`",
+                                key, value
+                            )
+                        })?,
+                );
+            }
+            "background" => {
+                self.background = Some(match &*value()? {
+                    "none" => "none".to_owned(),
+                    _ => color()?,
+                })
+            }
+            "text-length" => self.text_length = Some(flag()?),
+            "minify" => self.minify = Some(flag()?),
+            "crisp" => {
+                self.shape_rendering = Some(if flag()? {
+                    ShapeRendering::Crisp
+                } else {
+                    ShapeRendering::Auto
+                });
+            }
+            "rendering" => {
+                self.shape_rendering = Some(match &*value()? {
+                    "auto" => ShapeRendering::Auto,
+                    "crisp" => ShapeRendering::Crisp,
+                    "smooth" => ShapeRendering::Smooth,
+                    other => {
+                        return Err(format!(
+                            "option `{}` must be `auto`, `crisp`, or `smooth`, not `{}`",
+                            key, other
+                        ))
+                    }
+                });
+            }
+            "legend" => self.legend = Some(flag()?),
+            "legend-gap" => {
+                let value = value()?;
+                self.legend_gap = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|gap: &f32| gap.is_finite() && *gap >= 0.0)
+                        .ok_or_else(|| {
+                            format!(
+                                "option `{}` must be a non-negative number of pixels, not `{}`",
+                                key, value
+                            )
+                        })?,
+                );
+            }
+            "legend-align" => {
+                self.legend_align = Some(match &*value()? {
+                    "top" => LegendAlign::Top,
+                    "center" => LegendAlign::Center,
+                    "bottom" => LegendAlign::Bottom,
+                    other => {
+                        return Err(format!(
+                            "option `{}` must be `top`, `center`, or `bottom`, not `{}`",
+                            key, other
+                        ))
+                    }
+                });
+            }
+            "precision" => {
+                let value = value()?;
+                self.precision = Some(value.parse().map_err(|_| {
+                    format!(
+                        "option `{}` must be a number of decimal places, not `{}`",
+                        key, value
+                    )
+                })?);
+            }
+            "timeout" => {
+                let value = value()?;
+                self.timeout = Some(value.parse().map_err(|_| {
+                    format!(
+                        "option `{}` must be a number of milliseconds, not `{}`",
+                        key, value
+                    )
+                })?);
+            }
+            "show-source" => self.show_source = Some(flag()?),
+            "source-comment" => self.source_comment = Some(flag()?),
+            "export" => self.export = Some(flag()?),
+            "anchor" => self.anchor = Some(flag()?),
+            "label-defs" => {
+                self.label_defs = Some(match &*value()? {
+                    "in-place" => LabelDefs::InPlace,
+                    "end" => LabelDefs::End,
+                    other => {
+                        return Err(format!(
+                            "option `{}` must be `in-place` or `end`, not `{}`",
+                            key, other
+                        ))
+                    }
+                });
+            }
+            "shared-labels" => self.shared_labels = Some(flag()?),
+            "unique-labels" => self.unique_labels = Some(flag()?),
+            "keep-source" => {
+                self.keep_source = Some(match raw_value {
+                    None | Some("after") | Some("true") => KeepSource::After,
+                    Some("before") => KeepSource::Before,
+                    Some("false") => KeepSource::Off,
+                    Some(other) => {
+                        return Err(format!(
+                            "option `{}` must be `before`, `after`, or `false`, not `{}`",
+                            key, other
+                        ))
+                    }
+                });
+            }
+            "check-contrast" => {
+                self.check_contrast = Some(match raw_value {
+                    None | Some("warn") | Some("true") => CheckLevel::Warn,
+                    Some("error") => CheckLevel::Error,
+                    Some("false") => CheckLevel::Off,
+                    Some(other) => {
+                        return Err(format!(
+                            "option `{}` must be `warn`, `error`, or `false`, not `{}`",
+                            key, other
+                        ))
+                    }
+                });
+            }
+            "check-labels" => {
+                self.check_labels = Some(match raw_value {
+                    None | Some("error") | Some("true") => CheckLevel::Error,
+                    Some("warn") => CheckLevel::Warn,
+                    Some("false") => CheckLevel::Off,
+                    Some(other) => {
+                        return Err(format!(
+                            "option `{}` must be `error`, `warn`, or `false`, not `{}`",
+                            key, other
+                        ))
+                    }
+                });
+            }
+            _ => return Err(format!("unknown option: `{}`", key)),
+        }
+
+        Ok(())
+    }
+
+    /// Fill the unspecified fields with the values from `fallback`.
+    pub fn or(mut self, fallback: &Self) -> Self {
+        self.mode = self.mode.or(fallback.mode);
+        self.class = self.class.or_else(|| fallback.class.clone());
+        self.font = self.font.or_else(|| fallback.font.clone());
+        self.center = self.center.or(fallback.center);
+        self.link = self.link.or(fallback.link);
+        self.scroll = self.scroll.or(fallback.scroll);
+        self.width = self.width.or_else(|| fallback.width.clone());
+        self.height = self.height.or_else(|| fallback.height.clone());
+        self.caption = self.caption.or_else(|| fallback.caption.clone());
+        self.number_figures = self.number_figures.or(fallback.number_figures);
+        self.alt = self.alt.or_else(|| fallback.alt.clone());
+        self.aria = self.aria.or(fallback.aria);
+        self.title = self.title.or_else(|| fallback.title.clone());
+        self.desc = self.desc.or(fallback.desc);
+        self.storage = self.storage.or(fallback.storage);
+        self.encoding = self.encoding.or(fallback.encoding);
+        self.size_limit = self.size_limit.or(fallback.size_limit);
+        self.size_limit_error = self.size_limit_error.or(fallback.size_limit_error);
+        self.allow_large = self.allow_large.or(fallback.allow_large);
+        self.file = self.file.or_else(|| fallback.file.clone());
+        self.file_dir = self.file_dir.or_else(|| fallback.file_dir.clone());
+        self.file_url = self.file_url.or_else(|| fallback.file_url.clone());
+        self.unique_ids = self.unique_ids.or(fallback.unique_ids);
+        self.fallback_lang = self
+            .fallback_lang
+            .or_else(|| fallback.fallback_lang.clone());
+        self.passthrough = self.passthrough.or(fallback.passthrough);
+        self.dark_variant = self.dark_variant.or(fallback.dark_variant);
+        self.font_size = self.font_size.or(fallback.font_size);
+        self.scale = self.scale.or(fallback.scale);
+        self.stroke = self.stroke.or_else(|| fallback.stroke.clone());
+        self.stroke_width = self.stroke_width.or(fallback.stroke_width);
+        self.background = self.background.or_else(|| fallback.background.clone());
+        self.text_length = self.text_length.or(fallback.text_length);
+        self.precision = self.precision.or(fallback.precision);
+        self.minify = self.minify.or(fallback.minify);
+        self.shape_rendering = self.shape_rendering.or(fallback.shape_rendering);
+        self.legend = self.legend.or(fallback.legend);
+        self.legend_gap = self.legend_gap.or(fallback.legend_gap);
+        self.legend_align = self.legend_align.or(fallback.legend_align);
+        self.timeout = self.timeout.or(fallback.timeout);
+        self.show_source = self.show_source.or(fallback.show_source);
+        self.keep_source = self.keep_source.or(fallback.keep_source);
+        self.source_comment = self.source_comment.or(fallback.source_comment);
+        self.export = self.export.or(fallback.export);
+        self.anchor = self.anchor.or(fallback.anchor);
+        self.label_defs = self.label_defs.or(fallback.label_defs);
+        self.shared_labels = self.shared_labels.or(fallback.shared_labels);
+        self.unique_labels = self.unique_labels.or(fallback.unique_labels);
+        self.check_contrast = self.check_contrast.or(fallback.check_contrast);
+        self.check_labels = self.check_labels.or(fallback.check_labels);
+        self
+    }
+}
@@ -0,0 +1,67 @@
+//! Rendering diagrams in parallel, enabled by the `parallel` Cargo feature
+use std::{
+    cell::Cell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+thread_local! {
+    /// Whether this thread is one of those spawned by [`map`].
+    static IN_MAP: Cell<bool> = Cell::new(false);
+}
+
+/// Call `f` for each item on as many threads as there are CPUs. The outputs
+/// are returned in the order of `items`. If called by `f` (e.g., to render
+/// the diagrams of each file transformed in parallel), the items are processed
+/// on the calling thread because the other CPUs are busy anyway.
+pub fn map<T: Send, U: Send>(items: Vec<T>, f: impl Fn(T) -> U + Sync) -> Vec<U> {
+    let num_threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    if num_threads <= 1 || IN_MAP.with(Cell::get) {
+        return items.into_iter().map(f).collect();
+    }
+
+    let len = items.len();
+    let items: Vec<Mutex<Option<T>>> = items.into_iter().map(|x| Mutex::new(Some(x))).collect();
+    let outputs: Vec<Mutex<Option<U>>> = (0..len).map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| {
+                IN_MAP.with(|in_map| in_map.set(true));
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    let item = item.lock().unwrap().take().unwrap();
+                    *outputs[i].lock().unwrap() = Some(f(item));
+                }
+            });
+        }
+    });
+
+    outputs
+        .into_iter()
+        .map(|output| output.into_inner().unwrap().unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preserves_order() {
+        let outputs = map((0..100).collect(), |i: u32| {
+            thread::sleep(std::time::Duration::from_millis(u64::from(i % 7)));
+            i * 2
+        });
+        assert_eq!(outputs, (0..100).map(|i| i * 2).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,1670 @@
+//! Conversion from ASCII diagrams to SVG images
+use crate::{
+    color::{blend, contrast_ratio, parse_color, Rgba, MIN_CONTRAST_RATIO},
+    options::{LegendAlign, Options, ShapeRendering},
+};
+
+/// The default font used for diagrams.
+///
+/// The selection made here attempts to approximate the monospace font used by
+/// rustdoc's stylesheet. Source Code Pro isn't necessarily available because
+/// images can't access the containing page's `@font-face`.
+const DIAGRAM_FONT: &str =
+    "'Source Code Pro','Andale Mono','Segoe UI Mono','Dejavu Sans Mono','Consolas',monospace";
+
+/// The page background assumed behind a diagram with a transparent background.
+pub const LIGHT_PAGE_BACKGROUND: &str = "white";
+
+/// The page background assumed behind a dark variant, which matches that of
+/// rustdoc's `dark` theme.
+pub const DARK_PAGE_BACKGROUND: &str = "#353535";
+
+/// The parameters for rendering diagrams.
+///
+/// This type is decoupled from `svgbob::Settings` so that the options
+/// provided by this crate don't change their meaning when `svgbob` is
+/// updated. [`RenderSettings::to_svgbob`] is the only place that should touch
+/// `svgbob::Settings`.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// The font family (CSS `font-family`) used for texts
+    pub font_family: String,
+    /// The font size in pixels
+    pub font_size: usize,
+    /// The size of a character cell's width in pixels. A cell's height is
+    /// twice as large as this.
+    pub scale: f32,
+    /// The stroke width in pixels
+    pub stroke_width: f32,
+    /// The color of lines and texts
+    pub stroke_color: String,
+    /// The color of filled shapes
+    pub fill_color: String,
+    /// The background color
+    pub background: String,
+    /// Fill the background with [`Self::background`]. If `false`, the image
+    /// has no background rectangle at all.
+    pub backdrop: bool,
+    /// Add `textLength` attributes to `<text>` elements so that texts are
+    /// aligned to the cells regardless of the font's metrics
+    pub text_length: bool,
+    /// The number of decimal places to which coordinates and sizes are
+    /// rounded
+    pub precision: usize,
+    /// Remove the redundant whitespace and attributes from SVG images
+    pub minify: bool,
+    /// The `shape-rendering` of lines and shapes
+    pub shape_rendering: ShapeRendering,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            font_family: DIAGRAM_FONT.to_owned(),
+            font_size: 13,
+            scale: 8.0,
+            stroke_width: 1.0,
+            stroke_color: "black".to_owned(),
+            fill_color: "black".to_owned(),
+            background: "white".to_owned(),
+            backdrop: true,
+            text_length: true,
+            precision: 2,
+            minify: false,
+            shape_rendering: ShapeRendering::Auto,
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Construct `RenderSettings` by applying the specified options to the
+    /// default values.
+    pub fn from_options(options: &Options) -> Self {
+        let mut this = Self::default();
+        if let Some(font) = &options.font {
+            this.font_family = font.clone();
+        }
+        if let Some(font_size) = options.font_size {
+            // Keep the text fit in the cells
+            this.scale *= font_size as f32 / this.font_size as f32;
+            this.font_size = font_size;
+        }
+        if let Some(scale) = options.scale {
+            this.scale *= scale;
+            this.font_size = (this.font_size as f32 * scale).round().max(1.0) as usize;
+        }
+        if let Some(stroke) = &options.stroke {
+            this.stroke_color = stroke.clone();
+            this.fill_color = stroke.clone();
+        }
+        if let Some(stroke_width) = options.stroke_width {
+            this.stroke_width = stroke_width;
+        }
+        match options.background.as_deref() {
+            Some("none") => {
+                this.background = "transparent".to_owned();
+                this.backdrop = false;
+            }
+            Some(background) => this.background = background.to_owned(),
+            None => {}
+        }
+        if let Some(text_length) = options.text_length {
+            this.text_length = text_length;
+        }
+        if let Some(precision) = options.precision {
+            this.precision = precision;
+        }
+        if let Some(minify) = options.minify {
+            this.minify = minify;
+        }
+        if let Some(shape_rendering) = options.shape_rendering {
+            this.shape_rendering = shape_rendering;
+        }
+        this
+    }
+
+    /// Get the settings for rendering a variant suitable for a dark
+    /// background.
+    pub fn to_dark(&self) -> Self {
+        Self {
+            stroke_color: "#ddd".to_owned(),
+            fill_color: "#ddd".to_owned(),
+            ..self.clone()
+        }
+    }
+
+    /// Find the colors that don't contrast enough with the background, which is
+    /// composited over `page` (an opaque color).
+    pub fn low_contrast_colors(&self, page: &str) -> Vec<LowContrast> {
+        let resolve = |color: &str| parse_color(color).ok();
+        let (Some(page), Some(background)) = (resolve(page), resolve(&self.background)) else {
+            return Vec::new();
+        };
+        let background = blend(background, page);
+
+        let mut found = Vec::new();
+        for (name, color) in [("stroke", &self.stroke_color), ("fill", &self.fill_color)] {
+            if name == "fill" && *color == self.stroke_color {
+                continue;
+            }
+            let Some(resolved) = resolve(color) else {
+                continue;
+            };
+            let ratio = contrast_ratio(blend(resolved, background), background);
+            if ratio < MIN_CONTRAST_RATIO {
+                found.push(LowContrast {
+                    name,
+                    color: color.clone(),
+                    resolved,
+                    background: self.background.clone(),
+                    resolved_background: background,
+                    ratio,
+                });
+            }
+        }
+        found
+    }
+
+    /// Convert `self` to `svgbob::Settings`.
+    ///
+    /// When updating `svgbob`, review this method to make sure that every
+    /// field is still mapped to a setting with the same meaning.
+    #[cfg(feature = "enable")]
+    fn to_svgbob(&self) -> svgbob::Settings {
+        svgbob::Settings {
+            font_size: self.font_size,
+            font_family: self.font_family.clone(),
+            fill_color: self.fill_color.clone(),
+            background: self.background.clone(),
+            stroke_color: self.stroke_color.clone(),
+            stroke_width: self.stroke_width,
+            scale: self.scale,
+            include_backdrop: self.backdrop,
+            include_styles: true,
+            include_defs: true,
+        }
+    }
+}
+
+/// A color found by [`RenderSettings::low_contrast_colors`].
+#[derive(Debug)]
+pub struct LowContrast {
+    /// The name of the palette entry (e.g., `stroke`)
+    pub name: &'static str,
+    /// The color as specified
+    pub color: String,
+    pub resolved: Rgba,
+    /// The background color as specified
+    pub background: String,
+    /// The background color composited over the page
+    pub resolved_background: Rgba,
+    pub ratio: f64,
+}
+
+/// Render an ASCII diagram as an SVG image.
+///
+/// Returns an error message if the renderer panicked or produced nothing.
+pub fn to_svg(art: &str, settings: &RenderSettings) -> Result<String, String> {
+    #[cfg(test)]
+    let to_svg_inner =
+        |art: &str, settings| test_renderer(art).unwrap_or_else(|| to_svg_inner(art, settings));
+
+    let svg_code = std::panic::catch_unwind(|| to_svg_inner(art, settings)).map_err(|e| {
+        e.downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| e.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "the renderer panicked".to_owned())
+    })?;
+
+    if svg_code.is_empty() {
+        return Err("the renderer produced nothing".to_owned());
+    }
+
+    let mut svg_code = normalize_svg(&svg_code);
+    // Only applies to shapes, so texts are unaffected
+    match settings.shape_rendering {
+        ShapeRendering::Auto => {}
+        ShapeRendering::Crisp => add_root_attr(&mut svg_code, "shape-rendering", "crispEdges"),
+        ShapeRendering::Smooth => {
+            add_root_attr(&mut svg_code, "shape-rendering", "geometricPrecision")
+        }
+    }
+    if settings.minify {
+        svg_code = minify_svg(&svg_code);
+    }
+    Ok(round_numbers(&svg_code, settings.precision))
+}
+
+/// The attributes that can be removed if their values are `0`, which are
+/// their initial values.
+const ZERO_DEFAULT_ATTRS: &[&str] = &["cx", "cy", "x", "x1", "x2", "y", "y1", "y2"];
+
+/// Remove the whitespace between elements, the redundant whitespace in tags
+/// and stylesheets, and the attributes set to their initial values. The
+/// contents of `<text>` elements are left untouched.
+fn minify_svg(mut svg_code: &str) -> String {
+    let mut output = String::with_capacity(svg_code.len());
+    let mut element = "";
+    while !svg_code.is_empty() {
+        let text_len = svg_code.find('<').unwrap_or(svg_code.len());
+        let (text, rest) = svg_code.split_at(text_len);
+        match element {
+            "text" => output.push_str(text),
+            "style" => minify_css(text, &mut output),
+            _ if text.trim().is_empty() => {}
+            _ => output.push_str(text),
+        }
+        svg_code = rest;
+        if svg_code.is_empty() {
+            break;
+        }
+
+        // Find the end of the tag, skipping quoted attribute values
+        let mut quote = None;
+        let tag_len = svg_code
+            .bytes()
+            .position(|b| match (quote, b) {
+                (None, b'>') => true,
+                (None, b'"' | b'\'') => {
+                    quote = Some(b);
+                    false
+                }
+                (Some(q), _) if q == b => {
+                    quote = None;
+                    false
+                }
+                _ => false,
+            })
+            .map_or(svg_code.len(), |i| i + 1);
+        let (tag, rest) = svg_code.split_at(tag_len);
+        svg_code = rest;
+
+        // The contents of `<text>` and `<style>` need special treatment
+        let name_len = tag[1..]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(tag.len() - 1);
+        let name = &tag[1..1 + name_len];
+        element = match name {
+            "text" | "style" if !tag.ends_with("/>") => name,
+            _ => "",
+        };
+
+        minify_tag(tag, &mut output);
+    }
+    output
+}
+
+/// Collapse the whitespace in a tag and remove the attributes set to their
+/// initial values.
+fn minify_tag(tag: &str, out: &mut String) {
+    let Some(body) = tag
+        .strip_prefix('<')
+        .and_then(|tag| tag.strip_suffix('>'))
+        .filter(|body| !body.starts_with(['/', '!', '?']))
+    else {
+        out.push_str(tag);
+        return;
+    };
+    let (body, self_closing) = match body.strip_suffix('/') {
+        Some(body) => (body, true),
+        None => (body, false),
+    };
+
+    let mut rest = body.trim();
+    let name_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    out.push('<');
+    out.push_str(&rest[..name_len]);
+    rest = rest[name_len..].trim_start();
+
+    // `name="value"`, `name = 'value'`, etc.
+    while !rest.is_empty() {
+        let Some(eq) = rest.find('=') else {
+            // Not understood; keep the rest as is
+            out.push(' ');
+            out.push_str(rest);
+            break;
+        };
+        let name = rest[..eq].trim();
+        let value_part = rest[eq + 1..].trim_start();
+        let Some(quote) = value_part
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '"' | '\''))
+        else {
+            out.push(' ');
+            out.push_str(rest);
+            break;
+        };
+        let Some(value_len) = value_part[1..].find(quote) else {
+            out.push(' ');
+            out.push_str(rest);
+            break;
+        };
+        let value = &value_part[1..1 + value_len];
+        rest = value_part[value_len + 2..].trim_start();
+
+        if value == "0" && ZERO_DEFAULT_ATTRS.contains(&name) {
+            continue;
+        }
+        out.push(' ');
+        out.push_str(name);
+        out.push('=');
+        out.push(quote);
+        out.push_str(value);
+        out.push(quote);
+    }
+
+    if self_closing {
+        out.push('/');
+    }
+    out.push('>');
+}
+
+/// Collapse the whitespace in a stylesheet, removing it around punctuation.
+fn minify_css(css: &str, out: &mut String) {
+    let is_punct = |c: char| matches!(c, '{' | '}' | ';' | ':' | ',' | '>');
+    let mut pending_space = false;
+    for c in css.trim().chars() {
+        if c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space && !is_punct(c) && !out.ends_with(is_punct) {
+            out.push(' ');
+        }
+        pending_space = false;
+        out.push(c);
+    }
+}
+
+/// The attributes whose numbers are rounded by [`round_numbers`].
+const GEOMETRY_ATTRS: &[&str] = &[
+    "cx",
+    "cy",
+    "d",
+    "height",
+    "points",
+    "r",
+    "rx",
+    "ry",
+    "textLength",
+    "viewBox",
+    "width",
+    "x",
+    "x1",
+    "x2",
+    "y",
+    "y1",
+    "y2",
+];
+
+/// Round the numbers in the geometry attributes (e.g., `x` and `d`) of an SVG
+/// image to the specified number of decimal places. Texts are left untouched.
+fn round_numbers(mut svg_code: &str, precision: usize) -> String {
+    let mut output = String::with_capacity(svg_code.len());
+    while let Some(i) = svg_code.find('<') {
+        // Copy the text preceding the tag
+        output.push_str(&svg_code[..i]);
+        svg_code = &svg_code[i..];
+
+        // Process the attributes (`name="value"`) until the end of the tag
+        loop {
+            let Some(i) = svg_code.find(|c| matches!(c, '"' | '\'' | '>')) else {
+                output.push_str(svg_code);
+                return output;
+            };
+            let quote = svg_code.as_bytes()[i];
+            if quote == b'>' {
+                output.push_str(&svg_code[..=i]);
+                svg_code = &svg_code[i + 1..];
+                break;
+            }
+
+            let (head, rest) = svg_code.split_at(i + 1);
+            let Some(len) = rest.find(quote as char) else {
+                output.push_str(svg_code);
+                return output;
+            };
+            let name = head[..i].trim_end().strip_suffix('=').unwrap_or_default();
+            let name = name.trim_end();
+            let name = &name[name
+                .rfind(|c: char| c.is_whitespace())
+                .map_or(name.len(), |k| k + 1)..];
+            output.push_str(head);
+            if GEOMETRY_ATTRS.contains(&name) {
+                round_numbers_in_value(&rest[..len], precision, &mut output);
+            } else {
+                output.push_str(&rest[..len]);
+            }
+            output.push(quote as char);
+            svg_code = &rest[len + 1..];
+        }
+    }
+    output.push_str(svg_code);
+    output
+}
+
+/// Round the decimal numbers (e.g., `-12.307693`) in an attribute value.
+fn round_numbers_in_value(mut value: &str, precision: usize, out: &mut String) {
+    use std::fmt::Write;
+    let is_number_char = |c: char| c.is_ascii_digit() || c == '.';
+    while let Some(i) = value.find(is_number_char) {
+        out.push_str(&value[..i]);
+        value = &value[i..];
+        let len = value.find(|c| !is_number_char(c)).unwrap_or(value.len());
+        let (number, rest) = value.split_at(len);
+        value = rest;
+
+        match number.parse::<f64>() {
+            Ok(x) if number.contains('.') => {
+                let start = out.len();
+                write!(out, "{:.*}", precision, x).unwrap();
+                if out[start..].contains('.') {
+                    let trimmed = out.trim_end_matches('0').trim_end_matches('.').len();
+                    out.truncate(trimmed);
+                }
+                // Avoid `-0`
+                if &out[start..] == "0" && out[..start].ends_with('-') {
+                    out.remove(start - 1);
+                }
+            }
+            _ => out.push_str(number),
+        }
+    }
+    out.push_str(value);
+}
+
+/// Remove the parts of an SVG image that don't affect its appearance but may
+/// change between renderer versions: the XML prolog, comments, and
+/// `<metadata>` elements.
+fn normalize_svg(mut svg_code: &str) -> String {
+    let mut output = String::with_capacity(svg_code.len());
+    while let Some(i) = svg_code.find('<') {
+        output.push_str(&svg_code[..i]);
+        svg_code = &svg_code[i..];
+
+        let end = if svg_code.starts_with("<?") {
+            svg_code.find("?>").map(|i| i + 2)
+        } else if svg_code.starts_with("<!--") {
+            svg_code.find("-->").map(|i| i + 3)
+        } else if svg_code.starts_with("<metadata") {
+            svg_code.find("</metadata>").map(|i| i + 11)
+        } else {
+            // Copy the tag as is
+            let end = svg_code.find('>').map_or(svg_code.len(), |i| i + 1);
+            output.push_str(&svg_code[..end]);
+            svg_code = &svg_code[end..];
+            continue;
+        };
+        svg_code = &svg_code[end.unwrap_or(svg_code.len())..];
+        // Don't leave a blank line
+        if output.ends_with('\n') || output.is_empty() {
+            svg_code = svg_code.strip_prefix('\n').unwrap_or(svg_code);
+        }
+    }
+    output.push_str(svg_code);
+    output
+}
+
+/// Simulate a misbehaving renderer if the diagram's first line is one of the
+/// following:
+///
+///  - `#test:panic` panics.
+///  - `#test:empty` produces nothing.
+///  - `#test:sleep` takes a second.
+#[cfg(test)]
+fn test_renderer(art: &str) -> Option<String> {
+    match art.lines().next()? {
+        "#test:panic" => panic!("test panic"),
+        "#test:empty" => Some(String::new()),
+        "#test:sleep" => {
+            std::thread::sleep(std::time::Duration::from_secs(1));
+            None
+        }
+        _ => None,
+    }
+}
+
+#[cfg(feature = "enable")]
+fn to_svg_inner(art: &str, settings: &RenderSettings) -> String {
+    use svgbob::{
+        sauron::{html::attributes::AttributeValue, Attribute},
+        Node,
+    };
+
+    // Convert the diagram to SVG
+    let text_length = settings.text_length;
+    let settings = settings.to_svgbob();
+
+    let cb = svgbob::CellBuffer::from(art);
+
+    // svgbob only counts non-whitespace characters, but trailing whitespace
+    // and blank lines may be there for alignment. It also miscalculates the
+    // height. <https://github.com/ivanceras/svgbob/issues/77>
+    let (cols, rows) = art_size(art);
+    let width = cb
+        .bounds()
+        .map_or(0.0, |(_, bottom_right)| (bottom_right.x + 2) as f32)
+        .max(cols as f32)
+        * settings.scale;
+    let height = settings.scale * 2.0 * rows as f32;
+    let mut node: svgbob::Node<()> = cb.get_node_override_size(&settings, width, height);
+
+    traverse_pre_order_mut(&mut node, &mut |node| {
+        match node {
+            Node::Element(elem) if elem.tag == "text" && text_length => {
+                // Fix the horizontal layouting of texts by adding a `textLength` attribute
+                // to `<text>` elements.
+                let mut width = 0;
+                for child in elem.children() {
+                    if let Node::Leaf(leaf) = child {
+                        if leaf.is_text() {
+                            width += xml_text_width(leaf.as_text().unwrap());
+                        }
+                    }
+                }
+
+                let text_len = width as f32 * settings.scale;
+                elem.attrs.push(Attribute::new(
+                    None,
+                    "textLength",
+                    AttributeValue::from(text_len),
+                ));
+
+                return false;
+            }
+            _ => {}
+        }
+
+        true
+    });
+
+    // FIXME: Replace with let-else when stabilized
+    let elem = if let svgbob::Node::Element(elem) = &mut node {
+        elem
+    } else {
+        unreachable!()
+    };
+
+    // Patch the root element (`<svg>`)
+    // Let the image be scaled down proportionally
+    elem.attrs.push(Attribute::new(
+        None,
+        "viewBox",
+        AttributeValue::from(format!("0 0 {} {}", width, height)),
+    ));
+    elem.attrs.push(Attribute::new(
+        None,
+        "style",
+        AttributeValue::from("transform:translate(0.5px,0.5px)"),
+    ));
+
+    use svgbob::Render;
+    let mut svg_code = String::new();
+    node.render(&mut svg_code).unwrap();
+
+    svg_code
+}
+
+/// Get the number of columns and rows of a diagram as authored, including
+/// trailing whitespace and a trailing blank line.
+#[cfg(feature = "enable")]
+fn art_size(art: &str) -> (usize, usize) {
+    use unicode_width::UnicodeWidthStr;
+    let lines = art.split('\n');
+    // A wide character (e.g., CJK) occupies two columns
+    let cols = lines.clone().map(UnicodeWidthStr::width).max();
+    (cols.unwrap_or(0), lines.count())
+}
+
+/// Like [`unicode_width::UnicodeWidthStr`] but handles entity references
+/// (e.g., `&amp;` and `&#x3042;`). Assumes the input is in a valid form of an
+/// XML text node.
+#[cfg(feature = "enable")]
+fn xml_text_width(html_text: &str) -> usize {
+    use unicode_width::UnicodeWidthStr;
+    html_text
+        .split('&')
+        .enumerate()
+        .map(|(i, part)| {
+            if i == 0 {
+                return part.width();
+            }
+            match part
+                .find(';')
+                .and_then(|k| Some((entity_width(&part[..k])?, k)))
+            {
+                // "& # x 3 0 4 2 ; rest"
+                //  ^ ^^^^^^^^^^^ ^ ^^^^
+                //  │      │      │  └─ Counted as usual
+                //  │      │      └─ Removed with the entity name
+                //  │      └─ Decoded to measure the character's width
+                //  └─ This part is removed by `split`
+                Some((width, k)) => width + part[k + 1..].width(),
+                // Not an entity reference; count the `&` removed by `split`
+                None => 1 + part.width(),
+            }
+        })
+        .sum()
+}
+
+/// Get the width of the character represented by an entity reference with the
+/// specified name (the part between `&` and `;`, e.g., `amp`, `#38`, or
+/// `#x26`). Returns `None` if it's not a valid name.
+#[cfg(feature = "enable")]
+fn entity_width(s: &str) -> Option<usize> {
+    use unicode_width::UnicodeWidthChar;
+    let digits = s
+        .strip_prefix("#x")
+        .or_else(|| s.strip_prefix("#X"))
+        .map(|hex| (hex, 16))
+        .or_else(|| s.strip_prefix('#').map(|dec| (dec, 10)));
+    match digits {
+        Some((digits, radix)) => {
+            if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+                return None;
+            }
+            // An invalid code point is displayed as a replacement character
+            let c = u32::from_str_radix(digits, radix)
+                .ok()
+                .and_then(char::from_u32)
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            Some(c.width().unwrap_or(0))
+        }
+        // The predefined entities (`&amp;`, `&lt;`, `&gt;`, `&quot;`, and
+        // `&apos;`) all represent narrow characters. Assume the same for
+        // others.
+        None if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric()) => Some(1),
+        None => None,
+    }
+}
+
+#[cfg(feature = "enable")]
+fn traverse_pre_order_mut<MSG>(
+    node: &mut svgbob::Node<MSG>,
+    cb: &mut dyn FnMut(&mut svgbob::Node<MSG>) -> bool,
+) {
+    if cb(node) {
+        if let Some(children) = node.children_mut() {
+            for child in children.iter_mut() {
+                traverse_pre_order_mut(child, cb);
+            }
+        }
+    }
+}
+
+/// Render an ASCII diagram as an SVG image, leaving the texts unprocessed.
+#[cfg(not(feature = "enable"))]
+fn to_svg_inner(art: &str, settings: &RenderSettings) -> String {
+    use std::fmt::Write;
+    use unicode_width::UnicodeWidthStr;
+
+    let lines = art.split('\n');
+    let cols = lines
+        .clone()
+        .map(|line| line.width())
+        .fold(0, std::cmp::max);
+    let rows = lines.clone().count();
+
+    let col_width = settings.scale;
+    let row_height = settings.scale * 2.0;
+    let width = cols as f32 * col_width;
+    let height = rows as f32 * row_height;
+
+    let mut content = String::new();
+    for (i, line) in lines.enumerate() {
+        let mut x = 0.0;
+        let y = (i as f32 + 0.75) * row_height;
+        let mut last_i = 0;
+
+        // Divide `line` by whitespace so that each text span is positioned
+        // precisely at their endpoints
+        split_whitespace_indices(line, |span, start_i| {
+            x += line[last_i..start_i].width() as f32 * col_width;
+            last_i = start_i;
+
+            write!(content, r#"<text x="{}" y="{}""#, x, y).unwrap();
+            if settings.text_length {
+                write!(
+                    content,
+                    r#" textLength="{}""#,
+                    span.width() as f32 * col_width
+                )
+                .unwrap();
+            }
+            content.push('>');
+            escape_html(span, &mut content);
+            content.push_str("</text>");
+        });
+    }
+
+    fn split_whitespace_indices(mut s: &str, mut f: impl FnMut(&str, usize)) {
+        // Skip the the first whitespace characters
+        let s_trimmed = s.trim_start();
+        let mut offset = s.len() - s_trimmed.len();
+        s = s_trimmed;
+        while !s.is_empty() {
+            // Find the first whitespace character
+            let i = s
+                .char_indices()
+                .find(|(_, c)| c.is_whitespace())
+                .map(|(i, _)| i);
+
+            // Emit a span comprised of non-whitespace characters
+            {
+                let i = i.unwrap_or(s.len());
+                let part = &s[..i];
+                f(part, offset);
+                offset += i;
+                s = &s[i..];
+            }
+
+            // Skip the subsequent whitespace characters
+            let s_trimmed = s.trim_start();
+            offset += s.len() - s_trimmed.len();
+            s = s_trimmed;
+        }
+    }
+
+    let mut font = String::new();
+    escape_html(&settings.font_family, &mut font);
+    let mut stroke_color = String::new();
+    escape_html(&settings.stroke_color, &mut stroke_color);
+    let mut background = String::new();
+    escape_html(&settings.background, &mut background);
+    let backdrop = if settings.backdrop {
+        format!(
+            r#"<rect class="backdrop" x="0" y="0" width="{}" height="{}"></rect>"#,
+            width, height
+        )
+    } else {
+        String::new()
+    };
+
+    format!(
+        include_str!("minimal_template.svg"),
+        font = font,
+        font_size = settings.font_size,
+        stroke_color = stroke_color,
+        stroke_width = settings.stroke_width,
+        background = background,
+        width = width,
+        height = height,
+        backdrop = backdrop,
+        content = content,
+    )
+}
+
+/// The namespace of SVG elements.
+const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+
+/// The namespace of XLink attributes (e.g., `xlink:href`).
+const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+/// The HTML named character references that may appear in SVG images,
+/// replaced with numeric ones because XML only predefines `&amp;`, `&lt;`,
+/// `&gt;`, `&quot;`, and `&apos;`.
+const HTML_ENTITIES: &[(&str, u32)] = &[
+    ("nbsp", 0xa0),
+    ("copy", 0xa9),
+    ("reg", 0xae),
+    ("deg", 0xb0),
+    ("plusmn", 0xb1),
+    ("middot", 0xb7),
+    ("laquo", 0xab),
+    ("raquo", 0xbb),
+    ("times", 0xd7),
+    ("divide", 0xf7),
+    ("ndash", 0x2013),
+    ("mdash", 0x2014),
+    ("bull", 0x2022),
+    ("hellip", 0x2026),
+    ("trade", 0x2122),
+    ("larr", 0x2190),
+    ("uarr", 0x2191),
+    ("rarr", 0x2192),
+    ("darr", 0x2193),
+    ("harr", 0x2194),
+];
+
+/// Check if a character is allowed in an XML 1.0 document.
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | ' '..='\u{d7ff}' | '\u{e000}'..='\u{fffd}' | '\u{10000}'..)
+}
+
+/// Make an SVG image a well-formed standalone XML document, which browsers
+/// require to display it from a `data:` URI or a file: the root element is
+/// given the namespace declarations it lacks, character references that are
+/// only valid in HTML or refer to characters not allowed in XML are replaced,
+/// and so are such characters themselves.
+pub fn to_standalone_svg(svg_code: &str) -> String {
+    use std::fmt::Write;
+
+    let mut output = String::with_capacity(svg_code.len());
+    let mut rest = svg_code;
+    while let Some(i) = rest.find(|c: char| c == '&' || !is_xml_char(c)) {
+        output.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if !rest.starts_with('&') {
+            output.push(char::REPLACEMENT_CHARACTER);
+            rest = &rest[rest.chars().next().unwrap().len_utf8()..];
+            continue;
+        }
+
+        let name = rest[1..]
+            .find(';')
+            .map(|len| &rest[1..1 + len])
+            .filter(|name| {
+                !name.is_empty() && name.chars().all(|c| c == '#' || c.is_ascii_alphanumeric())
+            });
+        let Some(name) = name else {
+            // A bare `&`
+            output.push_str("&amp;");
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[name.len() + 2..];
+
+        let code_point = if let Some(digits) = name.strip_prefix('#') {
+            match digits.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => digits.parse().ok(),
+            }
+            .and_then(char::from_u32)
+            .filter(|&c| is_xml_char(c))
+            .map_or(0xfffd, u32::from)
+        } else if matches!(name, "amp" | "lt" | "gt" | "quot" | "apos") {
+            output.push('&');
+            output.push_str(name);
+            output.push(';');
+            continue;
+        } else if let Some(&(_, code_point)) = HTML_ENTITIES.iter().find(|(n, _)| *n == name) {
+            code_point
+        } else {
+            // Unknown; keep it as text
+            output.push_str("&amp;");
+            output.push_str(name);
+            output.push(';');
+            continue;
+        };
+        write!(output, "&#{};", code_point).unwrap();
+    }
+    output.push_str(rest);
+
+    if let Some(root_tag) = find_root_tag(&output) {
+        let tag = &output[root_tag.clone()];
+        let mut attrs = String::new();
+        if !tag.contains(" xmlns=") {
+            write!(attrs, " xmlns=\"{}\"", SVG_NAMESPACE).unwrap();
+        }
+        if output.contains("xlink:") && !tag.contains(" xmlns:xlink=") {
+            write!(attrs, " xmlns:xlink=\"{}\"", XLINK_NAMESPACE).unwrap();
+        }
+        output.insert_str(root_tag.start + "<svg".len(), &attrs);
+    }
+    output
+}
+
+/// Calculate the 64-bit FNV-1a hash of the specified bytes. Unlike
+/// `DefaultHasher`, the result is guaranteed to be stable.
+pub fn content_hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Find the start tag of the root `<svg>` element and return its range.
+fn find_root_tag(svg_code: &str) -> Option<std::ops::Range<usize>> {
+    let start = svg_code.find("<svg")?;
+
+    // Find the end of the start tag, skipping quoted attribute values
+    let mut quote = None;
+    let len = svg_code[start..].bytes().position(|b| match (quote, b) {
+        (None, b'>') => true,
+        (None, b'"' | b'\'') => {
+            quote = Some(b);
+            false
+        }
+        (Some(q), _) if q == b => {
+            quote = None;
+            false
+        }
+        _ => false,
+    })?;
+    Some(start..start + len + 1)
+}
+
+/// Insert `<title>` and `<desc>` elements as the first children of the root
+/// `<svg>` element. Does nothing if the root element can't be found.
+pub fn insert_svg_description(svg_code: &mut String, title: Option<&str>, desc: Option<&str>) {
+    let Some(root_tag) = find_root_tag(svg_code) else {
+        return;
+    };
+    let end = root_tag.end;
+    if svg_code[..end].ends_with("/>") {
+        // No room for children
+        return;
+    }
+
+    let mut elements = String::new();
+    if let Some(title) = title {
+        elements.push_str("<title>");
+        escape_html(title, &mut elements);
+        elements.push_str("</title>");
+    }
+    if let Some(desc) = desc {
+        elements.push_str("<desc>");
+        escape_html(desc, &mut elements);
+        elements.push_str("</desc>");
+    }
+    svg_code.insert_str(end, &elements);
+}
+
+/// Add an attribute to the root `<svg>` element. If the element already has
+/// `class` or `style`, the value is appended to it. Does nothing if the root
+/// element can't be found.
+pub fn add_root_attr(svg_code: &mut String, name: &str, value: &str) {
+    let Some(root_tag) = find_root_tag(svg_code) else {
+        return;
+    };
+
+    let mut escaped = String::new();
+    escape_html(value, &mut escaped);
+
+    let existing = format!(" {}=\"", name);
+    if let Some(i) = svg_code[root_tag.clone()].find(&existing) {
+        let value_start = root_tag.start + i + existing.len();
+        if let Some(len) = svg_code[value_start..].find('"') {
+            if matches!(name, "class" | "style") {
+                let separator = if name == "class" { ' ' } else { ';' };
+                svg_code.insert_str(value_start + len, &format!("{}{}", separator, escaped));
+            } else {
+                svg_code.replace_range(value_start..value_start + len, &escaped);
+            }
+            return;
+        }
+    }
+
+    let end = root_tag.end
+        - if svg_code[..root_tag.end].ends_with("/>") {
+            2
+        } else {
+            1
+        };
+    svg_code.insert_str(end, &format!(" {}=\"{}\"", name, escaped));
+}
+
+/// Get the value of an attribute of the root `<svg>` element.
+fn root_attr<'a>(svg_code: &'a str, name: &str) -> Option<&'a str> {
+    let root_tag = &svg_code[find_root_tag(svg_code)?];
+    let pattern = format!(" {}=\"", name);
+    let start = root_tag.find(&pattern)? + pattern.len();
+    Some(&root_tag[start..start + root_tag[start..].find('"')?])
+}
+
+/// Combine the SVG images of a diagram and its legend into one, placing the
+/// legend to the right of the diagram. The images are nested in a new root
+/// element whose background is `background`.
+pub fn compose_legend(
+    diagram: &str,
+    legend: &str,
+    gap: f32,
+    align: LegendAlign,
+    background: &str,
+) -> Result<String, String> {
+    let size = |svg_code: &str| {
+        let attr = |name| {
+            root_attr(svg_code, name)
+                .and_then(|value| value.parse::<f32>().ok())
+                .ok_or_else(|| format!("the image has no valid `{}` attribute", name))
+        };
+        Ok::<_, String>((attr("width")?, attr("height")?))
+    };
+    let (diagram_size, legend_size) = (size(diagram)?, size(legend)?);
+
+    // Avoid printing rounding errors
+    let round = |x: f32| (x * 100.0).round() / 100.0;
+    let width = round(diagram_size.0 + gap + legend_size.0);
+    let height = diagram_size.1.max(legend_size.1);
+    let y = |h: f32| {
+        round(match align {
+            LegendAlign::Top => 0.0,
+            LegendAlign::Center => (height - h) / 2.0,
+            LegendAlign::Bottom => height - h,
+        })
+    };
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" \
+         viewBox=\"0 0 {0} {1}\"><rect width=\"{0}\" height=\"{1}\" fill=\"",
+        width, height
+    );
+    escape_html(background, &mut out);
+    out.push_str("\"></rect>");
+    for (svg_code, x, h) in [
+        (diagram, 0.0, diagram_size.1),
+        (legend, round(diagram_size.0 + gap), legend_size.1),
+    ] {
+        let mut svg_code = svg_code.to_owned();
+        add_root_attr(&mut svg_code, "x", &x.to_string());
+        add_root_attr(&mut svg_code, "y", &y(h).to_string());
+        out.push_str(&svg_code);
+    }
+    out.push_str("</svg>");
+    Ok(out)
+}
+
+/// Prepare an SVG image to be included in an HTML document.
+///
+/// Unlike an image in an `<img>` element, an inline SVG image shares
+/// stylesheets and IDs with the page. Therefore, the image's stylesheets are
+/// scoped to the image by the class `svgbob-{suffix}`, and `-{suffix}` is
+/// appended to its IDs. The line breaks are replaced so that the image fits in
+/// a line of a Markdown HTML block.
+pub fn to_inline_svg(svg_code: &str, suffix: &str) -> String {
+    let class = format!("svgbob-{}", suffix);
+    let mut svg_code = svg_code.to_owned();
+
+    // Rename the IDs and the references to them (e.g., `url(#arrow)` in a
+    // stylesheet)
+    let ids: Vec<String> = svg_code
+        .match_indices(" id=\"")
+        .filter_map(|(i, m)| {
+            let rest = &svg_code[i + m.len()..];
+            Some(rest[..rest.find('"')?].to_owned())
+        })
+        .collect();
+    for id in ids {
+        let new_id = format!("{}-{}", id, suffix);
+        for (from, to) in [
+            (format!(" id=\"{}\"", id), format!(" id=\"{}\"", new_id)),
+            (format!("url(#{})", id), format!("url(#{})", new_id)),
+            (format!("\"#{}\"", id), format!("\"#{}\"", new_id)),
+        ] {
+            svg_code = svg_code.replace(&from, &to);
+        }
+    }
+
+    add_root_attr(&mut svg_code, "class", &class);
+
+    let mut output = String::with_capacity(svg_code.len());
+    let mut rest = &*svg_code;
+    while let Some(i) = rest.find("<style") {
+        let Some(content_start) = rest[i..].find('>').map(|k| i + k + 1) else {
+            break;
+        };
+        let content_len = rest[content_start..].find("</style>").unwrap_or(0);
+        output.push_str(&rest[..content_start]);
+        scope_css(
+            &rest[content_start..content_start + content_len],
+            &class,
+            &mut output,
+        );
+        rest = &rest[content_start + content_len..];
+    }
+    output.push_str(rest);
+
+    // Line breaks in tags (between attributes) are whitespace. Elsewhere
+    // (e.g., in `<desc>`), they are kept as character references.
+    let mut in_tag = false;
+    let mut inline = String::with_capacity(output.len());
+    for c in output.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            '\r' => continue,
+            '\n' if in_tag => {
+                inline.push(' ');
+                continue;
+            }
+            '\n' => {
+                inline.push_str("&#10;");
+                continue;
+            }
+            _ => {}
+        }
+        inline.push(c);
+    }
+    inline
+}
+
+/// Prefix each selector in a stylesheet with `.{class} `. A selector starting
+/// with `.svgbob ` (the class of the root element of an image rendered by
+/// svgbob) is rescoped. At-rules are left as-is.
+fn scope_css(css: &str, class: &str, out: &mut String) {
+    for rule in css.split_inclusive('}') {
+        let Some((selectors, body)) = rule.split_once('{') else {
+            out.push_str(rule);
+            continue;
+        };
+        if selectors.trim_start().starts_with('@') {
+            out.push_str(rule);
+            continue;
+        }
+        for (i, selector) in selectors.split(',').enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let selector = selector.trim();
+            let selector = selector.strip_prefix(".svgbob ").unwrap_or(selector);
+            out.push('.');
+            out.push_str(class);
+            out.push(' ');
+            out.push_str(selector.trim_start());
+        }
+        out.push('{');
+        out.push_str(body);
+    }
+}
+
+/// Escape a string for inclusion in HTML text or a quoted attribute value.
+pub fn escape_html(mut s: &str, out: &mut String) {
+    loop {
+        let i = s
+            .as_bytes()
+            .iter()
+            .position(|b| matches!(b, b'<' | b'>' | b'&' | b'"' | 0));
+        out.push_str(&s[..i.unwrap_or(s.len())]);
+        if let Some(i) = i {
+            out.push_str(match s.as_bytes()[i] {
+                b'<' => "&lt;",
+                b'>' => "&gt;",
+                b'&' => "&amp;",
+                b'"' => "&quot;",
+                0 => " ",
+                _ => unreachable!(),
+            });
+            s = &s[i + 1..];
+        } else {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        assert_eq!(
+            normalize_svg(
+                "<?xml version=\"1.0\"?>\n<!-- Generator: x 1.0 -->\n\
+                 <svg a=\">\"><metadata><x/></metadata><text>a&lt;!--</text>\n\
+                 <!-- b --></svg>"
+            ),
+            "<svg a=\">\"><text>a&lt;!--</text>\n</svg>"
+        );
+    }
+
+    /// Check that a document is well-formed XML whose root element is an SVG
+    /// element. Only supports the constructs found in the SVG images produced
+    /// by this crate (no DTDs, CDATA sections, comments, or processing
+    /// instructions).
+    fn check_well_formed(xml: &str) -> Result<(), String> {
+        fn check_text(text: &str) -> Result<(), String> {
+            if let Some(c) = text.chars().find(|&c| !is_xml_char(c) || c == '<') {
+                return Err(format!("invalid character {:?} in {:?}", c, text));
+            }
+            for part in text.split('&').skip(1) {
+                let (name, _) = part
+                    .split_once(';')
+                    .ok_or_else(|| format!("bare `&` in {:?}", text))?;
+                let valid = match name.strip_prefix('#') {
+                    Some(digits) => match digits.strip_prefix('x') {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => digits.parse().ok(),
+                    }
+                    .and_then(char::from_u32)
+                    .map_or(false, is_xml_char),
+                    None => matches!(name, "amp" | "lt" | "gt" | "quot" | "apos"),
+                };
+                if !valid {
+                    return Err(format!("invalid reference `&{};`", name));
+                }
+            }
+            Ok(())
+        }
+
+        let mut stack: Vec<&str> = Vec::new();
+        let mut num_roots = 0;
+        let mut rest = xml;
+        loop {
+            let i = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..i];
+            if stack.is_empty() && !text.trim().is_empty() {
+                return Err(format!("text outside the root element: {:?}", text));
+            }
+            check_text(text)?;
+            rest = &rest[i..];
+            if rest.is_empty() {
+                break;
+            }
+
+            let mut quote = None;
+            let end = rest
+                .find(|c| match (quote, c) {
+                    (None, '>') => true,
+                    (None, '"' | '\'') => {
+                        quote = Some(c);
+                        false
+                    }
+                    (Some(q), _) if q == c => {
+                        quote = None;
+                        false
+                    }
+                    _ => false,
+                })
+                .ok_or("unclosed tag")?;
+            let tag = &rest[1..end];
+            rest = &rest[end + 1..];
+
+            if let Some(name) = tag.strip_prefix('/') {
+                if stack.pop() != Some(name.trim_end()) {
+                    return Err(format!("mismatched `</{}>`", name));
+                }
+                continue;
+            }
+            let (tag, self_closing) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let name_len = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = &tag[..name_len];
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || ":_-".contains(c))
+            {
+                return Err(format!("invalid tag `<{}>`", tag));
+            }
+
+            let mut attrs: Vec<(&str, &str)> = Vec::new();
+            let mut attr_rest = tag[name_len..].trim_start();
+            while !attr_rest.is_empty() {
+                let (attr, value) = attr_rest
+                    .split_once('=')
+                    .ok_or_else(|| format!("attribute without a value in `<{}>`", tag))?;
+                let (attr, value) = (attr.trim_end(), value.trim_start());
+                let quote = value
+                    .chars()
+                    .next()
+                    .filter(|c| matches!(c, '"' | '\''))
+                    .ok_or_else(|| format!("unquoted attribute value in `<{}>`", tag))?;
+                let value_len = value[1..].find(quote).unwrap();
+                check_text(&value[1..1 + value_len])?;
+                if attrs.iter().any(|&(a, _)| a == attr) {
+                    return Err(format!("duplicate attribute `{}` in `<{}>`", attr, tag));
+                }
+                attrs.push((attr, &value[1..1 + value_len]));
+                attr_rest = value[value_len + 2..].trim_start();
+            }
+
+            if stack.is_empty() {
+                num_roots += 1;
+                if name != "svg" || !attrs.contains(&("xmlns", SVG_NAMESPACE)) {
+                    return Err(format!("invalid root element `<{}>`", tag));
+                }
+                if xml.contains(" xlink:") && !attrs.contains(&("xmlns:xlink", XLINK_NAMESPACE)) {
+                    return Err("undeclared `xlink` prefix".to_owned());
+                }
+            }
+            if !self_closing {
+                stack.push(name);
+            }
+        }
+
+        if let Some(name) = stack.last() {
+            return Err(format!("unclosed `<{}>`", name));
+        }
+        if num_roots != 1 {
+            return Err(format!("{} root elements", num_roots));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn standalone() {
+        let svg_code = to_standalone_svg(
+            "<svg><a xlink:href=\"#x\">&nbsp;&amp;&#0;&#x41;&bogus; & \u{1}\u{fffe}</a></svg>",
+        );
+        assert_eq!(
+            svg_code,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+             xmlns:xlink=\"http://www.w3.org/1999/xlink\">\
+             <a xlink:href=\"#x\">&#160;&amp;&#65533;&#65;&amp;bogus; &amp; \u{fffd}\u{fffd}</a></svg>"
+        );
+        assert_eq!(check_well_formed(&svg_code), Ok(()));
+        assert!(
+            check_well_formed("<svg xmlns=\"http://www.w3.org/2000/svg\">&nbsp;</svg>").is_err()
+        );
+        // Already standalone
+        assert_eq!(to_standalone_svg(&svg_code), svg_code);
+    }
+
+    #[test]
+    fn well_formed() {
+        let corpus = [
+            "a --> b",
+            ".---.\n| A |\n'---'",
+            "+--+  *-->  o--[x]\n|  |  <--*  /  \\\n+--+  ^  v  `--'",
+            "<b>bold</b> & \"quotes\" 'apostrophes' ]]>",
+            "&nbsp; &amp; &#0; &#xd800; & &;",
+            "tab\there \u{1}\u{1b}[0m \u{fffe}",
+            "日本語 → ✓ ｱｲｳ",
+            "\n  \n",
+        ];
+        let minified = RenderSettings {
+            font_family: "Fira & \"Co\"".to_owned(),
+            minify: true,
+            ..Default::default()
+        };
+        let pretty = RenderSettings {
+            text_length: false,
+            ..Default::default()
+        };
+
+        for art in corpus {
+            for settings in [&minified, &pretty] {
+                let Ok(mut svg_code) = to_svg(art, settings) else {
+                    continue;
+                };
+                insert_svg_description(&mut svg_code, Some(art), Some(art));
+                let svg_code = to_standalone_svg(&svg_code);
+                if let Err(e) = check_well_formed(&svg_code) {
+                    panic!("{} for {:?}:\n{}", e, art, svg_code);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inline_svg() {
+        let mut svg_code = "<svg class=\"svgbob\" style=\"a:b\">\n\
+             <style>.svgbob line, .svgbob text{stroke:red;marker-end:url(#arrow)}\n\
+             rect.backdrop{fill:white}</style>\
+             <marker id=\"arrow\"></marker><desc>a\nb</desc></svg>"
+            .to_owned();
+        add_root_attr(&mut svg_code, "role", "img");
+        add_root_attr(&mut svg_code, "style", "c:\"d\"");
+        assert_eq!(
+            to_inline_svg(&svg_code, "42"),
+            "<svg class=\"svgbob svgbob-42\" style=\"a:b;c:&quot;d&quot;\" role=\"img\">&#10;\
+             <style>.svgbob-42 line,.svgbob-42 text{stroke:red;marker-end:url(#arrow-42)}\
+             .svgbob-42 rect.backdrop{fill:white}</style>\
+             <marker id=\"arrow-42\"></marker><desc>a&#10;b</desc></svg>"
+        );
+    }
+
+    #[test]
+    fn legend() {
+        let diagram = r#"<svg width="40" height="32"><rect></rect></svg>"#;
+        let legend = r#"<svg x="9" width="20.5" height="16"></svg>"#;
+        assert_eq!(
+            compose_legend(diagram, legend, 8.0, LegendAlign::Center, "#fff").unwrap(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"68.5\" height=\"32\" \
+             viewBox=\"0 0 68.5 32\"><rect width=\"68.5\" height=\"32\" fill=\"#fff\"></rect>\
+             <svg width=\"40\" height=\"32\" x=\"0\" y=\"0\"><rect></rect></svg>\
+             <svg x=\"48\" width=\"20.5\" height=\"16\" y=\"8\"></svg></svg>"
+        );
+
+        let e = compose_legend(diagram, "<svg width=\"1\">", 0.0, LegendAlign::Top, "red");
+        assert_eq!(e.unwrap_err(), "the image has no valid `height` attribute");
+    }
+
+    #[test]
+    fn shape_rendering() {
+        let art = "+--+ a\n|  |\n+--+";
+        let svg_code = to_svg(art, &RenderSettings::default()).unwrap();
+        assert!(!svg_code.contains("shape-rendering"), "{}", svg_code);
+
+        for (shape_rendering, value) in [
+            (ShapeRendering::Crisp, "crispEdges"),
+            (ShapeRendering::Smooth, "geometricPrecision"),
+        ] {
+            let settings = RenderSettings {
+                shape_rendering,
+                ..Default::default()
+            };
+            let svg_code = to_svg(art, &settings).unwrap();
+            let root_tag = &svg_code[find_root_tag(&svg_code).unwrap()];
+            assert!(
+                root_tag.contains(&format!(r#" shape-rendering="{}""#, value)),
+                "{}",
+                svg_code
+            );
+            assert_eq!(svg_code.matches("shape-rendering").count(), 1);
+        }
+    }
+
+    #[test]
+    fn no_backdrop() {
+        let art = "+--+ a\n|  |\n+--+";
+        let svg_code = to_svg(art, &RenderSettings::default()).unwrap();
+        assert!(
+            svg_code.contains(r#"<rect class="backdrop""#),
+            "{}",
+            svg_code
+        );
+
+        let mut options = Options::default();
+        options
+            .apply("background", Some("none".to_owned()))
+            .unwrap();
+        let settings = RenderSettings::from_options(&options);
+        assert_eq!(settings.background, "transparent");
+        let svg_code = to_svg(art, &settings).unwrap();
+        assert!(!svg_code.contains(r#"class="backdrop""#), "{}", svg_code);
+    }
+
+    #[test]
+    fn text_length() {
+        let mut settings = RenderSettings::default();
+        let art = "a --> b";
+        assert!(to_svg(art, &settings).unwrap().contains(" textLength="));
+        settings.text_length = false;
+        let svg_code = to_svg(art, &settings).unwrap();
+        assert!(!svg_code.contains("textLength"), "{}", svg_code);
+        assert!(svg_code.contains(">b</text>"), "{}", svg_code);
+    }
+
+    #[test]
+    fn minify() {
+        assert_eq!(
+            minify_svg(
+                "<svg  xmlns=\"http://www.w3.org/2000/svg\" >\n  <style>.a line, .a path {\n  \
+                 stroke: black;\n  font-family: 'Source Code Pro', monospace;\n}\n</style>\n  \
+                 <rect class=\"backdrop\" x=\"0\" y=\"0\" width=\"10\"></rect>\n  \
+                 <text x=\"0\" y=\"10.5\"  textLength=\"8\">  a &lt;  b </text>\n  \
+                 <line x1 = '0' x2=\"0.0\" class=\"x=&quot;0&quot;\"/>\n  <g>\n  </g>\n</svg>"
+            ),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\"><style>.a line,.a path{stroke:black;\
+             font-family:'Source Code Pro',monospace;}</style>\
+             <rect class=\"backdrop\" width=\"10\"></rect>\
+             <text y=\"10.5\" textLength=\"8\">  a &lt;  b </text>\
+             <line x2=\"0.0\" class=\"x=&quot;0&quot;\"/><g></g></svg>"
+        );
+    }
+
+    #[test]
+    fn round() {
+        assert_eq!(
+            round_numbers(
+                "<svg width=\"221.53847\" viewBox=\"0 0 221.53847 98.46154\" a='1.234'>\
+                 <path d=\"M 6.1538463,55.384617 A 6.1,6 0,0,1 -0.001,.5\" class=\"x1.234\"/>\
+                 <text x=\"27.692308\"> x=\"1.234\" 3.14159</text></svg>",
+                2
+            ),
+            "<svg width=\"221.54\" viewBox=\"0 0 221.54 98.46\" a='1.234'>\
+             <path d=\"M 6.15,55.38 A 6.1,6 0,0,1 0,0.5\" class=\"x1.234\"/>\
+             <text x=\"27.69\"> x=\"1.234\" 3.14159</text></svg>"
+        );
+        assert_eq!(
+            round_numbers("<line x1=\"1.5\" x2=\"2.49\"/>", 0),
+            "<line x1=\"2\" x2=\"2\"/>"
+        );
+    }
+
+    /// Rounding doesn't move anything by more than half a unit in the last
+    /// decimal place, which is invisible at the usual sizes.
+    #[test]
+    fn rounding_preserves_geometry() {
+        let art =
+            ".---.  *-->  +--+\n| A |-----> /B/ |\n'---'  \"x\" +--+\n  (  )  o--/ \\ 3.14159";
+        let settings = RenderSettings {
+            font_size: 20,
+            scale: 20.0 / 13.0 * 8.0,
+            ..RenderSettings::default()
+        };
+        let rounded = to_svg(art, &settings).unwrap();
+        let exact = to_svg(
+            art,
+            &RenderSettings {
+                precision: 9,
+                ..settings
+            },
+        )
+        .unwrap();
+        assert!(rounded.len() < exact.len());
+
+        let split = |s: &str| -> Vec<String> {
+            s.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+                .map(str::to_owned)
+                .collect()
+        };
+        let (rounded_parts, exact_parts) = (split(&rounded), split(&exact));
+        assert_eq!(rounded_parts.len(), exact_parts.len());
+        for (r, e) in rounded_parts.iter().zip(&exact_parts) {
+            match (r.parse::<f64>(), e.parse::<f64>()) {
+                (Ok(r), Ok(e)) => assert!((r - e).abs() <= 0.005, "{} vs {}", r, e),
+                _ => assert_eq!(r, e),
+            }
+        }
+        assert!(rounded.contains(">3.14159</text>"), "{}", rounded);
+    }
+
+    /// Render `tests/snapshots/rounding.bob` at the default precision and
+    /// compare it with `tests/snapshots/rounding.svg`, which is regenerated
+    /// if `SVGBOBDOC_BLESS=1` is set. The snapshot's geometry must match that
+    /// of an unrounded rendering to a hundredth of a pixel. The font size
+    /// makes the cell size fractional so that there is something to round.
+    #[cfg(feature = "enable")]
+    #[test]
+    fn rounding_snapshot() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+        let art = std::fs::read_to_string(dir.join("rounding.bob")).unwrap();
+        let art = art.trim_end_matches('\n');
+        let settings = RenderSettings {
+            font_size: 20,
+            scale: 20.0 / 13.0 * 8.0,
+            ..RenderSettings::default()
+        };
+        let svg_code = to_svg(art, &settings).unwrap();
+
+        let snapshot_path = dir.join("rounding.svg");
+        if std::env::var_os("SVGBOBDOC_BLESS").map_or(false, |v| v == "1") {
+            std::fs::write(&snapshot_path, &svg_code).unwrap();
+        }
+        let snapshot = std::fs::read_to_string(&snapshot_path).unwrap();
+        assert_eq!(
+            svg_code, snapshot,
+            "the rendering differs from the snapshot; run with `SVGBOBDOC_BLESS=1` \
+             to update it if this is intended"
+        );
+
+        let exact = to_svg(
+            art,
+            &RenderSettings {
+                precision: 9,
+                ..settings
+            },
+        )
+        .unwrap();
+        assert_ne!(exact, snapshot, "nothing was rounded");
+        let split = |s: &str| -> Vec<String> {
+            s.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+                .map(str::to_owned)
+                .collect()
+        };
+        let (snapshot_parts, exact_parts) = (split(&snapshot), split(&exact));
+        assert_eq!(snapshot_parts.len(), exact_parts.len());
+        for (r, e) in snapshot_parts.iter().zip(&exact_parts) {
+            match (r.parse::<f64>(), e.parse::<f64>()) {
+                (Ok(r), Ok(e)) => assert!((r - e).abs() <= 0.005, "{} vs {}", r, e),
+                _ => assert_eq!(r, e),
+            }
+        }
+        assert!(snapshot.contains("3.14159"), "{}", snapshot);
+    }
+
+    #[test]
+    fn deterministic_output() {
+        let art = ".---.  *-->  +--+\n| A |-----> /B/ |\n'---'  \"x\" +--+";
+        let settings = RenderSettings::default();
+        let svg_code = to_svg(art, &settings).unwrap();
+        for _ in 0..4 {
+            assert_eq!(to_svg(art, &settings).unwrap(), svg_code);
+        }
+    }
+
+    #[cfg(feature = "enable")]
+    #[test]
+    fn text_width() {
+        assert_eq!(xml_text_width("abc"), 3);
+        assert_eq!(xml_text_width("あいう"), 6);
+        assert_eq!(xml_text_width("a &lt;b&gt; &amp;&quot;&apos;"), 9);
+        assert_eq!(xml_text_width("&#12354;&#x3044;&#X3046;"), 6);
+        assert_eq!(xml_text_width("a&#65;あ&#x3042;&amp;"), 7);
+        assert_eq!(xml_text_width("&#0;&#x110000;"), 1);
+        // Not entity references
+        assert_eq!(xml_text_width("a & b"), 5);
+        assert_eq!(xml_text_width("&#;&#xg;&;"), 10);
+    }
+
+    #[cfg(feature = "enable")]
+    #[test]
+    fn art_width() {
+        assert_eq!(art_size("abc\n--> "), (4, 2));
+        assert_eq!(art_size("あいう\n-->\n"), (6, 3));
+        assert_eq!(art_size(""), (0, 1));
+    }
+
+    #[test]
+    fn presets_pass_contrast_check() {
+        let light = RenderSettings::default();
+        assert!(light.low_contrast_colors(LIGHT_PAGE_BACKGROUND).is_empty());
+        let dark = light.to_dark();
+        let dark = RenderSettings {
+            background: "transparent".to_owned(),
+            ..dark
+        };
+        assert!(dark.low_contrast_colors(DARK_PAGE_BACKGROUND).is_empty());
+    }
+
+    #[test]
+    fn low_contrast_colors() {
+        let settings = RenderSettings {
+            stroke_color: "lightgray".to_owned(),
+            fill_color: "lightgray".to_owned(),
+            ..RenderSettings::default()
+        };
+        let found = settings.low_contrast_colors(LIGHT_PAGE_BACKGROUND);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!(found[0].name, "stroke");
+        assert_eq!(found[0].resolved, [0xd3, 0xd3, 0xd3, 0xff]);
+        assert_eq!(found[0].resolved_background, [0xff, 0xff, 0xff, 0xff]);
+
+        // A transparent background shows the page through
+        let settings = RenderSettings {
+            stroke_color: "#444".to_owned(),
+            fill_color: "#ddd".to_owned(),
+            background: "transparent".to_owned(),
+            ..RenderSettings::default()
+        };
+        let found = settings.low_contrast_colors(DARK_PAGE_BACKGROUND);
+        assert_eq!(found.len(), 1, "{:?}", found);
+        assert_eq!(found[0].name, "stroke");
+        assert_eq!(found[0].resolved_background, [0x35, 0x35, 0x35, 0xff]);
+        assert!(settings.low_contrast_colors("white").len() == 1);
+    }
+}
@@ -0,0 +1,165 @@
+//! Build-time statistics, enabled by `SVGBOBDOC_STATS=1`
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+/// The name of the environment variable enabling statistics.
+const ENV_VAR: &str = "SVGBOBDOC_STATS";
+
+/// The number of the slowest diagrams listed in the summary.
+const NUM_SLOWEST: usize = 10;
+
+/// Check if statistics are enabled.
+pub fn enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        std::env::var_os(ENV_VAR).map_or(false, |value| !value.is_empty() && value != "0")
+    })
+}
+
+/// The time taken to render a diagram.
+#[derive(Debug)]
+struct RenderTime {
+    /// The label or the first line of the diagram
+    name: String,
+    lines: usize,
+    time: Duration,
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    renders: Vec<RenderTime>,
+}
+
+impl Stats {
+    /// Call `render` and record the time taken by it.
+    pub fn time<T>(&mut self, name: String, lines: usize, render: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let output = render();
+        self.renders.push(RenderTime {
+            name,
+            lines,
+            time: start.elapsed(),
+        });
+        output
+    }
+
+    /// Format the summary of the recorded statistics.
+    pub fn summary(&self) -> String {
+        let total: Duration = self.renders.iter().map(|r| r.time).sum();
+        let mut out = format!(
+            "svgbobdoc: rendered {} diagram(s) in {} ms",
+            self.renders.len(),
+            total.as_millis()
+        );
+
+        let mut slowest: Vec<&RenderTime> = self.renders.iter().collect();
+        slowest.sort_by(|a, b| b.time.cmp(&a.time));
+        slowest.truncate(NUM_SLOWEST);
+        if !slowest.is_empty() {
+            out.push_str("; the slowest ones:");
+        }
+        for r in slowest {
+            write!(
+                out,
+                "\n  {:>6} ms  `{}` ({} lines)",
+                r.time.as_millis(),
+                r.name,
+                r.lines
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// Prints the summary when the compiler thread exits.
+struct ProcessStats(RefCell<Stats>);
+
+impl Drop for ProcessStats {
+    fn drop(&mut self) {
+        let stats = self.0.get_mut();
+        if !stats.renders.is_empty() {
+            eprintln!("{}", stats.summary());
+        }
+    }
+}
+
+thread_local! {
+    static PROCESS_STATS: ProcessStats = ProcessStats(RefCell::default());
+
+    /// The number of diagrams rendered by this thread, counted even if
+    /// statistics are disabled.
+    static RENDER_COUNT: Cell<usize> = Cell::new(0);
+}
+
+/// Get the number of diagrams rendered by this thread.
+pub fn render_count() -> usize {
+    RENDER_COUNT.with(Cell::get)
+}
+
+/// Call `render` and, if statistics are enabled, record the time taken by it
+/// to be included in the summary printed at the end of the compilation.
+pub fn time_render<T>(
+    name: impl FnOnce() -> String,
+    lines: usize,
+    render: impl FnOnce() -> T,
+) -> T {
+    RENDER_COUNT.with(|count| count.set(count.get() + 1));
+    if !enabled() {
+        return render();
+    }
+    // Don't hold the borrow in `render` in case it panics
+    let mut stats = Stats::default();
+    let output = stats.time(name(), lines, render);
+    PROCESS_STATS.with(|s| s.0.borrow_mut().renders.append(&mut stats.renders));
+    output
+}
+
+/// Record the time taken to render a diagram on another thread, which would
+/// otherwise be counted by that thread.
+#[cfg(feature = "parallel")]
+pub fn record_render(name: String, lines: usize, time: Duration) {
+    RENDER_COUNT.with(|count| count.set(count.get() + 1));
+    if enabled() {
+        PROCESS_STATS.with(|s| {
+            s.0.borrow_mut()
+                .renders
+                .push(RenderTime { name, lines, time })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_lists_slowest() {
+        let mut stats = Stats::default();
+        stats.time("fast".to_owned(), 1, || {});
+        stats.time("[slow]".to_owned(), 42, || {
+            std::thread::sleep(Duration::from_millis(50))
+        });
+
+        let summary = stats.summary();
+        assert!(
+            summary.starts_with("svgbobdoc: rendered 2 diagram(s) in "),
+            "{}",
+            summary
+        );
+        let lines: Vec<&str> = summary.lines().skip(1).collect();
+        assert_eq!(lines.len(), 2, "{}", summary);
+        assert!(
+            lines[0].ends_with(" ms  `[slow]` (42 lines)"),
+            "{}",
+            summary
+        );
+        let ms: u128 = lines[0].trim().split(' ').next().unwrap().parse().unwrap();
+        assert!(ms >= 50, "{}", summary);
+        assert!(lines[1].ends_with(" ms  `fast` (1 lines)"), "{}", summary);
+    }
+}
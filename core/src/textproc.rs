@@ -0,0 +1,3549 @@
+use base64::{engine::general_purpose, Engine as _};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Mutex, OnceLock},
+};
+
+#[cfg(feature = "files")]
+use crate::files::write_svg_file;
+#[cfg(feature = "parallel")]
+use crate::stats::record_render;
+use crate::{
+    color::{to_hex, MIN_CONTRAST_RATIO},
+    diag::{Error, Result, Span, Warnings},
+    options::{
+        parse_option_list, CheckLevel, Encoding, KeepSource, LabelDefs, LegendAlign, OptionPart,
+        Options, OutputMode, Storage,
+    },
+    render::{
+        add_root_attr, compose_legend, content_hash, escape_html, insert_svg_description,
+        to_inline_svg, to_standalone_svg, to_svg, RenderSettings, DARK_PAGE_BACKGROUND,
+        LIGHT_PAGE_BACKGROUND,
+    },
+    stats::time_render,
+    timeout::{TimeoutError, WORKERS},
+};
+
+/// The default value of the `timeout` option in milliseconds.
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+
+/// The default value of the `size-limit` option in kilobytes.
+const DEFAULT_SIZE_LIMIT_KB: usize = 64;
+
+/// The default value of the `legend-gap` option in pixels.
+const DEFAULT_LEGEND_GAP: f32 = 16.0;
+
+/// The line separating a diagram with `legend` from its legend.
+const LEGEND_SEPARATOR: &str = "#legend";
+
+/// The languages of code blocks treated as diagrams. `svgbob` is the canonical
+/// one; the others ease migration from other tools.
+const LANGUAGES: &[&str] = &["svgbob", "bob", "aa", "ascii-art", "diagram"];
+
+/// The current state of the code block finder.
+#[derive(Debug)]
+pub struct TextProcState {
+    code_block: Option<CodeBlock>,
+    indented_block: Option<IndentedBlock>,
+    /// The options applied to code blocks that don't override them.
+    defaults: Options,
+    /// The link labels defined so far, normalized by [`normalize_label`].
+    labels: HashMap<String, LabelDef>,
+    /// The link labels referenced by images (`![label]`) so far, normalized
+    /// by [`normalize_label`].
+    references: Vec<(String, Span)>,
+    /// The link labels defined by Markdown link reference definitions
+    /// (`[label]: url`) so far, normalized by [`normalize_label`]. They
+    /// satisfy references to images that aren't diagrams.
+    link_defs: Vec<String>,
+    trailing_defs: TrailingDefs,
+    /// The references with overrides (`![label]{...}`) found so far, which
+    /// are replaced with placeholders (see [`reference_placeholder`]) until
+    /// the end of the document.
+    overrides: Vec<ReferenceOverride>,
+    /// Tracks the indented code blocks that aren't diagrams, in which images
+    /// aren't references.
+    code_context: CodeContext,
+    /// The first non-blank line of the current document, which describes
+    /// where its labels are defined if the source location is unavailable
+    first_line: Option<String>,
+    /// The labels registered in [`UNIQUE_LABELS`] by this state, normalized
+    /// by [`normalize_label`]. They aren't cleared by
+    /// [`TextProcState::finalize`], so the documents processed for different
+    /// types by `transform_trait_impls!` can't define the same label.
+    unique_labels: HashSet<String>,
+    /// Where the parts of the input referred to by [`Span`]s are, as
+    /// provided by [`TextProcState::set_sites`]
+    sites: Vec<Site>,
+    cache: RenderCache,
+}
+
+/// Where a part of the input is in the source code. The text processor
+/// doesn't know it because [`Span`]s are just indices.
+#[derive(Debug, Clone)]
+pub struct Site {
+    /// Distinguishes the position from the others in the crate, not
+    /// necessarily in a readable way (e.g., a `proc_macro::Span`'s `Debug`
+    /// output).
+    pub id: String,
+    /// The file, line, and column, if known.
+    pub location: Option<String>,
+}
+
+/// A reference to a diagram label followed by options (`![label]{...}`).
+#[derive(Debug)]
+struct ReferenceOverride {
+    /// The label, normalized by [`normalize_label`]
+    label: String,
+    /// The image's text, which is the default alternative text
+    text: String,
+    options: Options,
+    span: Span,
+}
+
+/// A labeled diagram, which can be rendered again for a reference with
+/// overrides.
+#[derive(Debug, Clone)]
+struct LabelSource {
+    label: String,
+    art: String,
+    /// The options applied to the diagram, including the defaults
+    options: Options,
+}
+
+/// An exported diagram label.
+#[derive(Debug)]
+struct SharedLabel {
+    /// The link reference definition (`[label]: dest`)
+    def: String,
+    source: LabelSource,
+}
+
+/// The link reference definitions to be appended to the end of the document.
+#[derive(Debug, Default)]
+struct TrailingDefs {
+    /// The destinations (URLs followed by optional titles) of the images
+    /// emitted by `mode=reference` so far. The `i`-th one is defined as
+    /// `[svgbob-{i + 1}]`.
+    images: Vec<String>,
+    /// The definitions of the diagram labels (`[label]: dest`) moved by
+    /// `label-defs=end` so far
+    labels: Vec<String>,
+}
+
+/// The rendered images (`src`) keyed by diagrams' sources and the options
+/// affecting the rendering.
+pub(crate) type Images = HashMap<(String, String), String>;
+
+/// [`Images`] and, during a dry run, the diagrams found by it.
+#[derive(Debug, Default)]
+struct RenderCache {
+    images: Images,
+    /// The diagrams to be rendered by [`TextProcState::prerender`]. If this
+    /// is `Some`, diagrams are collected here instead of being rendered.
+    #[cfg(feature = "parallel")]
+    pending: Option<Vec<PendingRender>>,
+    /// The files read by `file`, which rustc must be told to track.
+    files: Vec<PathBuf>,
+    /// The labeled diagrams in the document, keyed by normalized labels
+    label_sources: HashMap<String, LabelSource>,
+    warnings: Warnings,
+}
+
+impl RenderCache {
+    /// Check if diagrams are being collected instead of being rendered.
+    fn is_dry_run(&self) -> bool {
+        #[cfg(feature = "parallel")]
+        return self.pending.is_some();
+        #[cfg(not(feature = "parallel"))]
+        false
+    }
+}
+
+/// A diagram found by a dry run.
+#[cfg(feature = "parallel")]
+#[derive(Debug)]
+struct PendingRender {
+    /// The key in [`SVG_CACHE`]
+    key: (String, String),
+    art: String,
+    settings: RenderSettings,
+    /// The time limit in milliseconds, or `0` for no limit
+    timeout: u64,
+    /// The label or the first line of the diagram
+    name: String,
+}
+
+/// The SVG images rendered so far in this process, keyed by diagrams' sources
+/// and `RenderSettings`. The macro invocations in a crate are expanded in the
+/// same process, so a diagram repeated across them is rendered only once.
+static SVG_CACHE: OnceLock<Mutex<HashMap<(String, String), String>>> = OnceLock::new();
+
+/// The exported diagram labels, keyed by crate names and normalized labels.
+/// References to them are resolved by the macro invocations expanded later
+/// with `shared-labels`.
+static SHARED_LABELS: OnceLock<Mutex<HashMap<(String, String), SharedLabel>>> = OnceLock::new();
+
+/// The diagram labels defined so far with `unique-labels`, keyed like
+/// [`SHARED_LABELS`].
+static UNIQUE_LABELS: OnceLock<Mutex<HashMap<(String, String), UniqueLabel>>> = OnceLock::new();
+
+/// The first definition of a diagram label with `unique-labels`.
+#[derive(Debug)]
+struct UniqueLabel {
+    /// Identifies the definition, so that defining it again by expanding the
+    /// same macro invocation again (as rust-analyzer does) isn't reported
+    site: String,
+    /// Describes where the label is defined
+    location: String,
+}
+
+/// The diagrams numbered by `number-figures`, keyed by crate names, in the
+/// order in which they're numbered.
+static FIGURES: OnceLock<Mutex<HashMap<String, Vec<Figure>>>> = OnceLock::new();
+
+/// A diagram numbered by `number-figures`.
+#[derive(Debug)]
+struct Figure {
+    /// The hash of the diagram's source and options, which identifies the
+    /// diagram when the macro invocation is expanded again
+    key: u64,
+    caption: String,
+}
+
+/// Get the name of the crate being compiled.
+fn crate_name() -> String {
+    std::env::var("CARGO_CRATE_NAME").unwrap_or_default()
+}
+
+/// Get the key of a label in [`SHARED_LABELS`] or [`UNIQUE_LABELS`].
+fn shared_label_key(label: &str) -> (String, String) {
+    (crate_name(), normalize_label(label))
+}
+
+/// Give the next figure number in the crate to a diagram with the specified
+/// caption. A diagram already numbered (identified by `key`) keeps its number.
+fn number_figure(key: u64, caption: &str) -> usize {
+    let mut figures = FIGURES.get_or_init(Default::default).lock().unwrap();
+    let figures = figures.entry(crate_name()).or_default();
+    if let Some(i) = figures.iter().position(|figure| figure.key == key) {
+        return i + 1;
+    }
+    figures.push(Figure {
+        key,
+        caption: caption.to_owned(),
+    });
+    figures.len()
+}
+
+/// Get the ID of the `<figure>` element of the specified figure number.
+fn figure_id(number: usize) -> String {
+    format!("figure-{}", number)
+}
+
+/// Produce a Markdown list of the figures numbered so far in the crate, each
+/// linking to its `<figure>` element. Returns `None` if there are no figures.
+pub fn figure_list() -> Option<String> {
+    use std::fmt::Write;
+
+    let figures = FIGURES.get_or_init(Default::default).lock().unwrap();
+    let figures = figures.get(&crate_name())?;
+    let mut output = String::new();
+    for (i, figure) in figures.iter().enumerate() {
+        write!(output, "- [Figure {}: ", i + 1).unwrap();
+        escape_markdown(&figure.caption, &mut output);
+        writeln!(output, "](#{})", figure_id(i + 1)).unwrap();
+    }
+    Some(output)
+}
+
+#[derive(Debug)]
+struct LabelDef {
+    /// The label as written in the code fence header.
+    label: String,
+    /// The fragment containing the code fence.
+    span: Span,
+    /// Whether the label may be referenced outside the macro invocation, in
+    /// which case it's not reported if it's never referenced inside.
+    exported: bool,
+    /// The ID of the anchor emitted by `anchor`.
+    anchor_id: Option<String>,
+}
+
+#[derive(Debug)]
+struct CodeBlock {
+    /// The markers of the blockquotes containing the code block (e.g., ` > `),
+    /// which are removed from each line and added to the output
+    quote: String,
+    /// The number of the blockquotes containing the code block
+    quote_depth: usize,
+    /// The indentation of the opening fence, which is removed from each line
+    /// of the content
+    indent: String,
+    /// The backtick or tilde run of the opening fence
+    fence: String,
+    captured: Option<CapturedCodeBlock>,
+    start: Span,
+}
+
+#[derive(Debug)]
+struct CapturedCodeBlock {
+    content: String,
+    params: CodeBlockParams,
+}
+
+/// An indented code block following a `<!-- svgbob -->` marker.
+#[derive(Debug)]
+struct IndentedBlock {
+    /// The indentation of the marker.
+    indent: String,
+    /// The minimum indentation of the code block's lines in columns.
+    width: usize,
+    /// The lines captured so far. `None` until the first line is found.
+    content: Option<String>,
+    /// The number of blank lines following `content`, which are part of the
+    /// code block only if more indented lines follow.
+    pending_blank_lines: usize,
+    params: CodeBlockParams,
+    start: Span,
+}
+
+/// Decides whether a line is in an indented code block that isn't marked as a
+/// diagram, which is left as-is.
+#[derive(Debug, Default)]
+struct CodeContext {
+    /// The minimum indentation in columns of the lines of the indented code
+    /// block being skipped, if any
+    code_width: Option<usize>,
+    /// Whether the previous line is a paragraph's, which an indented line
+    /// continues instead of starting a code block
+    in_paragraph: bool,
+    /// The content columns of the list items containing the current line
+    list_columns: Vec<usize>,
+    /// The minimum indentation of the top-level prose in columns, which is
+    /// `1` for `///` comments
+    doc_indent: Option<usize>,
+}
+
+impl CodeContext {
+    /// Check if a line continues the indented code block being skipped.
+    fn continues_code(&mut self, line: &str) -> bool {
+        let Some(width) = self.code_width else {
+            return false;
+        };
+        if line.trim().is_empty() || strip_indent_columns(line, width).is_some() {
+            return true;
+        }
+        self.code_width = None;
+        false
+    }
+
+    /// Check if a line starts an indented code block. If so, the following
+    /// lines are checked by [`Self::continues_code`].
+    fn starts_code(&mut self, line: &str) -> bool {
+        if self.in_paragraph || line.trim().is_empty() {
+            return false;
+        }
+        let base = match self.list_columns.last() {
+            Some(&column) => column,
+            None => self.doc_indent.unwrap_or(0),
+        };
+        let width = indentation_width(&line[..line.len() - line.trim_start().len()]);
+        if width < base + 4 {
+            return false;
+        }
+        self.code_width = Some(base + 4);
+        true
+    }
+
+    /// Track the lines that aren't in code blocks.
+    fn other_line(&mut self, line: &str, is_prose: bool) {
+        let content = line.trim_start();
+        if content.trim_end().is_empty() || !is_prose {
+            self.in_paragraph = false;
+            return;
+        }
+        let width = indentation_width(&line[..line.len() - content.len()]);
+        let marker_len = list_marker_len(content);
+        if !self.in_paragraph || marker_len.is_some() {
+            // A new block leaves the list items it isn't indented into
+            while self
+                .list_columns
+                .last()
+                .map_or(false, |&column| width < column)
+            {
+                self.list_columns.pop();
+            }
+            if self.list_columns.is_empty() {
+                self.doc_indent = Some(self.doc_indent.map_or(width, |d| d.min(width)));
+            }
+        }
+        if let Some(marker_len) = marker_len {
+            self.list_columns.push(width + marker_len);
+        }
+        self.in_paragraph = true;
+    }
+}
+
+/// Get the width of the list marker (e.g., `- ` or `1. `) starting a line,
+/// including the following spaces. Returns `None` if the line doesn't start
+/// with one.
+fn list_marker_len(line: &str) -> Option<usize> {
+    let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+    let marker = match line.as_bytes().get(digits)? {
+        b'-' | b'*' | b'+' if digits == 0 => 1,
+        b'.' | b')' if (1..=9).contains(&digits) => digits + 1,
+        _ => return None,
+    };
+    let rest = &line[marker..];
+    let spaces = rest.bytes().take_while(|&b| b == b' ').count();
+    Some(match spaces {
+        _ if rest.trim().is_empty() => marker + 1,
+        0 => return None,
+        // An indented code block starts one space after the marker
+        5.. => marker + 1,
+        _ => marker + spaces,
+    })
+}
+
+#[derive(Debug)]
+struct CodeBlockParams {
+    label: Option<String>,
+    options: Options,
+}
+
+/// The output of `TextProcState::step`.
+#[derive(Debug)]
+pub enum TextProcOutput {
+    /// Output the input fragment (`#[doc = "..."]`) without modification,
+    /// preserving its positional information.
+    Passthrough,
+    /// Output nothing.
+    Empty,
+    /// Output a new documentation text. The positional association between the
+    /// input fragment and `.0` is erased.
+    Fragment(String),
+}
+
+impl TextProcState {
+    pub fn new(defaults: Options) -> Self {
+        Self {
+            code_block: None,
+            indented_block: None,
+            defaults,
+            labels: HashMap::new(),
+            references: Vec::new(),
+            link_defs: Vec::new(),
+            trailing_defs: TrailingDefs::default(),
+            overrides: Vec::new(),
+            code_context: CodeContext::default(),
+            first_line: None,
+            unique_labels: HashSet::new(),
+            sites: Vec::new(),
+            cache: RenderCache::default(),
+        }
+    }
+
+    /// Like [`Self::new`] but reuses the images rendered for other documents.
+    pub(crate) fn with_images(defaults: Options, images: Images) -> Self {
+        let mut state = Self::new(defaults);
+        state.cache.images = images;
+        state
+    }
+
+    /// Get the images rendered so far, including those passed to
+    /// [`Self::with_images`].
+    pub(crate) fn into_images(self) -> Images {
+        self.cache.images
+    }
+
+    /// Process a fragment of a document. `spans` are the spans of the
+    /// fragment's lines. If there are fewer of them than the lines (e.g.,
+    /// because the lines of a string literal can't be located), the last one
+    /// is used for the rest of the lines.
+    pub fn step(&mut self, fragment: &str, spans: &[Span]) -> Result<TextProcOutput> {
+        if self.first_line.is_none() {
+            self.first_line = fragment
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty())
+                .map(str::to_owned);
+        }
+
+        let mut i = 0;
+        let mut line_index = 0;
+
+        let mut new_frag: Option<String> = None;
+
+        // If `new_frag` is `None`, then this flag indicates whether the input
+        // fragment is outputed as-is.
+        let mut passthrough = !matches!(
+            self.code_block,
+            Some(CodeBlock {
+                captured: Some(_),
+                ..
+            })
+        );
+
+        // Disables "pass-through" mode, preparing `new_frag` for custom
+        // generation.
+        macro_rules! prepare_nonpassthrough_emission {
+            () => {
+                if new_frag.is_none() {
+                    new_frag = Some(if passthrough {
+                        fragment[0..i].to_owned()
+                    } else {
+                        String::new()
+                    });
+                }
+                passthrough = false;
+            };
+        }
+
+        /// ```text
+        /// ^( *(?:`{3,}|~{3,}))\s*(.*?)\s*$
+        /// ```
+        fn detect_fence(s: &str) -> Option<(&str, &str)> {
+            let bytes = s.as_bytes();
+            let fence_len = {
+                let indent = bytes.iter().take_while(|&&b| b == b' ').count();
+                let fence_ch = *bytes.get(indent)?;
+                if !matches!(fence_ch, b'`' | b'~') {
+                    return None;
+                }
+                let fence = bytes[indent..]
+                    .iter()
+                    .take_while(|&&b| b == fence_ch)
+                    .count();
+                if fence < 3 {
+                    return None;
+                }
+                indent + fence
+            };
+
+            let (fence, rest) = s.split_at(fence_len);
+
+            Some((fence, rest.trim()))
+        }
+
+        /// Remove the opening fence's indentation from a line of the content,
+        /// or as much of it as the line has.
+        fn remove_indent<'a>(mut line: &'a str, mut indent: &str) -> &'a str {
+            while !line.is_empty()
+                && !indent.is_empty()
+                && line.as_bytes()[0] == indent.as_bytes()[0]
+                && (indent.as_bytes()[0] == b' ' || indent.as_bytes()[0] == b'\t')
+            {
+                line = &line[1..];
+                indent = &indent[1..];
+            }
+            line
+        }
+
+        loop {
+            let next_break = fragment[i..].find('\n');
+            let span = spans[line_index.min(spans.len() - 1)];
+
+            let line = &fragment[i..];
+            let line = if let Some(next_break) = next_break {
+                &line[0..next_break]
+            } else {
+                line
+            };
+
+            let mut close_code_block = false;
+            let mut passthrough_line = true;
+            let mut replacement_line: Option<String> = None;
+            let mut consumed = false;
+
+            if let Some(block) = &mut self.indented_block {
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                if line.trim().is_empty() {
+                    // A blank line may be either inside or after the code
+                    // block
+                    if block.content.is_some() {
+                        block.pending_blank_lines += 1;
+                        passthrough_line = false;
+                        consumed = true;
+                    }
+                } else if let Some(line) = strip_indent_columns(line, block.width) {
+                    let content = block.content.get_or_insert_with(String::new);
+                    for _ in 0..std::mem::take(&mut block.pending_blank_lines) {
+                        content.push('\n');
+                    }
+                    *content += line;
+                    content.push('\n');
+                    passthrough_line = false;
+                    consumed = true;
+                } else {
+                    // The indentation dropped below the code block's. Convert
+                    // the captured lines (if any) and process this line as
+                    // usual.
+                    let block = self.indented_block.take().unwrap();
+                    if let Some(content) = block.content {
+                        prepare_nonpassthrough_emission!();
+
+                        let captured = CapturedCodeBlock {
+                            content,
+                            params: block.params,
+                        };
+                        let new_frag = new_frag.as_mut().unwrap();
+                        self.emit_diagram(
+                            new_frag,
+                            captured,
+                            &block.indent,
+                            "",
+                            block.start,
+                            true,
+                        )?;
+                    }
+                }
+            }
+
+            if let (false, Some(code_block)) = (consumed, &self.code_block) {
+                if quote_markers(line, code_block.quote_depth).1 < code_block.quote_depth {
+                    // The blockquote containing the code block ended, and so
+                    // did the code block as per CommonMark. Convert the
+                    // captured lines (if any) and process this line as usual.
+                    let code_block = self.code_block.take().unwrap();
+                    if let Some(captured) = code_block.captured {
+                        prepare_nonpassthrough_emission!();
+
+                        let new_frag = new_frag.as_mut().unwrap();
+                        self.emit_diagram(
+                            new_frag,
+                            captured,
+                            &code_block.indent,
+                            &code_block.quote,
+                            code_block.start,
+                            true,
+                        )?;
+                    }
+                }
+            }
+
+            if consumed {
+                // Captured by the indented code block
+            } else if let Some(code_block) = &mut self.code_block {
+                let line = &line[quote_markers(line, code_block.quote_depth).0..];
+                if is_closing_fence(line, &code_block.indent, &code_block.fence) {
+                    // Reached the end of the code block
+                    if let Some(captured) = code_block.captured.take() {
+                        passthrough_line = false;
+                        prepare_nonpassthrough_emission!();
+
+                        // Convert this captured code block to a SVG diagram
+                        let (indent, quote, start) = (
+                            code_block.indent.clone(),
+                            code_block.quote.clone(),
+                            code_block.start,
+                        );
+                        let new_frag = new_frag.as_mut().unwrap();
+                        self.emit_diagram(
+                            new_frag,
+                            captured,
+                            &indent,
+                            &quote,
+                            start,
+                            next_break.is_some(),
+                        )?;
+                    }
+
+                    close_code_block = true;
+                } else if let Some(captured) = &mut code_block.captured {
+                    let line = line.strip_suffix('\r').unwrap_or(line);
+                    captured.content += remove_indent(line, &code_block.indent);
+                    captured.content.push('\n');
+                    passthrough_line = false;
+                    replacement_line = quoted_blank_line(&code_block.quote);
+                }
+            } else {
+                // Detect a code block, which may be in blockquotes
+                let (quote_len, quote_depth) = quote_markers(line, usize::MAX);
+                let (quote, unquoted) = line.split_at(quote_len);
+                if self.code_context.continues_code(unquoted) {
+                    // Left as-is
+                } else if let Some((fence, language)) = detect_fence(unquoted) {
+                    self.code_context.other_line(unquoted, false);
+                    let fence_chars = fence.trim_start();
+                    let mut code_block = CodeBlock {
+                        quote: quote.to_owned(),
+                        quote_depth,
+                        indent: fence[..fence.len() - fence_chars.len()].to_owned(),
+                        fence: fence_chars.to_owned(),
+                        captured: None,
+                        start: span,
+                    };
+
+                    if let Some(params) = parse_svgbob_info(language, LANGUAGES, span)? {
+                        passthrough_line = false;
+                        self.define_label(&params, span)?;
+
+                        if params.options.passthrough.or(self.defaults.passthrough) == Some(true) {
+                            // Leave the contents as-is, but make sure rustdoc
+                            // renders them as plain text
+                            replacement_line = Some(format!("{}{}text", quote, fence));
+                        } else {
+                            // This is the code blcok we are interested in.
+                            // Capture the contents.
+                            code_block.captured = Some(CapturedCodeBlock {
+                                content: String::new(),
+                                params,
+                            });
+                            replacement_line = quoted_blank_line(quote);
+                        }
+                    }
+
+                    self.code_block = Some(code_block);
+                } else if let Some(params) = detect_marker(line)
+                    .map(|info| parse_svgbob_info(info, &["svgbob"], span))
+                    .transpose()?
+                    .flatten()
+                {
+                    // `<!-- svgbob -->` marks the following indented code
+                    // block as a diagram. The marker itself is left as-is.
+                    self.code_context.other_line(unquoted, false);
+                    self.define_label(&params, span)?;
+                    if params.options.passthrough.or(self.defaults.passthrough) != Some(true) {
+                        let indent = &line[..line.len() - line.trim_start().len()];
+                        self.indented_block = Some(IndentedBlock {
+                            indent: indent.to_owned(),
+                            width: indentation_width(indent) + 4,
+                            content: None,
+                            pending_blank_lines: 0,
+                            params,
+                            start: span,
+                        });
+                    }
+                } else if self.code_context.starts_code(unquoted) {
+                    // An indented code block, which isn't a diagram
+                } else {
+                    self.code_context.other_line(unquoted, true);
+                    if let Some(label) = detect_link_definition(line) {
+                        self.link_defs.push(normalize_label(label));
+                    }
+                    let mut images = Vec::new();
+                    scan_image_references(line, |label, text, range| {
+                        images.push((label, text, range))
+                    });
+
+                    // Replace the references with overrides with placeholders
+                    let mut rewritten: Option<String> = None;
+                    let mut last = 0;
+                    for (label, text, range) in images {
+                        self.references.push((normalize_label(label), span));
+                        let Some(len) = override_len(&line[range.end..]) else {
+                            continue;
+                        };
+                        let options =
+                            parse_reference_overrides(&line[range.end + 1..range.end + len - 1])
+                                .map_err(|e| Error::new(span, e))?;
+                        let rewritten = rewritten.get_or_insert_with(String::new);
+                        rewritten.push_str(&line[last..range.start]);
+                        rewritten.push_str(&reference_placeholder(self.overrides.len()));
+                        self.overrides.push(ReferenceOverride {
+                            label: normalize_label(label),
+                            text: text.to_owned(),
+                            options,
+                            span,
+                        });
+                        last = range.end + len;
+                    }
+                    if let Some(mut rewritten) = rewritten {
+                        rewritten.push_str(&line[last..]);
+                        passthrough_line = false;
+                        replacement_line = Some(rewritten);
+                    }
+                }
+            }
+
+            if close_code_block {
+                self.code_block = None;
+            }
+
+            if passthrough_line {
+                if let Some(new_frag) = &mut new_frag {
+                    *new_frag += line;
+                    if next_break.is_some() {
+                        new_frag.push('\n');
+                    }
+                }
+            } else if passthrough || replacement_line.is_some() {
+                prepare_nonpassthrough_emission!();
+            }
+
+            if let Some(replacement_line) = replacement_line {
+                let new_frag = new_frag.as_mut().unwrap();
+                *new_frag += &replacement_line;
+                if next_break.is_some() {
+                    new_frag.push('\n');
+                }
+            }
+
+            if let Some(next_break) = next_break {
+                i += next_break + 1;
+                line_index += 1;
+            } else {
+                break;
+            }
+        }
+
+        Ok(if let Some(new_frag) = new_frag {
+            TextProcOutput::Fragment(new_frag)
+        } else if passthrough {
+            TextProcOutput::Passthrough
+        } else {
+            TextProcOutput::Empty
+        })
+    }
+
+    /// Process a document consisting of the specified fragments, each of which
+    /// is followed by a line break, and call [`Self::finalize`]. Each fragment
+    /// comes with the spans of its lines as in [`Self::step`].
+    pub fn transform_document(
+        &mut self,
+        fragments: impl IntoIterator<Item = Result<(String, Vec<Span>)>>,
+    ) -> Result<String> {
+        let fragments = fragments.into_iter().collect::<Result<Vec<_>>>()?;
+        #[cfg(feature = "parallel")]
+        self.prerender(&fragments);
+
+        let mut output = String::new();
+        for (st, spans) in fragments {
+            match self.step(&st, &spans)? {
+                TextProcOutput::Passthrough => output.push_str(&st),
+                TextProcOutput::Fragment(fr) => output.push_str(&fr),
+                TextProcOutput::Empty => {}
+            }
+            output.push('\n');
+        }
+        output += &self.finalize()?;
+        self.resolve_overrides(&mut output)?;
+        Ok(output)
+    }
+
+    /// Replace the placeholders of the references with overrides in `output`
+    /// with images rendered for them.
+    fn resolve_overrides(&mut self, output: &mut String) -> Result<()> {
+        let sources = std::mem::take(&mut self.cache.label_sources);
+        for (i, reference) in std::mem::take(&mut self.overrides).iter().enumerate() {
+            let source = match sources.get(&reference.label) {
+                Some(source) => Some(source.clone()),
+                None if self.defaults.shared_labels == Some(true) => SHARED_LABELS
+                    .get_or_init(Default::default)
+                    .lock()
+                    .unwrap()
+                    .get(&shared_label_key(&reference.label))
+                    .map(|shared| shared.source.clone()),
+                None => None,
+            };
+            let Some(source) = source else {
+                return Err(Error::new(
+                    reference.span,
+                    format!(
+                        "options can only follow a reference to a diagram label, \
+                         but `{}` isn't one",
+                        reference.label
+                    ),
+                ));
+            };
+            let image = render_reference(&source, reference, &mut self.cache)?;
+            *output = output.replacen(&reference_placeholder(i), &image, 1);
+        }
+        Ok(())
+    }
+
+    /// Render the diagrams in a document in parallel so that processing the
+    /// document finds them in [`SVG_CACHE`].
+    #[cfg(feature = "parallel")]
+    fn prerender(&self, fragments: &[(String, Vec<Span>)]) {
+        // Find the diagrams by processing the document without rendering
+        // them. Errors are reported when the document is processed for real.
+        let mut dry_run = TextProcState::new(self.defaults.clone());
+        dry_run.cache.pending = Some(Vec::new());
+        if fragments
+            .iter()
+            .all(|(st, spans)| dry_run.step(st, spans).is_ok())
+        {
+            let _ = dry_run.finalize();
+        }
+        let pending = dry_run.cache.pending.take().unwrap();
+        if pending.len() < 2 {
+            return;
+        }
+
+        let results = crate::parallel::map(pending, |p| {
+            let start = std::time::Instant::now();
+            let svg_code = if p.timeout == 0 {
+                Some(to_svg(&p.art, &p.settings))
+            } else {
+                // A diagram that timed out is rendered again (and reported)
+                // when the document is processed for real
+                let (art, settings) = (p.art.clone(), p.settings.clone());
+                WORKERS
+                    .run(std::time::Duration::from_millis(p.timeout), move || {
+                        to_svg(&art, &settings)
+                    })
+                    .ok()
+            };
+            (p, svg_code, start.elapsed())
+        });
+
+        let mut svg_cache = SVG_CACHE
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        for (p, svg_code, time) in results {
+            record_render(p.name, p.art.lines().count(), time);
+            // Failures are handled when the document is processed for real
+            if let Some(Ok(svg_code)) = svg_code {
+                svg_cache.entry(p.key).or_insert(svg_code);
+            }
+        }
+    }
+
+    /// Take the paths of the files read by `file` so far, which the macro
+    /// output should include to make rustc track them.
+    pub fn take_files(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.cache.files)
+    }
+
+    /// Add a file to those returned by [`Self::take_files`], e.g., the
+    /// `Cargo.toml` specifying the options.
+    pub fn track_file(&mut self, path: PathBuf) {
+        if !self.cache.files.contains(&path) {
+            self.cache.files.push(path);
+        }
+    }
+
+    /// Collect the warnings instead of writing them to the standard error
+    /// output, so that the caller can report them at their spans.
+    pub fn collect_warnings(&mut self) {
+        self.cache.warnings = Warnings::collecting();
+    }
+
+    /// Take the warnings collected since [`Self::collect_warnings`].
+    pub fn take_warnings(&mut self) -> Vec<(Span, String)> {
+        self.cache.warnings.take()
+    }
+
+    /// Specify where the parts of the input are in the source code, indexed
+    /// by [`Span::index`]. With `unique-labels`, they tell a definition of a
+    /// label from the same one expanded again and describe the first one
+    /// when another one is reported.
+    pub fn set_sites(&mut self, sites: Vec<Site>) {
+        self.sites = sites;
+    }
+
+    fn site(&self, span: Span) -> Option<&Site> {
+        self.sites.get(span.index()?)
+    }
+
+    /// Convert a captured code block to an SVG diagram and append it to
+    /// `output` with each line prefixed by `quote`. It's surrounded by blank
+    /// lines so that it neither joins the preceding paragraph nor lets an HTML
+    /// block swallow the following lines. The line break ending the trailing
+    /// blank line is left out unless `line_break` is set.
+    fn emit_diagram(
+        &mut self,
+        output: &mut String,
+        mut captured: CapturedCodeBlock,
+        indent: &str,
+        quote: &str,
+        start: Span,
+        line_break: bool,
+    ) -> Result<()> {
+        captured.content.pop(); // Remove trailing "\n"
+        let converted = convert_diagram(
+            &captured.content,
+            captured.params,
+            &self.defaults,
+            &mut self.cache,
+            &mut self.trailing_defs,
+            indent,
+            start,
+        )?;
+        // Leave no trace if nothing is left in place
+        if converted.is_empty() {
+            return Ok(());
+        }
+        let blank = quote.trim_end();
+        if !output.is_empty() {
+            output.push_str(blank);
+            output.push('\n');
+        }
+        *output += &quote_lines(&converted, quote);
+        output.push('\n');
+        output.push_str(blank);
+        if line_break {
+            output.push('\n');
+        }
+        Ok(())
+    }
+
+    /// Render a diagram read from elsewhere (e.g., a file). `params` is
+    /// specified like the part of a code fence header following `svgbob,`.
+    pub fn transform_diagram(&mut self, art: &str, params: &str, span: Span) -> Result<String> {
+        let mut params: CodeBlockParams = params.parse().map_err(|e| Error::new(span, e))?;
+        // The output is included in another doc comment, which references it
+        params.options.export = Some(true);
+        self.define_label(&params, span)?;
+
+        let mut output = String::new();
+        if params.options.passthrough.or(self.defaults.passthrough) == Some(true) {
+            write_code_block(&mut output, art, "text", "");
+        } else {
+            output = convert_diagram(
+                art,
+                params,
+                &self.defaults,
+                &mut self.cache,
+                &mut self.trailing_defs,
+                "",
+                span,
+            )?;
+        }
+        output.push('\n');
+        output += &self.finalize()?;
+        Ok(output)
+    }
+
+    /// Define the link label of a diagram if it has one.
+    fn define_label(&mut self, params: &CodeBlockParams, span: Span) -> Result<()> {
+        if let Some(label) = &params.label {
+            let def = LabelDef {
+                label: label.clone(),
+                span,
+                exported: params.options.export == Some(true),
+                anchor_id: (params.options.anchor.or(self.defaults.anchor) == Some(true))
+                    .then(|| anchor_id(label)),
+            };
+            if let Some(id) = &def.anchor_id {
+                // Different labels can be sanitized to the same ID
+                if let Some(other) = self
+                    .labels
+                    .values()
+                    .find(|other| other.anchor_id.as_ref() == Some(id))
+                {
+                    let mut error = Error::new(
+                        span,
+                        format!(
+                            "the anchor ID of label `{}` (`{}`) is already used by label `{}`",
+                            label, id, other.label
+                        ),
+                    );
+                    error.combine(Error::new(
+                        other.span,
+                        format!("label `{}` is defined here", other.label),
+                    ));
+                    return Err(error);
+                }
+            }
+            if let Some(first) = self.labels.insert(normalize_label(label), def) {
+                // Point at both definitions because either might be the
+                // misspelled one
+                let mut error =
+                    Error::new(span, format!("duplicate definition of label `{}`", label));
+                error.combine(Error::new(
+                    first.span,
+                    format!("label `{}` is first defined here", first.label),
+                ));
+                return Err(error);
+            }
+
+            // A diagram being prerendered is defined again for real
+            if self.defaults.unique_labels == Some(true) && !self.cache.is_dry_run() {
+                if !self.unique_labels.insert(normalize_label(label)) {
+                    return Err(Error::new(
+                        span,
+                        format!(
+                            "diagram label `{}` is already defined for another type by this \
+                             macro invocation",
+                            label
+                        ),
+                    ));
+                }
+
+                let location = self.describe_location(span);
+                let site = match self.site(span) {
+                    Some(site) => format!("{} {}", site.id, location),
+                    None => format!("{:?} {}", span, location),
+                };
+                let mut unique_labels = UNIQUE_LABELS.get_or_init(Default::default).lock().unwrap();
+                let first = unique_labels
+                    .entry(shared_label_key(label))
+                    .or_insert_with(|| UniqueLabel {
+                        site: site.clone(),
+                        location,
+                    });
+                if first.site != site {
+                    // The first definition's span belongs to another macro
+                    // invocation and can't be pointed at
+                    return Err(Error::new(
+                        span,
+                        format!(
+                            "diagram label `{}` is already defined by another macro \
+                             invocation in this crate, in {}",
+                            label, first.location
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Describe where the fragment at `span` is, for pointing at it from
+    /// another macro invocation.
+    fn describe_location(&self, span: Span) -> String {
+        if let Some(location) = self.site(span).and_then(|site| site.location.as_ref()) {
+            return format!("`{}`", location);
+        }
+
+        let mut line = self.first_line.clone().unwrap_or_default();
+        if let Some((i, _)) = line.char_indices().nth(40) {
+            line.truncate(i);
+            line += "...";
+        }
+        format!("the doc comment beginning with `{}`", line)
+    }
+
+    /// Finish processing a document. Returns the text to be appended to the
+    /// output, which is non-empty if an indented code block extends to the end.
+    ///
+    /// The state can then be reused for another document, in which case the
+    /// diagrams rendered so far are not rendered again.
+    pub fn finalize(&mut self) -> Result<String> {
+        let labels = std::mem::take(&mut self.labels);
+        let references = std::mem::take(&mut self.references);
+        let link_defs = std::mem::take(&mut self.link_defs);
+        self.first_line = None;
+        self.code_context = CodeContext::default();
+
+        if let Some(code_block) = self.code_block.take() {
+            if code_block.captured.is_some() {
+                return Err(Error::new(code_block.start, "unclosed code block"));
+            }
+        }
+
+        let mut output = String::new();
+        if let Some(IndentedBlock {
+            content: Some(content),
+            indent,
+            params,
+            start,
+            ..
+        }) = self.indented_block.take()
+        {
+            let captured = CapturedCodeBlock { content, params };
+            self.emit_diagram(&mut output, captured, &indent, "", start, false)?;
+            if !output.is_empty() {
+                // Separate the diagram from the last fragment
+                output.insert(0, '\n');
+            }
+        }
+
+        // Separate the definitions by blank lines from the document and each
+        // other
+        use std::fmt::Write;
+        let trailing_defs = std::mem::take(&mut self.trailing_defs);
+        for (i, dest) in trailing_defs.images.iter().enumerate() {
+            write!(output, "\n[svgbob-{}]: {}\n", i + 1, dest).unwrap();
+        }
+        for def in trailing_defs.labels.iter() {
+            write!(output, "\n{}\n", def).unwrap();
+        }
+
+        // Resolve the references to the labels exported by other macro
+        // invocations
+        let mut shared_defs = Vec::new();
+        if self.defaults.shared_labels == Some(true) {
+            let shared_labels = SHARED_LABELS.get_or_init(Default::default).lock().unwrap();
+            for (label, _) in references.iter() {
+                if labels.contains_key(label)
+                    || link_defs.contains(label)
+                    || shared_defs.contains(label)
+                {
+                    continue;
+                }
+                if let Some(shared) = shared_labels.get(&shared_label_key(label)) {
+                    write!(output, "\n{}\n", shared.def).unwrap();
+                    shared_defs.push(label.clone());
+                }
+            }
+        }
+
+        // A label is probably misspelled or left over if it's never
+        // referenced
+        let mut unused: Vec<_> = labels
+            .iter()
+            .filter(|(label, def)| !def.exported && !references.iter().any(|(r, _)| r == *label))
+            .map(|(_, def)| def)
+            .collect();
+        unused.sort_by_key(|def| &def.label);
+
+        let check = self.defaults.check_labels.unwrap_or(CheckLevel::Off);
+        if check != CheckLevel::Off {
+            let mut problems = Vec::new();
+            for (label, span) in references.iter() {
+                if !labels.contains_key(label)
+                    && !link_defs.contains(label)
+                    && !shared_defs.contains(label)
+                {
+                    problems.push((*span, format!("undefined diagram label `{}`", label)));
+                }
+            }
+            for def in unused {
+                problems.push((
+                    def.span,
+                    format!("diagram label `{}` is never referenced", def.label),
+                ));
+            }
+
+            if check == CheckLevel::Error {
+                let mut error: Option<Error> = None;
+                for (span, message) in problems {
+                    let e = Error::new(span, message);
+                    match &mut error {
+                        Some(error) => error.combine(e),
+                        None => error = Some(e),
+                    }
+                }
+                if let Some(error) = error {
+                    return Err(error);
+                }
+            } else if !self.cache.is_dry_run() {
+                for (span, message) in problems {
+                    self.cache.warnings.warn(span, message);
+                }
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// Parse the info string of a code block (or the content of a marker comment).
+/// Returns `None` if it doesn't start with one of `languages`.
+fn parse_svgbob_info(
+    info: &str,
+    languages: &[&str],
+    span: Span,
+) -> Result<Option<CodeBlockParams>> {
+    languages
+        .iter()
+        .find_map(|language| {
+            let rest = info.strip_prefix(language)?;
+            if rest.is_empty() {
+                Some("") // exactly "svgbob"
+            } else {
+                rest.strip_prefix(',') // `Some` if "svgbob,[...]"
+            }
+        })
+        .map(|params| params.parse().map_err(|e| Error::new(span, e)))
+        .transpose()
+}
+
+/// Check if a line closes a fenced code block opened by `fence` indented by
+/// `indent`. As per CommonMark, the closing fence may be longer than the
+/// opening one and may be followed by spaces (or `\r` in a file with CRLF line
+/// endings), but not by an info string.
+///
+/// Both fences may be indented by up to three spaces relative to the
+/// enclosing container (e.g., a list item), so the closing fence may be
+/// indented differently. The container's indentation isn't tracked, so up to
+/// three spaces more or fewer than the opening fence are accepted.
+fn is_closing_fence(line: &str, indent: &str, fence: &str) -> bool {
+    let line = line.trim_end();
+    let closing = line.trim_start_matches(' ');
+    let width = line.len() - closing.len();
+    let opening_width = indentation_width(indent);
+    if width > opening_width + 3 || width + 3 < opening_width {
+        return false;
+    }
+    let fence_ch = fence.as_bytes()[0];
+    closing.len() >= fence.len() && closing.bytes().all(|b| b == fence_ch)
+}
+
+/// Find up to `max_depth` blockquote markers (`>`, each optionally preceded by
+/// up to three spaces and followed by a space) at the start of a line. Returns
+/// their length in bytes and their number.
+fn quote_markers(line: &str, max_depth: usize) -> (usize, usize) {
+    let bytes = line.as_bytes();
+    let (mut len, mut depth) = (0, 0);
+    while depth < max_depth {
+        let spaces = bytes[len..].iter().take_while(|&&b| b == b' ').count();
+        if spaces > 3 || bytes.get(len + spaces) != Some(&b'>') {
+            break;
+        }
+        len += spaces + 1;
+        if bytes.get(len) == Some(&b' ') {
+            len += 1;
+        }
+        depth += 1;
+    }
+    (len, depth)
+}
+
+/// Get the line replacing a line removed from blockquotes, which keeps them
+/// from ending. Returns `None` outside blockquotes.
+fn quoted_blank_line(quote: &str) -> Option<String> {
+    (!quote.is_empty()).then(|| quote.trim_end().to_owned())
+}
+
+/// Prefix each line of a text with blockquote markers.
+fn quote_lines(text: &str, quote: &str) -> String {
+    if quote.is_empty() {
+        return text.to_owned();
+    }
+    text.split('\n')
+        .map(|line| {
+            if line.is_empty() {
+                quote.trim_end().to_owned()
+            } else {
+                format!("{}{}", quote, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Get the content of a line consisting of an HTML comment, e.g., `svgbob`
+/// for `<!-- svgbob -->`.
+fn detect_marker(line: &str) -> Option<&str> {
+    let comment = line.trim().strip_prefix("<!--")?.strip_suffix("-->")?;
+    Some(comment.trim())
+}
+
+/// Get the width of indentation in columns, expanding tabs to multiples of
+/// four as per CommonMark.
+fn indentation_width(indent: &str) -> usize {
+    indent.bytes().fold(0, |column, b| match b {
+        b'\t' => (column / 4 + 1) * 4,
+        _ => column + 1,
+    })
+}
+
+/// Remove `width` columns of indentation from a line. Returns `None` if the
+/// line is indented less than that.
+fn strip_indent_columns(line: &str, width: usize) -> Option<&str> {
+    let mut column = 0;
+    for (i, b) in line.bytes().enumerate() {
+        if column >= width {
+            return Some(&line[i..]);
+        }
+        match b {
+            b' ' => column += 1,
+            b'\t' => column = (column / 4 + 1) * 4,
+            _ => return None,
+        }
+    }
+    (column >= width).then_some("")
+}
+
+/// Normalize a link label for matching as per CommonMark, i.e., perform case
+/// folding and collapse consecutive whitespace characters.
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Derive the ID of a labeled diagram's anchor from the label: `diagram-`
+/// followed by the label in lowercase, in which each run of characters other
+/// than ASCII letters, digits, `-`, and `_` is replaced with `-`. Such runs
+/// are removed at the end of the label.
+fn anchor_id(label: &str) -> String {
+    let mut id = "diagram-".to_owned();
+    for c in label.trim().chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            id.push(c.to_ascii_lowercase());
+        } else if !id.ends_with('-') {
+            id.push('-');
+        }
+    }
+    while id.len() > "diagram-".len() && id.ends_with('-') {
+        id.pop();
+    }
+    id
+}
+
+/// Get the label of a Markdown link reference definition (`[label]: url`)
+/// starting a line.
+fn detect_link_definition(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix('[')?;
+    let (label, rest) = rest.split_once(']')?;
+    (rest.starts_with(':') && !label.trim().is_empty()).then_some(label)
+}
+
+/// Find the link labels referenced by reference-style images (`![label]`,
+/// `![text][label]`, and `![label][]`) in a line of Markdown text. Code spans
+/// and images escaped by a backslash (`\![label]`) are skipped.
+fn scan_image_references<'a>(
+    line: &'a str,
+    mut f: impl FnMut(&'a str, &'a str, std::ops::Range<usize>),
+) {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            // An escaped character (e.g., `\![label]`) doesn't start an image
+            b'\\' => i += 2,
+            b'`' => {
+                // Skip a code span
+                let run = bytes[i..].iter().take_while(|&&b| b == b'`').count();
+                let fence = &line[i..i + run];
+                i += run;
+                let mut k = i;
+                while let Some(pos) = line[k..].find(fence) {
+                    let end = k + pos;
+                    let run2 = bytes[end..].iter().take_while(|&&b| b == b'`').count();
+                    if run2 == run {
+                        i = end + run;
+                        break;
+                    }
+                    k = end + run2;
+                }
+            }
+            b'!' if bytes.get(i + 1) == Some(&b'[') => {
+                let start = i;
+                let text_start = i + 2;
+                let Some(text_len) = line[text_start..].find(']') else {
+                    break;
+                };
+                let text = &line[text_start..text_start + text_len];
+                i = text_start + text_len + 1;
+
+                match bytes.get(i) {
+                    Some(b'(') => {
+                        // An inline image
+                    }
+                    Some(b'[') => {
+                        let Some(label_len) = line[i + 1..].find(']') else {
+                            break;
+                        };
+                        let label = &line[i + 1..i + 1 + label_len];
+                        i += label_len + 2;
+                        f(if label.is_empty() { text } else { label }, text, start..i);
+                    }
+                    _ => f(text, text, start..i),
+                }
+            }
+            _ => i += 1,
+        }
+    }
+}
+
+/// The options that can be specified at a reference to a diagram label.
+const OVERRIDABLE_OPTIONS: &[&str] = &[
+    "alt",
+    "title",
+    "class",
+    "width",
+    "height",
+    "scale",
+    "font",
+    "font-size",
+    "stroke",
+    "stroke-width",
+    "background",
+    "crisp",
+    "rendering",
+];
+
+/// Get the length of the option list enclosed in braces (`{...}`) at the
+/// start of `s`, if any. A value in double quotes may include `}`.
+fn override_len(s: &str) -> Option<usize> {
+    let mut chars = s.char_indices();
+    if chars.next()? != (0, '{') {
+        return None;
+    }
+    let mut quoted = false;
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            '}' if !quoted => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parse the options following a reference to a diagram label.
+fn parse_reference_overrides(s: &str) -> std::result::Result<Options, String> {
+    let mut options = Options::default();
+    for part in parse_option_list(s)? {
+        match part {
+            OptionPart::Label(label, _) => {
+                return Err(format!(
+                    "a reference to a diagram label can't define label `{}`",
+                    label
+                ));
+            }
+            OptionPart::Option(key, value) => {
+                options.apply(&key, value)?;
+                if !OVERRIDABLE_OPTIONS.contains(&&*key) {
+                    return Err(format!(
+                        "option `{}` can't be specified at a reference to a diagram label",
+                        key
+                    ));
+                }
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Get the text standing in for the `i`-th reference with overrides until
+/// the end of the document. It can't appear in doc comments.
+fn reference_placeholder(i: usize) -> String {
+    format!("\u{0}svgbob-reference-{}\u{0}", i)
+}
+
+/// Render a labeled diagram as an HTML `<img>` element for a reference with
+/// overrides. The diagram is rendered with the overrides applied to its
+/// options, while only the overrides and the title apply to the element.
+fn render_reference(
+    source: &LabelSource,
+    reference: &ReferenceOverride,
+    cache: &mut RenderCache,
+) -> Result<String> {
+    let options = reference.options.clone().or(&source.options);
+    let settings = RenderSettings::from_options(&options);
+    let src = render_to_url(
+        &source.art,
+        &settings,
+        &options,
+        Some(&source.label),
+        None,
+        cache,
+        reference.span,
+    )?
+    .map_err(|e| {
+        Error::new(
+            reference.span,
+            format!("failed to render diagram `{}`: {}", source.label, e),
+        )
+    })?;
+
+    let display = reference.options.clone().or(&Options {
+        // The reference's text is written for the image like `alt`
+        alt: Some(reference.text.clone()),
+        title: source.options.title.clone(),
+        aria: source.options.aria,
+        ..Options::default()
+    });
+    let alt = display.alt.as_deref().unwrap_or_default();
+    let mut output = String::new();
+    write_img_element(&mut output, &src, None, alt, OutputMode::Img, &display);
+    Ok(output)
+}
+
+impl std::str::FromStr for CodeBlockParams {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut this = CodeBlockParams {
+            label: None,
+            options: Options::default(),
+        };
+
+        let mut label_text = None;
+        for part in parse_option_list(s)? {
+            match part {
+                OptionPart::Label(label, text) => {
+                    this.label = Some(label);
+                    label_text = text;
+                }
+                OptionPart::Option(key, value) => this.options.apply(&key, value)?,
+            }
+        }
+
+        // The alternative text of a labeled diagram is specified by each
+        // reference, so the label's text names the image by `title` instead
+        if this.options.title.is_none() {
+            this.options.title = label_text;
+        }
+
+        Ok(this)
+    }
+}
+
+/// Remove the `*` at the start of each line of a block doc comment
+/// (`/** ... */`) as rustdoc does. A doc comment converted to `#[doc = ...]`
+/// is no longer treated as such, so the stars would otherwise be rendered as
+/// list markers, turning a fence into an empty Rust code block (a doctest).
+///
+/// The stars must be in the same column, preceded only by whitespace, on
+/// every line except for the first line and the leading and trailing blank
+/// lines. Otherwise, or if `text` is a single line, it's returned unchanged.
+pub fn strip_comment_stars(text: &str) -> String {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let star_column = |line: &str| {
+        let column = line.len() - line.trim_start_matches([' ', '\t']).len();
+        line[column..].starts_with('*').then_some(column)
+    };
+
+    let mut start = usize::from(star_column(lines[0]).is_none());
+    let mut end = lines.len();
+    while start < end && lines[start].trim().is_empty() {
+        start += 1;
+    }
+    while end > start && lines[end - 1].trim().is_empty() {
+        end -= 1;
+    }
+    if lines.len() < 2 || start == end {
+        return text.to_owned();
+    }
+    let column = star_column(lines[start]);
+    if column.is_none()
+        || lines[start..end]
+            .iter()
+            .any(|line| star_column(line) != column)
+    {
+        return text.to_owned();
+    }
+    let column = column.unwrap();
+
+    lines
+        .iter()
+        .map(|line| match star_column(line) {
+            Some(c) if c == column => &line[column + 1..],
+            _ => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Read a diagram's source from a file whose path is relative to
+/// `CARGO_MANIFEST_DIR`. Returns the full path and the source without a
+/// trailing line break.
+pub fn read_art_file(path: &str, span: Span) -> Result<(PathBuf, String)> {
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let path = std::path::Path::new(&manifest_dir).join(path);
+    let art = std::fs::read_to_string(&path)
+        .map_err(|e| Error::new(span, format!("couldn't read `{}`: {}", path.display(), e)))?;
+    let mut art = art.replace("\r\n", "\n");
+    if art.ends_with('\n') {
+        art.pop();
+    }
+    Ok((path, art))
+}
+
+/// Render a diagram and get the text replacing its code block, indented by
+/// `indent`. If the renderer fails, the text is a code block containing the
+/// diagram's source. Other errors (e.g., a timeout) are reported at `span`.
+///
+/// The destinations of images referenced by `mode=reference` and the
+/// definitions of labels moved by `label-defs=end` are added to
+/// `trailing_defs`. The text is empty if nothing is left in place.
+fn convert_diagram(
+    art: &str,
+    params: CodeBlockParams,
+    defaults: &Options,
+    cache: &mut RenderCache,
+    trailing_defs: &mut TrailingDefs,
+    indent: &str,
+    span: Span,
+) -> Result<String> {
+    let options = params.options.or(defaults);
+    let mut settings = RenderSettings::from_options(&options);
+
+    let file_art;
+    let art = match &options.file {
+        Some(_) if !art.trim().is_empty() => {
+            return Err(Error::new(
+                span,
+                "a code block with option `file` must be empty",
+            ));
+        }
+        Some(file) => {
+            let (path, art) = read_art_file(file, span)?;
+            if !cache.files.contains(&path) {
+                cache.files.push(path);
+            }
+            file_art = art;
+            &file_art[..]
+        }
+        None => art,
+    };
+
+    // A link reference definition can only be used by Markdown images
+    let inline = options.mode == Some(OutputMode::Svg) && params.label.is_none();
+
+    // A link reference definition can only refer to a single image, and an
+    // inline SVG image can't be switched by `<picture>`
+    let dark_variant = options.dark_variant == Some(true) && params.label.is_none() && !inline;
+    if dark_variant {
+        // Let the page background show through
+        settings.background = "transparent".to_owned();
+    }
+
+    match options.check_contrast.unwrap_or(CheckLevel::Off) {
+        CheckLevel::Off => {}
+        // Don't report the colors twice
+        _ if cache.is_dry_run() => {}
+        check => check_contrast(
+            art,
+            &settings,
+            dark_variant,
+            check,
+            &mut cache.warnings,
+            span,
+        )?,
+    }
+
+    let fall_back = |e: String, warnings: &mut Warnings| {
+        // Degrade to the original text
+        let lang = options.fallback_lang.as_deref().unwrap_or("text");
+        warnings.warn(
+            span,
+            format_args!(
+                "failed to render a diagram starting with `{}`: {}; \
+                 emitting it as a `{}` code block instead",
+                art.lines().next().unwrap_or_default(),
+                e,
+                lang
+            ),
+        );
+        let mut output = String::new();
+        write_code_block(&mut output, art, lang, indent);
+        output
+    };
+
+    let label = params.label.as_deref();
+    // An inline SVG image is given unique class names and IDs
+    let inline_suffix = inline.then(|| {
+        let hash = content_hash(format!("{}{:?}{:?}", art, settings, options).as_bytes());
+        format!("{:08x}", hash as u32)
+    });
+    let inline_suffix = inline_suffix.as_deref();
+
+    let src = match render_to_url(art, &settings, &options, label, inline_suffix, cache, span)? {
+        Ok(src) => src,
+        Err(e) => return Ok(fall_back(e, &mut cache.warnings)),
+    };
+
+    let dark_src = if dark_variant {
+        match render_to_url(art, &settings.to_dark(), &options, label, None, cache, span)? {
+            Ok(src) => Some(src),
+            Err(e) => return Ok(fall_back(e, &mut cache.warnings)),
+        }
+    } else {
+        None
+    };
+
+    // Output the SVG as an image element
+    use std::fmt::Write;
+
+    // Keep the output inside the enclosing list item, if any
+    let mut output = indent.to_owned();
+
+    // The link destination with a title shown as a tooltip
+    let mut dest = src.clone();
+    if let Some(title) = options.title.as_deref().filter(|title| !title.is_empty()) {
+        dest.push_str(" \"");
+        escape_markdown(title, &mut dest);
+        dest.push('"');
+    }
+
+    // A source comment isn't needed if the source is visible anyway
+    let source_shown = options.keep_source.map_or(false, |k| k != KeepSource::Off)
+        || (options.show_source == Some(true) && params.label.is_none());
+
+    if let Some(label) = params.label {
+        // A link reference definition can only be used by Markdown images,
+        // so `mode` doesn't apply here
+        let def = format!("[{}]: {}", label, dest);
+        let in_place = options.label_defs.unwrap_or(LabelDefs::InPlace) == LabelDefs::InPlace;
+        if options.anchor == Some(true) {
+            // An empty HTML block, which must be separated from the
+            // definition by a blank line
+            write!(output, "<div id=\"{}\"></div>", anchor_id(&label)).unwrap();
+            if in_place {
+                write!(output, "\n\n{}", indent).unwrap();
+            }
+        }
+
+        let source = LabelSource {
+            label: label.clone(),
+            art: art.to_owned(),
+            options: options.clone(),
+        };
+        if options.export == Some(true) && !cache.is_dry_run() {
+            SHARED_LABELS
+                .get_or_init(Default::default)
+                .lock()
+                .unwrap()
+                .insert(
+                    shared_label_key(&label),
+                    SharedLabel {
+                        def: def.clone(),
+                        source: source.clone(),
+                    },
+                );
+        }
+        cache.label_sources.insert(normalize_label(&label), source);
+        if in_place {
+            output.push_str(&def);
+        } else {
+            trailing_defs.labels.push(def);
+        }
+    } else {
+        let mode = options.mode.unwrap_or(OutputMode::Markdown);
+        let alt = match &options.alt {
+            Some(alt) => alt.clone(),
+            None => default_alt(art),
+        };
+        // Markdown isn't recognized inside an HTML block, so wrapping
+        // requires the image to be an HTML element, too
+        let center = options.center == Some(true);
+        let scroll = options.scroll == Some(true);
+        // An inline SVG image must be in an HTML block, which it doesn't
+        // start by itself
+        let wrap = center || scroll || inline;
+        let link = options.link == Some(true);
+        // Markdown images can't be given a size
+        let sized = options.width.is_some() || options.height.is_some();
+        if wrap {
+            output.push_str("<div");
+            if center || scroll {
+                output.push_str(r#" style=""#);
+                if scroll {
+                    output.push_str("overflow-x:auto");
+                }
+                if center {
+                    if scroll {
+                        output.push(';');
+                    }
+                    output.push_str("text-align:center");
+                }
+                output.push('"');
+            }
+            output.push('>');
+        }
+        if let Some(caption) = &options.caption {
+            // A diagram being prerendered is numbered again for real
+            let number = (options.number_figures == Some(true) && !cache.is_dry_run()).then(|| {
+                let key = content_hash(format!("{}{:?}", art, options).as_bytes());
+                number_figure(key, caption)
+            });
+            // `<figure>` requires the image to be an HTML element, too
+            match number {
+                Some(number) => write!(output, r#"<figure id="{}">"#, figure_id(number)).unwrap(),
+                None => output.push_str("<figure>"),
+            }
+            if inline {
+                output.push_str(&src);
+                write!(
+                    output,
+                    r#"<figcaption id="svgbob-{}-caption">"#,
+                    inline_suffix.unwrap()
+                )
+                .unwrap();
+            } else {
+                write_img_element(&mut output, &src, dark_src.as_deref(), &alt, mode, &options);
+                output.push_str("<figcaption>");
+            }
+            if let Some(number) = number {
+                write!(output, "Figure {}: ", number).unwrap();
+            }
+            escape_html(caption, &mut output);
+            output.push_str("</figcaption></figure>");
+        } else if mode == OutputMode::Markdown && dark_src.is_none() && !wrap && !sized {
+            // An image can be the text of a link: `[![alt](src)](src)`
+            if link {
+                output.push('[');
+            }
+            output.push_str("![");
+            escape_markdown(&alt, &mut output);
+            write!(output, "]({})", dest).unwrap();
+            if link {
+                write!(output, "]({})", src).unwrap();
+            }
+        } else if mode == OutputMode::Reference && dark_src.is_none() && !wrap && !sized {
+            // Identical images share a definition
+            let images = &mut trailing_defs.images;
+            let i = match images.iter().position(|def| *def == dest) {
+                Some(i) => i,
+                None => {
+                    images.push(dest);
+                    images.len() - 1
+                }
+            };
+            if link {
+                output.push('[');
+            }
+            output.push_str("![");
+            escape_markdown(&alt, &mut output);
+            write!(output, "][svgbob-{}]", i + 1).unwrap();
+            if link {
+                write!(output, "][svgbob-{}]", i + 1).unwrap();
+            }
+        } else if inline {
+            output.push_str(&src);
+        } else {
+            write_img_element(&mut output, &src, dark_src.as_deref(), &alt, mode, &options);
+        }
+        if wrap {
+            output.push_str("</div>");
+        }
+
+        if options.show_source == Some(true) {
+            // Keep it on one line so that the art's blank lines and
+            // indentation can't end the HTML block or the enclosing list item
+            output.push_str("\n\n");
+            output.push_str(indent);
+            output.push_str("<details><summary>Diagram source</summary><pre>");
+            for (i, line) in art.split('\n').enumerate() {
+                if i > 0 {
+                    output.push_str("&#10;");
+                }
+                escape_html(line, &mut output);
+            }
+            output.push_str("</pre></details>");
+        }
+    }
+
+    if options.source_comment == Some(true) && !source_shown {
+        output.push_str("\n\n");
+        write_source_comment(&mut output, art, indent);
+    }
+
+    // Rustdoc doesn't run `text` code blocks as doctests
+    match options.keep_source.unwrap_or(KeepSource::Off) {
+        KeepSource::Off => {}
+        KeepSource::Before => {
+            let mut code_block = String::new();
+            write_code_block(&mut code_block, art, "text", indent);
+            code_block.push_str("\n\n");
+            output.insert_str(0, &code_block);
+        }
+        KeepSource::After => {
+            output.push_str("\n\n");
+            write_code_block(&mut output, art, "text", indent);
+        }
+    }
+
+    // Nothing is left in place if the label's definition was moved
+    if output.trim().is_empty() {
+        output.clear();
+    }
+    Ok(output)
+}
+
+/// Output an HTML comment containing a diagram's source, indented by
+/// `indent`. `&` and the second `-` of each `--` are replaced with `&amp;`
+/// and `&#45;`, respectively, so that the comment can't end early, and
+/// decoding character references restores the source.
+fn write_source_comment(output: &mut String, art: &str, indent: &str) {
+    output.push_str(indent);
+    output.push_str("<!-- svgbob-source\n");
+    for line in art.split('\n') {
+        output.push_str(indent);
+        let mut last = '\0';
+        for c in line.chars() {
+            match c {
+                '&' => output.push_str("&amp;"),
+                '-' if last == '-' => {
+                    output.push_str("&#45;");
+                    // Start a new pair
+                    last = '\0';
+                    continue;
+                }
+                _ => output.push(c),
+            }
+            last = c;
+        }
+        output.push('\n');
+    }
+    output.push_str(indent);
+    output.push_str("-->");
+}
+
+/// Split a diagram at the first [`LEGEND_SEPARATOR`] line into the diagram
+/// and its legend.
+fn split_legend(art: &str) -> Option<(&str, &str)> {
+    let mut start = 0;
+    for line in art.split('\n') {
+        let end = start + line.len();
+        if line.trim_end() == LEGEND_SEPARATOR {
+            let diagram = art[..start].strip_suffix('\n').unwrap_or_default();
+            return Some((diagram, art.get(end + 1..).unwrap_or_default()));
+        }
+        start = end + 1;
+    }
+    None
+}
+
+/// Render a diagram as an SVG image or get the cached one. Returns
+/// `Ok(Err(_))` if the renderer failed, and `Ok(Ok(String::new()))` in a dry
+/// run.
+#[cfg_attr(not(feature = "parallel"), allow(unused_variables))]
+fn render_svg(
+    art: &str,
+    settings: &RenderSettings,
+    options: &Options,
+    label: Option<&str>,
+    cache: &mut RenderCache,
+    span: Span,
+) -> Result<std::result::Result<String, String>> {
+    let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT_MS);
+    let name = || match label {
+        Some(label) => format!("[{}]", label),
+        None => art.lines().next().unwrap_or_default().to_owned(),
+    };
+    let svg_key = (art.to_owned(), format!("{:?}", settings));
+    let svg_cache = || {
+        SVG_CACHE
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    };
+    let cached = svg_cache().get(&svg_key).cloned();
+
+    #[cfg(feature = "parallel")]
+    if let Some(pending) = &mut cache.pending {
+        if cached.is_none() && !pending.iter().any(|p| p.key == svg_key) {
+            pending.push(PendingRender {
+                key: svg_key,
+                art: art.to_owned(),
+                settings: settings.clone(),
+                timeout,
+                name: name(),
+            });
+        }
+        return Ok(Ok(String::new()));
+    }
+
+    let result = if let Some(svg_code) = cached {
+        Ok(svg_code)
+    } else {
+        time_render(name, art.lines().count(), || {
+            if timeout == 0 {
+                return Ok(to_svg(art, settings));
+            }
+
+            let (art_owned, settings) = (art.to_owned(), settings.clone());
+            let e = match WORKERS.run(std::time::Duration::from_millis(timeout), move || {
+                to_svg(&art_owned, &settings)
+            }) {
+                Ok(result) => return Ok(result),
+                Err(e) => e,
+            };
+
+            if options.fallback_lang.is_some() {
+                // Degrade like other failures
+                return Ok(Err(match e {
+                    TimeoutError::TimedOut => {
+                        format!("the renderer did not finish within {} ms", timeout)
+                    }
+                    TimeoutError::TooManyAbandoned(count) => format!(
+                        "{} diagrams that timed out earlier are still being rendered",
+                        count
+                    ),
+                }));
+            }
+
+            let message = match e {
+                TimeoutError::TimedOut => format!(
+                    "rendering the diagram starting with `{}` ({} lines, {} bytes) \
+                 did not finish within {} ms; consider splitting the diagram, \
+                 raising the limit by `timeout=MS` or `SVGBOBDOC_RENDER_TIMEOUT_MS`, \
+                 or leaving it unrendered by `passthrough`",
+                    art.lines().next().unwrap_or_default(),
+                    art.lines().count(),
+                    art.len(),
+                    timeout
+                ),
+                TimeoutError::TooManyAbandoned(count) => format!(
+                    "not rendering the diagram starting with `{}` because {} \
+                 diagrams that timed out earlier are still being rendered",
+                    art.lines().next().unwrap_or_default(),
+                    count
+                ),
+            };
+            Err(Error::new(span, message))
+        })?
+    };
+    if let Ok(svg_code) = &result {
+        svg_cache()
+            .entry(svg_key)
+            .or_insert_with(|| svg_code.clone());
+    }
+    Ok(result)
+}
+
+/// Render a diagram and return the URL of the resulting image. Returns
+/// `Ok(Err(_))` if the renderer failed or, if `fallback-lang` is specified,
+/// timed out.
+fn render_to_url(
+    art: &str,
+    settings: &RenderSettings,
+    options: &Options,
+    label: Option<&str>,
+    inline_suffix: Option<&str>,
+    cache: &mut RenderCache,
+    span: Span,
+) -> Result<std::result::Result<String, String>> {
+    // Identical diagrams are rendered only once
+    let key = (
+        art.to_owned(),
+        format!("{:?} {:?} {:?}", settings, options, label),
+    );
+    if let Some(src) = cache.images.get(&key) {
+        return Ok(Ok(src.clone()));
+    }
+
+    let result = if options.legend == Some(true) {
+        let Some((diagram, legend)) = split_legend(art) else {
+            return Err(Error::new(
+                span,
+                format!(
+                    "a diagram with option `legend` must have a `{}` line",
+                    LEGEND_SEPARATOR
+                ),
+            ));
+        };
+        let diagram = render_svg(diagram, settings, options, label, cache, span)?;
+        let legend = render_svg(legend, settings, options, label, cache, span)?;
+        if cache.is_dry_run() {
+            return Ok(Ok(String::new()));
+        }
+        diagram.and_then(|diagram| {
+            compose_legend(
+                &diagram,
+                &legend?,
+                options.legend_gap.unwrap_or(DEFAULT_LEGEND_GAP),
+                options.legend_align.unwrap_or(LegendAlign::Top),
+                &settings.background,
+            )
+        })
+    } else {
+        render_svg(art, settings, options, label, cache, span)?
+    };
+    if cache.is_dry_run() {
+        return Ok(Ok(String::new()));
+    }
+
+    let mut svg_code = match result {
+        Ok(svg_code) => svg_code,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    if let Some(suffix) = inline_suffix {
+        let svg_code = inline_svg(svg_code, art, options, suffix);
+        cache.images.insert(key, svg_code.clone());
+        return Ok(Ok(svg_code));
+    }
+
+    // An empty title skips the element even if `alt` is specified
+    let title = options
+        .title
+        .as_ref()
+        .or(options.alt.as_ref())
+        .filter(|title| !title.is_empty());
+    let desc = (options.desc == Some(true)).then_some(art);
+    if title.is_some() || desc.is_some() {
+        insert_svg_description(&mut svg_code, title.map(|s| &**s), desc);
+    }
+    let svg_code = to_standalone_svg(&svg_code);
+
+    let file_url = match options.storage.unwrap_or(Storage::DataUri) {
+        Storage::DataUri => None,
+        Storage::File => write_svg_file(&svg_code, options).map_err(|e| Error::new(span, e))?,
+    };
+
+    let src = if let Some(file_url) = file_url {
+        file_url
+    } else {
+        embed_svg(&svg_code, art, options, &mut cache.warnings, span)?
+    };
+    cache.images.insert(key, src.clone());
+    Ok(Ok(src))
+}
+
+/// Prepare an SVG image to be emitted by `mode=svg`. The image is named by
+/// `<title>` and described by `<desc>` containing the diagram's source.
+fn inline_svg(mut svg_code: String, art: &str, options: &Options, suffix: &str) -> String {
+    let title = options
+        .title
+        .as_deref()
+        .or(options.alt.as_deref())
+        .or(options.caption.as_deref())
+        .unwrap_or_else(|| {
+            let mut lines = art.lines().map(str::trim);
+            lines.find(|line| !line.is_empty()).unwrap_or("diagram")
+        });
+    // Don't make screen readers read out the same text twice
+    let labelled_by_caption = options.caption.as_deref() == Some(title);
+    let desc = (options.desc != Some(false) && art.trim() != title).then_some(art);
+    let title = (!title.is_empty() && !labelled_by_caption).then_some(title);
+    if title.is_some() || desc.is_some() {
+        insert_svg_description(&mut svg_code, title, desc);
+    }
+
+    if options.aria != Some(false) {
+        if title.is_some() || labelled_by_caption {
+            add_root_attr(&mut svg_code, "role", "img");
+        } else {
+            // An empty title marks the image as decorative
+            add_root_attr(&mut svg_code, "aria-hidden", "true");
+        }
+        if labelled_by_caption {
+            add_root_attr(
+                &mut svg_code,
+                "aria-labelledby",
+                &format!("svgbob-{}-caption", suffix),
+            );
+        }
+    }
+    if options.scroll != Some(true) {
+        // Scale down wide diagrams like `<img>` elements
+        add_root_attr(&mut svg_code, "style", "max-width:100%;height:auto");
+    }
+    if let Some(class) = &options.class {
+        add_root_attr(&mut svg_code, "class", class);
+    }
+
+    to_inline_svg(&svg_code, suffix)
+}
+
+/// Report the colors that don't contrast enough with the background in the
+/// light variant and, if `dark_variant` is set, the dark variant.
+fn check_contrast(
+    art: &str,
+    settings: &RenderSettings,
+    dark_variant: bool,
+    check: CheckLevel,
+    warnings: &mut Warnings,
+    span: Span,
+) -> Result<()> {
+    let mut found = settings.low_contrast_colors(LIGHT_PAGE_BACKGROUND);
+    let num_light = found.len();
+    if dark_variant {
+        found.extend(settings.to_dark().low_contrast_colors(DARK_PAGE_BACKGROUND));
+    }
+
+    let mut error: Option<Error> = None;
+    for (i, c) in found.iter().enumerate() {
+        let message = format!(
+            "the {}diagram starting with `{}` has a low contrast ratio of {:.2}:1 \
+             (less than {}:1) between {} `{}` ({}) and background `{}` ({})",
+            if i < num_light {
+                ""
+            } else {
+                "dark variant of the "
+            },
+            art.lines().next().unwrap_or_default(),
+            c.ratio,
+            MIN_CONTRAST_RATIO,
+            c.name,
+            c.color,
+            to_hex(c.resolved),
+            c.background,
+            to_hex(c.resolved_background),
+        );
+        if check == CheckLevel::Error {
+            let e = Error::new(span, message);
+            match &mut error {
+                Some(error) => error.combine(e),
+                None => error = Some(e),
+            }
+        } else {
+            warnings.warn(span, message);
+        }
+    }
+
+    error.map_or(Ok(()), Err)
+}
+
+/// Encode an SVG image as a data URI. Reports the image at `span` if it
+/// exceeds `size-limit`.
+fn embed_svg(
+    svg_code: &str,
+    art: &str,
+    options: &Options,
+    warnings: &mut Warnings,
+    span: Span,
+) -> Result<String> {
+    let uri = match options.encoding.unwrap_or(Encoding::Base64) {
+        Encoding::Base64 => format!(
+            "data:image/svg+xml;base64,{}",
+            general_purpose::STANDARD.encode(svg_code)
+        ),
+        Encoding::Percent => {
+            let mut uri = "data:image/svg+xml;charset=utf-8,".to_owned();
+            percent_encode_data(svg_code, &mut uri);
+            uri
+        }
+    };
+
+    let size_limit = options.size_limit.unwrap_or(DEFAULT_SIZE_LIMIT_KB);
+    if size_limit != 0 && uri.len() > size_limit * 1024 && options.allow_large != Some(true) {
+        let message = format!(
+            "the diagram starting with `{}` takes {} KB when embedded, \
+             exceeding the limit of {} KB; consider simplifying it, using \
+             `storage=file` to store it in a separate file, or allowing it \
+             by `allow-large`",
+            art.lines().next().unwrap_or_default(),
+            (uri.len() + 1023) / 1024,
+            size_limit
+        );
+        if options.size_limit_error == Some(true) {
+            return Err(Error::new(span, message));
+        }
+        warnings.warn(span, message);
+    }
+
+    Ok(uri)
+}
+
+/// Percent-encode the characters that aren't allowed in a URL or would break
+/// the surrounding Markdown link destination or HTML attribute. Non-ASCII
+/// characters are left as-is.
+fn percent_encode_data(s: &str, out: &mut String) {
+    use std::fmt::Write;
+    for c in s.chars() {
+        if c.is_ascii_control() || " \"#%'()<>[\\]^`{|}".contains(c) {
+            write!(out, "%{:02X}", c as u32).unwrap();
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+/// Derive the alternative text of a diagram from its source by collapsing
+/// whitespace characters, including line breaks, into single spaces.
+fn default_alt(art: &str) -> String {
+    art.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Output text to be included in Markdown inline content verbatim (e.g., in
+/// the alternative text of an image) by escaping all ASCII punctuation
+/// characters.
+fn escape_markdown(s: &str, out: &mut String) {
+    for c in s.chars() {
+        if c.is_ascii_punctuation() {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Output a code block of the specified language containing the specified
+/// text. Each line is prefixed with `indent`.
+fn write_code_block(output: &mut String, text: &str, lang: &str, indent: &str) {
+    // The fence must be longer than any backtick sequence in `text`
+    let mut max_run = 0;
+    let mut run = 0;
+    for b in text.bytes() {
+        run = if b == b'`' { run + 1 } else { 0 };
+        max_run = max_run.max(run);
+    }
+    let fence = "`".repeat(max_run.max(2) + 1);
+
+    output.push_str(indent);
+    output.push_str(&fence);
+    output.push_str(lang);
+    output.push('\n');
+    for line in text.lines() {
+        output.push_str(indent);
+        output.push_str(line);
+        output.push('\n');
+    }
+    output.push_str(indent);
+    output.push_str(&fence);
+}
+
+#[cfg(not(feature = "files"))]
+fn write_svg_file(
+    _svg_code: &str,
+    _options: &Options,
+) -> std::result::Result<Option<String>, String> {
+    Err("`storage=file` requires the `files` Cargo feature".to_owned())
+}
+
+/// Output an HTML `<img>` element displaying the image at the specified URL.
+/// If `dark_src` is given, the element is wrapped in `<picture>` to display
+/// `dark_src` instead in dark mode. If `link` is enabled, the element is
+/// wrapped in a link to the image.
+fn write_img_element(
+    output: &mut String,
+    src: &str,
+    dark_src: Option<&str>,
+    alt: &str,
+    mode: OutputMode,
+    options: &Options,
+) {
+    let link = options.link == Some(true);
+    if link {
+        output.push_str(r#"<a href=""#);
+        escape_html(src, output);
+        output.push_str(r#"" target="_blank">"#);
+    }
+    if let Some(dark_src) = dark_src {
+        output.push_str(r#"<picture><source media="(prefers-color-scheme: dark)" srcset=""#);
+        escape_html(dark_src, output);
+        output.push_str(r#"">"#);
+    }
+    output.push_str(r#"<img src=""#);
+    escape_html(src, output);
+    output.push_str(r#"" alt=""#);
+    escape_html(alt, output);
+    output.push('"');
+    let mut style = Vec::new();
+    if let Some(width) = &options.width {
+        style.push(format!("width:{}", width));
+    }
+    if options.scroll == Some(true) {
+        // Keep the natural size; the enclosing `<div>` scrolls instead
+        style.push("max-width:none".to_owned());
+    } else if options.height.is_none() {
+        // Scale down wide diagrams to fit in the content column. Markdown
+        // images get the same treatment from rustdoc's stylesheet. A
+        // specified height is kept as is because shrinking the width would
+        // distort the image.
+        style.push("max-width:100%".to_owned());
+        style.push("height:auto".to_owned());
+    }
+    if let Some(height) = &options.height {
+        style.push(format!("height:{}", height));
+    }
+    output.push_str(r#" style=""#);
+    output.push_str(&style.join(";"));
+    output.push('"');
+    if mode == OutputMode::Img {
+        output.push_str(r#" loading="lazy" decoding="async""#);
+    }
+    if let Some(title) = options.title.as_deref().filter(|title| !title.is_empty()) {
+        output.push_str(r#" title=""#);
+        escape_html(title, output);
+        output.push('"');
+    }
+    if options.aria != Some(false) && options.alt.as_deref() != Some("") {
+        output.push_str(r#" role="img""#);
+        // The default alternative text (the diagram's source) is only useful
+        // when the image can't be displayed, so give it a concise name. A
+        // specified one already names the image.
+        if options.alt.is_none() {
+            let label = options.caption.as_deref().unwrap_or("diagram");
+            output.push_str(r#" aria-label=""#);
+            escape_html(label, output);
+            output.push('"');
+        }
+    }
+    // Let user stylesheets select diagrams
+    output.push_str(r#" class="svgbob"#);
+    if let Some(class) = &options.class {
+        output.push(' ');
+        escape_html(class, output);
+    }
+    output.push_str(r#"">"#);
+    if dark_src.is_some() {
+        output.push_str("</picture>");
+    }
+    if link {
+        output.push_str("</a>");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(input: &str) -> Result<String> {
+        let mut state = TextProcState::new(Options::default());
+        let output = match state.step(input, &[Span::call_site()])? {
+            TextProcOutput::Passthrough => input.to_owned(),
+            TextProcOutput::Fragment(fragment) => fragment,
+            TextProcOutput::Empty => String::new(),
+        };
+        let trailer = state.finalize()?;
+        Ok(output + &trailer)
+    }
+
+    /// Run the inputs in `tests/corpus` through the text processor and the
+    /// option parser. They must result in either `Ok(_)` or `Err(_)`, not a
+    /// panic.
+    #[test]
+    fn corpus() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+        let mut count = 0;
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let input = String::from_utf8(std::fs::read(&path).unwrap()).unwrap();
+            if path.extension() == Some("md".as_ref()) {
+                // As a string literal and as doc comments
+                let _ = process(&input);
+                let mut state = TextProcState::new(Options::default());
+                let _ = input
+                    .split('\n')
+                    .try_for_each(|line| state.step(line, &[Span::call_site()]).map(drop))
+                    .and_then(|()| state.finalize().map(drop));
+            } else {
+                for line in input.lines() {
+                    let _ = line.parse::<CodeBlockParams>();
+                }
+            }
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn keep_source() {
+        let output =
+            process(" - item\n\n   ```svgbob,keep-source\n   +--+\n    ``\n   ```").unwrap();
+        let (image, source) = output.split_once("\n\n   ```text\n").unwrap();
+        assert!(
+            image.starts_with(" - item\n\n\n   ![\\+\\-\\-\\+ \\`\\`](data:"),
+            "{}",
+            output
+        );
+        assert_eq!(source, "   +--+\n    ``\n   ```\n");
+
+        let output = process("```svgbob,keep-source=before\n+--+\n```").unwrap();
+        assert!(
+            output.starts_with("```text\n+--+\n```\n\n![\\+\\-\\-\\+](data:"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn tilde_fences() {
+        for fence in ["~~~", "~~~~", "~~~~~~~"] {
+            // The info string is only on the opening fence
+            let output = process(&format!(
+                "{0}svgbob,fallback-lang=x\n#test:empty\n{0}\nafter",
+                fence
+            ))
+            .unwrap();
+            assert_eq!(output, "```x\n#test:empty\n```\n\nafter", "{}", fence);
+
+            // A longer closing fence
+            let output = process(&format!(
+                "{}svgbob,fallback-lang=x\n#test:empty\n{}~~  \nafter",
+                fence, fence
+            ))
+            .unwrap();
+            assert_eq!(output, "```x\n#test:empty\n```\n\nafter", "{}", fence);
+        }
+
+        // Neither a shorter fence, a fence of the other character, nor a
+        // fence followed by an info string closes the block
+        let output =
+            process("~~~~svgbob,fallback-lang=x\n#test:empty\n~~~\n```\n~~~~ x\n~~~~\nafter")
+                .unwrap();
+        assert_eq!(
+            output,
+            "````x\n#test:empty\n~~~\n```\n~~~~ x\n````\n\nafter"
+        );
+    }
+
+    #[test]
+    fn backtick_fences() {
+        // Equal and longer closing fences
+        for closing in ["```", "```` ", "``````"] {
+            let output = process(&format!(
+                "```svgbob,fallback-lang=x\n#test:empty\n{}\nafter",
+                closing
+            ))
+            .unwrap();
+            assert_eq!(output, "```x\n#test:empty\n```\n\nafter", "{}", closing);
+        }
+
+        // A shorter one doesn't close the block
+        let output =
+            process("`````svgbob,fallback-lang=x\n#test:empty\n````\n`````\nafter").unwrap();
+        assert_eq!(output, "`````x\n#test:empty\n````\n`````\n\nafter");
+    }
+
+    #[test]
+    fn closing_fence_indentation() {
+        // Up to three spaces more or fewer than the opening fence
+        for (opening, closing) in [("", "   "), ("  ", ""), ("   ", "      "), ("    ", " ")] {
+            let output = process(&format!(
+                "{}```svgbob,fallback-lang=x\n#test:empty\n{}````\nafter",
+                opening, closing
+            ))
+            .unwrap();
+            assert_eq!(
+                output,
+                format!("{0}```x\n{0}#test:empty\n{0}```\n\nafter", opening),
+                "{:?}",
+                (opening, closing)
+            );
+        }
+
+        // More than that doesn't close the block
+        for (opening, closing) in [("", "    "), ("    ", "")] {
+            let input = format!(
+                "{}```svgbob,fallback-lang=x\n#test:empty\n{}```\n",
+                opening, closing
+            );
+            let e = process(&input).unwrap_err();
+            assert_eq!(e.to_string(), "unclosed code block", "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn language_aliases() {
+        for language in LANGUAGES {
+            let output = process(&format!(
+                "```{},fallback-lang=x\n#test:empty\n```\nafter",
+                language
+            ))
+            .unwrap();
+            assert_eq!(output, "```x\n#test:empty\n```\n\nafter", "{}", language);
+
+            let output = process_fragments(&[&format!("```{},[a]\n-->\n```\n![a]", language)]);
+            assert!(output.starts_with("[a]: data:"), "{}: {}", language, output);
+        }
+
+        // Neither a prefix of an alias nor a marker comment with an alias
+        // designates a diagram
+        let input = "```bobby\n-->\n```\n\n<!-- bob -->\n\n    -->\n";
+        assert_eq!(process(input).unwrap(), input);
+    }
+
+    #[test]
+    fn comment_stars() {
+        assert_eq!(
+            strip_comment_stars("\n * a\n *\n\t* ```svgbob\n * ```\n "),
+            "\n a\n\n ```svgbob\n ```\n "
+        );
+        assert_eq!(strip_comment_stars("* a\n * b"), "* a\n * b");
+        assert_eq!(strip_comment_stars("* a\n* b\n"), " a\n b\n");
+        assert_eq!(strip_comment_stars(" a\n b\n * c"), " a\n b\n * c");
+        assert_eq!(strip_comment_stars("* a"), "* a");
+        assert_eq!(strip_comment_stars("\n\n"), "\n\n");
+    }
+
+    #[test]
+    fn source_comment() {
+        let output = process(
+            " - item\n\n   ```svgbob,source-comment\n   <!-- a --> b\n   ---- &#45;\n   ```\n",
+        )
+        .unwrap();
+        let (_, comment) = output.split_once("\n\n   <!-- ").unwrap();
+        assert_eq!(
+            comment,
+            "svgbob-source\n   <!-&#45; a -&#45;> b\n   -&#45;-&#45; &amp;#45;\n   -->\n\n"
+        );
+
+        // Not if the source is visible anyway
+        for option in ["keep-source", "show-source"] {
+            let output =
+                process(&format!("```svgbob,source-comment,{}\n+--+\n```", option)).unwrap();
+            assert!(!output.contains("<!--"), "{}", output);
+        }
+    }
+
+    #[test]
+    fn check_labels() {
+        let check_with = |level, input: &str| {
+            let mut state = TextProcState::new(Options {
+                check_labels: Some(level),
+                ..Options::default()
+            });
+            state.transform_document(
+                input
+                    .split('\n')
+                    .map(|line| Ok((line.to_owned(), vec![Span::call_site()]))),
+            )
+        };
+        let check = |input: &str| check_with(CheckLevel::Error, input);
+
+        check("![a] ![b][A]\n\n```svgbob,[a]\n-->\n```").unwrap();
+
+        let e = check("![diagram]\n\n```svgbob,[diagramm]\n-->\n```").unwrap_err();
+        let messages: Vec<String> = e.into_iter().map(|(_, message)| message).collect();
+        assert_eq!(
+            messages,
+            [
+                "undefined diagram label `diagram`",
+                "diagram label `diagramm` is never referenced",
+            ]
+        );
+
+        // An image defined elsewhere
+        check("![logo] ![x][Logo]\n\n[logo]: https://example.com/logo.png").unwrap();
+
+        // Not references
+        check("`![a]` ``![b]`` \\![c] \\\\`![d]`\n\n```text\n![e]\n```\n\n~~~\n![f][g]\n~~~")
+            .unwrap();
+        check("\\\\![a]").unwrap_err();
+
+        // An exported label
+        check("```svgbob,[a],export\n-->\n```").unwrap();
+        check("```svgbob,[a],export=false\n-->\n```").unwrap_err();
+
+        // Indented code blocks
+        check("Text.\n\n    ![a] ![b]{scale=2}\n\n    ![c]\nText.").unwrap();
+        check(" Text.\n\n     ![a]\n\n - Item.\n\n       ![b]\n\n   1. Item.\n\n          ![c]")
+            .unwrap();
+        // Not indented enough, or continuing a paragraph
+        check(" Text.\n\n    ![a]").unwrap_err();
+        check(" - Item.\n\n      ![a]").unwrap_err();
+        check("Text.\n    ![a]").unwrap_err();
+        // Ended by a less indented line
+        check("    code\n![a]").unwrap_err();
+
+        // Reported as warnings
+        check_with(
+            CheckLevel::Warn,
+            "![diagram]\n\n```svgbob,[diagramm]\n-->\n```",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn shared_labels() {
+        let transform = |defaults: Options, input: &str| {
+            TextProcState::new(defaults).transform_document(
+                input
+                    .split('\n')
+                    .map(|line| Ok((line.to_owned(), vec![Span::call_site()]))),
+            )
+        };
+        let shared = || Options {
+            shared_labels: Some(true),
+            check_labels: Some(CheckLevel::Error),
+            ..Options::default()
+        };
+
+        // Not resolved before the definition is expanded
+        transform(shared(), "![Shared Figure]").unwrap_err();
+
+        let definition = transform(
+            Options::default(),
+            "```svgbob,[shared figure],export\n-->\n```",
+        )
+        .unwrap();
+        let definition = definition.trim();
+        assert!(
+            definition.starts_with("[shared figure]: data:"),
+            "{}",
+            definition
+        );
+
+        let output = transform(shared(), "![Shared Figure] ![x][shared  figure]").unwrap();
+        assert_eq!(
+            output,
+            format!("![Shared Figure] ![x][shared  figure]\n\n{}\n", definition)
+        );
+
+        // Opt-in
+        let output = transform(Options::default(), "![Shared Figure]").unwrap();
+        assert_eq!(output, "![Shared Figure]\n");
+    }
+
+    #[test]
+    fn file() {
+        let dir = std::env::temp_dir().join(format!("svgbobdoc-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.bob");
+        std::fs::write(&good, "-->\r\n").unwrap();
+        let bad = dir.join("bad.bob");
+        std::fs::write(&bad, b"\xff-->").unwrap();
+
+        // An absolute path is kept as is
+        let mut state = TextProcState::new(Options::default());
+        let output = state
+            .transform_document([Ok((
+                format!("```svgbob,file=\"{}\"\n```", good.display()),
+                vec![Span::call_site()],
+            ))])
+            .unwrap();
+        assert!(output.starts_with("![\\-\\-\\>](data:"), "{}", output);
+        assert_eq!(state.take_files(), [good.clone()]);
+
+        let e = process(&format!("```svgbob,file=\"{}\"\n-->\n```", good.display())).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "a code block with option `file` must be empty"
+        );
+
+        for path in [bad, dir.join("missing.bob")] {
+            let e = process(&format!("```svgbob,file=\"{}\"\n```", path.display())).unwrap_err();
+            let message = e.to_string();
+            assert!(
+                message.starts_with(&format!("couldn't read `{}`: ", path.display())),
+                "{}",
+                message
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unique_labels() {
+        let define = |unique_labels, doc: &str| {
+            let mut state = TextProcState::new(Options {
+                unique_labels: Some(unique_labels),
+                ..Options::default()
+            });
+            state.transform_document([Ok((
+                format!(
+                    "{}\n![unique label]\n```svgbob,[Unique  Label]\n-->\n```",
+                    doc
+                ),
+                vec![Span::call_site()],
+            ))])
+        };
+        define(false, "Foo").unwrap();
+        define(true, "Foo").unwrap();
+        // Expanding the same macro invocation again
+        define(true, "Foo").unwrap();
+        let e = define(true, "Bar").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "diagram label `Unique  Label` is already defined by another macro invocation in \
+             this crate, in the doc comment beginning with `Foo`"
+        );
+        define(false, "Bar").unwrap();
+
+        // The documents for different types in `transform_trait_impls!`
+        let mut state = TextProcState::new(Options {
+            unique_labels: Some(true),
+            ..Options::default()
+        });
+        let doc = || {
+            [Ok((
+                "```svgbob,[per type]\n-->\n```".to_owned(),
+                vec![Span::call_site()],
+            ))]
+        };
+        state.transform_document(doc()).unwrap();
+        let e = state.transform_document(doc()).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "diagram label `per type` is already defined for another type by this macro \
+             invocation"
+        );
+    }
+
+    #[test]
+    fn anchor() {
+        let output =
+            process(" - item\n\n   ```svgbob,[Boot Sequence!],anchor\n   -->\n   ```\n").unwrap();
+        assert!(
+            output.starts_with(
+                " - item\n\n\n   <div id=\"diagram-boot-sequence\"></div>\n\n   [Boot Sequence!]: data:"
+            ),
+            "{}",
+            output
+        );
+
+        assert_eq!(anchor_id("a_B-c"), "diagram-a_b-c");
+        assert_eq!(anchor_id("  図 1 "), "diagram-1");
+
+        let e = process("```svgbob,[a b],anchor\n-->\n```\n```svgbob,[a-b],anchor\n-->\n```")
+            .unwrap_err();
+        let messages: Vec<String> = e.into_iter().map(|(_, message)| message).collect();
+        assert_eq!(
+            messages,
+            [
+                "the anchor ID of label `a-b` (`diagram-a-b`) is already used by label `a b`",
+                "label `a b` is defined here",
+            ]
+        );
+    }
+
+    #[test]
+    fn label_defs() {
+        let output = process_fragments(&[
+            "- a ![x]\n  ```svgbob,[x],label-defs=end\n  -->\n  ```\n- b\n  \
+             ```svgbob,[y],label-defs=end,anchor\n  -->\n  ```\n- c ![y]",
+        ]);
+        let (document, defs) = output.split_once("\n\n[x]: data:").unwrap();
+        assert_eq!(
+            document,
+            "- a ![x]\n- b\n\n  <div id=\"diagram-y\"></div>\n\n- c ![y]"
+        );
+        assert!(defs.contains("\n\n[y]: data:"), "{}", output);
+        assert!(output.ends_with('\n'), "{}", output);
+    }
+
+    #[test]
+    fn reference_overrides() {
+        let output = process_fragments(&[
+            "![a] ![a]{scale=2, class=small, width=\"50%\"} `![a]{x}`",
+            "![text][a]{alt=\"}\"}",
+            "```svgbob,[a \"Arrow\"]\n-->\n```",
+        ]);
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].starts_with("![a] <img src=\"data:"), "{}", output);
+        assert!(
+            lines[0].ends_with(
+                "\" alt=\"a\" style=\"width:50%;max-width:100%;height:auto\" loading=\"lazy\" decoding=\"async\" \
+                 title=\"Arrow\" role=\"img\" \
+                 class=\"svgbob small\"> `![a]{x}`"
+            ),
+            "{}",
+            output
+        );
+        assert!(lines[1].contains(" alt=\"}\" "), "{}", output);
+        assert!(!output.contains('\0'), "{}", output);
+
+        // The overrides are applied to the rendering
+        let src = |line: &str| line.split('"').nth(1).unwrap().to_owned();
+        let def = output
+            .lines()
+            .find(|line| line.starts_with("[a]: "))
+            .unwrap();
+        assert_ne!(src(lines[0]), src(lines[1]));
+        assert!(
+            def.starts_with(&format!("[a]: {} ", src(lines[1]))),
+            "{}",
+            output
+        );
+
+        let e = TextProcState::new(Options::default())
+            .transform_document([Ok(("![a]{center}".to_owned(), vec![Span::call_site()]))])
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "option `center` can't be specified at a reference to a diagram label"
+        );
+        let e = TextProcState::new(Options::default())
+            .transform_document([Ok(("![a]{scale=2}".to_owned(), vec![Span::call_site()]))])
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "options can only follow a reference to a diagram label, but `a` isn't one"
+        );
+
+        // Left as-is in an indented code block
+        let input = "Text.\n\n    ![a]{scale=2}";
+        assert_eq!(process(input).unwrap(), input);
+    }
+
+    #[test]
+    fn duplicate_label() {
+        let e = process("```svgbob,[a]\n-->\n```\n\n```svgbob,[A]\n<--\n```\n").unwrap_err();
+        let messages: Vec<String> = e.into_iter().map(|(_, message)| message).collect();
+        assert_eq!(
+            messages,
+            [
+                "duplicate definition of label `A`",
+                "label `a` is first defined here",
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf() {
+        let output =
+            process("```svgbob,fallback-lang=x\r\n#test:empty\r\n```\r\nafter\r\n").unwrap();
+        assert_eq!(output, "```x\n#test:empty\n```\n\nafter\r\n");
+    }
+
+    #[test]
+    fn fallback_on_panic() {
+        let output = process("```svgbob,fallback-lang=plantuml\n#test:panic\n-->\n```\n").unwrap();
+        assert_eq!(output, "```plantuml\n#test:panic\n-->\n```\n\n");
+    }
+
+    #[test]
+    fn fallback_on_empty_output() {
+        let output = process(" - item\n\n   ```svgbob\n   #test:empty\n   ```\n").unwrap();
+        assert_eq!(
+            output,
+            " - item\n\n\n   ```text\n   #test:empty\n   ```\n\n"
+        );
+    }
+
+    #[test]
+    fn fallback_on_timeout() {
+        let output =
+            process("```svgbob,timeout=10,fallback-lang=plantuml\n#test:sleep\n```").unwrap();
+        assert_eq!(output, "```plantuml\n#test:sleep\n```\n");
+
+        // Without `fallback-lang`, a timeout is an error
+        let e = process("```svgbob,timeout=10\n#test:sleep\n```").unwrap_err();
+        assert!(
+            e.to_string().contains("did not finish within 10 ms"),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn indented_block() {
+        // Blank lines inside the code block are kept; the block ends when the
+        // indentation drops
+        let output =
+            process("<!-- svgbob,fallback-lang=x -->\n\n    #test:empty\n\n      a\n\n\nafter\n")
+                .unwrap();
+        assert_eq!(
+            output,
+            "<!-- svgbob,fallback-lang=x -->\n\n\n```x\n#test:empty\n\n  a\n```\n\nafter\n"
+        );
+
+        // The block may extend to the end
+        let output = process("<!-- svgbob,fallback-lang=x -->\n    #test:empty\n").unwrap();
+        assert_eq!(
+            output,
+            "<!-- svgbob,fallback-lang=x -->\n\n```x\n#test:empty\n```\n"
+        );
+
+        // Not followed by an indented code block
+        let input = "<!-- svgbob -->\ntext\n\n    code\n";
+        assert_eq!(process(input).unwrap(), input);
+    }
+
+    #[test]
+    fn prose_around_diagram() {
+        // Everything in one fragment. The prose must be neither dropped nor
+        // duplicated when a code block starts or ends mid-fragment.
+        for (input, expected) in [
+            (
+                "Some *prose*.\n  Indented line.\n```svgbob,fallback-lang=x\n#test:empty\n```",
+                "Some *prose*.\n  Indented line.\n\n```x\n#test:empty\n```\n",
+            ),
+            (
+                "```svgbob,fallback-lang=x\n#test:empty\n```\nMore *prose*.\n\n  Indented line.\n",
+                "```x\n#test:empty\n```\n\nMore *prose*.\n\n  Indented line.\n",
+            ),
+            (
+                "Before.\n\n```svgbob,fallback-lang=x\n#test:empty\n```\nAfter `code`.\n```rust\nlet x;\n```\nEnd.",
+                "Before.\n\n\n```x\n#test:empty\n```\n\nAfter `code`.\n```rust\nlet x;\n```\nEnd.",
+            ),
+            (
+                "A\n```svgbob,fallback-lang=x\n#test:empty\n```\nB\n```svgbob,fallback-lang=y\n#test:empty\n```\nC",
+                "A\n\n```x\n#test:empty\n```\n\nB\n\n```y\n#test:empty\n```\n\nC",
+            ),
+        ] {
+            assert_eq!(process(input).unwrap(), expected, "{:?}", input);
+        }
+    }
+
+    /// Process a document consisting of the specified fragments, such as
+    /// `#[doc = ...]` attributes produced by `///` comments.
+    fn process_fragments(fragments: &[&str]) -> String {
+        TextProcState::new(Options::default())
+            .transform_document(
+                fragments
+                    .iter()
+                    .map(|fragment| Ok((fragment.to_string(), vec![Span::call_site()]))),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn fenced_blocks_in_doc_comments() {
+        // One fragment per line, indented by a space. The prose and the
+        // other code block are kept verbatim, and the diagrams' lines are
+        // neither dropped nor leaked into the output.
+        let output = process_fragments(&[
+            " Before.",
+            " ```svgbob,fallback-lang=x",
+            " #test:empty",
+            "",
+            "   indented",
+            " ```",
+            " Between.",
+            " ```rust",
+            " let x;",
+            " ```",
+            " ~~~svgbob,fallback-lang=y",
+            " #test:empty",
+            " ~~~",
+            " After.",
+        ]);
+        assert_eq!(
+            output,
+            " Before.\n\n\n\n\n ```x\n #test:empty\n \n   indented\n ```\n\n Between.\n \
+             ```rust\n let x;\n ```\n\n\n ```y\n #test:empty\n ```\n\n After.\n"
+        );
+
+        // A block opening and closing in multi-line fragments
+        let output = process_fragments(&[
+            " Before.\n ```svgbob,fallback-lang=x\n #test:empty",
+            " #test:empty",
+            " ```\n After.\n ```svgbob,fallback-lang=y",
+            " #test:empty\n ```",
+        ]);
+        assert_eq!(
+            output,
+            " Before.\n\n\n ```x\n #test:empty\n #test:empty\n ```\n\n After.\n\n ```y\n \
+             #test:empty\n ```\n\n"
+        );
+    }
+
+    #[test]
+    fn blockquotes() {
+        // One fragment per line
+        let output = process_fragments(&[
+            " Before.",
+            " > Quoted.",
+            " > ```svgbob,fallback-lang=x",
+            " > #test:empty",
+            " >",
+            " >   indented",
+            " > ```",
+            " > After.",
+        ]);
+        assert_eq!(
+            output,
+            " Before.\n > Quoted.\n >\n >\n >\n >\n > ```x\n > #test:empty\n >\n >   indented\n \
+             > ```\n >\n > After.\n"
+        );
+
+        // Nested blockquotes in a multi-line fragment
+        let output = process_fragments(&[
+            "> > ~~~svgbob,fallback-lang=x\n> >#test:empty\n> > ~~~\n> > After.",
+        ]);
+        assert_eq!(
+            output,
+            "> >\n> >\n> >\n> > ```x\n> > #test:empty\n> > ```\n> >\n> > After.\n"
+        );
+
+        // Other code blocks in blockquotes are left as-is
+        let input = " > ```rust\n > ```svgbob\n > ```\n > ```svgbob,passthrough\n > -->\n > ```\n";
+        assert_eq!(
+            process_fragments(&[input]),
+            input.replace("svgbob,passthrough", "text") + "\n"
+        );
+
+        // The blockquote ends inside the code block
+        let output = process(" > ```svgbob,fallback-lang=x\n > #test:empty\n\nAfter.").unwrap();
+        assert_eq!(
+            output,
+            " >\n >\n >\n > ```x\n > #test:empty\n > ```\n >\n\nAfter."
+        );
+        let output =
+            process_fragments(&[" > ```svgbob,fallback-lang=x", " > #test:empty", " After."]);
+        assert_eq!(
+            output,
+            " >\n >\n > ```x\n > #test:empty\n > ```\n >\n After.\n"
+        );
+        let output =
+            process(" > ```rust\n > let x;\n ```svgbob,fallback-lang=x\n #test:empty\n ```")
+                .unwrap();
+        assert_eq!(
+            output,
+            " > ```rust\n > let x;\n\n ```x\n #test:empty\n ```\n"
+        );
+    }
+
+    #[test]
+    fn indented_block_in_doc_comments() {
+        // One fragment per line, indented by a space
+        let mut state = TextProcState::new(Options::default());
+        let mut output = String::new();
+        for line in [
+            " <!-- svgbob,fallback-lang=x -->",
+            "     #test:empty",
+            " ```",
+            "  ```",
+        ] {
+            match state.step(line, &[Span::call_site()]).unwrap() {
+                TextProcOutput::Passthrough => output += line,
+                TextProcOutput::Fragment(fragment) => output += &fragment,
+                TextProcOutput::Empty => {}
+            }
+            output.push('\n');
+        }
+        output += &state.finalize().unwrap();
+        assert_eq!(
+            output,
+            " <!-- svgbob,fallback-lang=x -->\n\n ```x\n #test:empty\n ```\n\n ```\n  ```\n"
+        );
+    }
+
+    #[test]
+    fn check_contrast() {
+        let input = "```svgbob,check-contrast=error,stroke=lightgray\n-->\n```";
+        let e = process(input).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "the diagram starting with `-->` has a low contrast ratio of 1.50:1 (less than \
+             3:1) between stroke `lightgray` (#d3d3d3) and background `white` (#ffffff)"
+        );
+
+        // A transparent background is checked against the page
+        let input = "```svgbob,check-contrast=error,stroke=lightgray,dark-variant\n-->\n```";
+        let e = process(input).unwrap_err();
+        assert!(
+            e.to_string()
+                .ends_with("and background `transparent` (#ffffff)"),
+            "{}",
+            e
+        );
+
+        // Warnings don't fail
+        process("```svgbob,check-contrast,stroke=lightgray\n-->\n```").unwrap();
+        process("```svgbob,check-contrast=error,dark-variant\n-->\n```").unwrap();
+
+        // Warnings can be collected to be reported at their spans
+        let mut state = TextProcState::new(Options::default());
+        state.collect_warnings();
+        state
+            .transform_document([Ok((
+                "```svgbob,check-contrast,stroke=lightgray\n-->\n```".to_owned(),
+                vec![Span::new(0)],
+            ))])
+            .unwrap();
+        let warnings = state.take_warnings();
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert_eq!(warnings[0].0, Span::new(0));
+        assert!(warnings[0].1.contains("low contrast"), "{:?}", warnings);
+    }
+
+    #[test]
+    fn nested_in_list() {
+        // One level deep. A line indented less than the fence loses what it
+        // has.
+        let output =
+            process(" - a\n\n   ```svgbob,fallback-lang=x\n   #test:empty\n    b\n c\n   ```\n")
+                .unwrap();
+        assert_eq!(
+            output,
+            " - a\n\n\n   ```x\n   #test:empty\n    b\n   c\n   ```\n\n"
+        );
+
+        // Two levels deep
+        let output = process(
+            " - a\n\n   1. b\n\n      ~~~~svgbob,fallback-lang=x\n      #test:empty\n        c\n      ~~~~\n",
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            " - a\n\n   1. b\n\n\n      ```x\n      #test:empty\n        c\n      ```\n\n"
+        );
+    }
+
+    #[test]
+    fn invalid_scale() {
+        for scale in ["0", "-1", "NaN", "inf", "big"] {
+            let e = process(&format!("```svgbob,scale={}\n-->\n```", scale)).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!("option `scale` must be a positive number, not `{}`", scale)
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_font_size() {
+        for size in ["0", "-1", "1.5", "big"] {
+            let e = process(&format!("```svgbob,font-size={}\n-->\n```", size)).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "option `font-size` must be a positive number of pixels, not `{}`",
+                    size
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn label_text() {
+        let output =
+            process("```svgbob,[machine \"State \\\"machine\\\"\"]\n-->\n```\n\n![machine]")
+                .unwrap();
+        let definition = output.lines().next().unwrap();
+        assert!(definition.starts_with("[machine]: data:"), "{}", output);
+        assert!(
+            definition.ends_with(r#" "State \"machine\"""#),
+            "{}",
+            output
+        );
+
+        // `title` takes precedence
+        let output = process("```svgbob,title=B,[a \"A\"]\n-->\n```\n\n![a]").unwrap();
+        assert!(
+            output.lines().next().unwrap().ends_with(r#" "B""#),
+            "{}",
+            output
+        );
+
+        let e = process("```svgbob,[a \"A\" b]\n-->\n```").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "expected `]` after the text of link label `a`"
+        );
+        let e = process("```svgbob,[a \"A]\n-->\n```").unwrap_err();
+        assert_eq!(e.to_string(), "unclosed quotes in link label `a`");
+    }
+
+    #[test]
+    fn legend() {
+        assert_eq!(
+            split_legend("a\nb\n#legend \nc\n#legend"),
+            Some(("a\nb", "c\n#legend"))
+        );
+        assert_eq!(split_legend("#legend"), Some(("", "")));
+        assert_eq!(split_legend("a\n #legend"), None);
+
+        let e = process("```svgbob,legend\n-->\n```").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "a diagram with option `legend` must have a `#legend` line"
+        );
+    }
+
+    #[test]
+    fn number_figures() {
+        let number = || {
+            process(
+                "```svgbob,number-figures,caption=\"A [b]\"\n-->\n```\n\
+                 ```svgbob,caption=c\n-->\n```\n\
+                 ```svgbob,number-figures,caption=d\n-->\n```",
+            )
+            .unwrap()
+        };
+        let output = number();
+        assert_eq!(output.matches(r#"<figure id="figure-1">"#).count(), 1);
+        assert_eq!(output.matches(r#"<figure id="figure-2">"#).count(), 1);
+        assert_eq!(output.matches("<figure>").count(), 1);
+        assert!(
+            output.contains("<figcaption>Figure 1: A [b]</figcaption>"),
+            "{}",
+            output
+        );
+        assert!(output.contains("<figcaption>c</figcaption>"), "{}", output);
+        assert!(
+            output.contains("<figcaption>Figure 2: d</figcaption>"),
+            "{}",
+            output
+        );
+
+        assert_eq!(
+            figure_list().unwrap(),
+            "- [Figure 1: A \\[b\\]](#figure-1)\n- [Figure 2: d](#figure-2)\n"
+        );
+
+        // Expanding the same macro invocation again
+        assert_eq!(number(), output);
+        assert_eq!(
+            figure_list().unwrap(),
+            "- [Figure 1: A \\[b\\]](#figure-1)\n- [Figure 2: d](#figure-2)\n"
+        );
+    }
+
+    #[test]
+    fn invalid_size() {
+        for length in ["0px", "-1em", "1.em", "1e3px", "20", "em", "20 em", "wide"] {
+            let e = process(&format!("```svgbob,width={}\n-->\n```", length)).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "option `width` must be a positive CSS length (e.g., `20em` or `50%`), not `{}`",
+                    length
+                )
+            );
+        }
+        process("```svgbob,height=.5IN\n-->\n```").unwrap();
+    }
+
+    #[test]
+    fn transform_diagram() {
+        let mut state = TextProcState::new(Options::default());
+        let output = state
+            .transform_diagram("+--+\n``", "passthrough", Span::call_site())
+            .unwrap();
+        assert_eq!(output, "```text\n+--+\n``\n```\n");
+
+        let output = state
+            .transform_diagram("-->", "[label]", Span::call_site())
+            .unwrap();
+        assert!(output.starts_with("[label]: data:"), "{}", output);
+
+        let e = state
+            .transform_diagram("-->", "mode=png", Span::call_site())
+            .unwrap_err();
+        assert_eq!(e.to_string(), "unknown output mode: `png`");
+    }
+
+    #[test]
+    fn invalid_stroke_width() {
+        for width in ["0", "-1", "NaN", "thick"] {
+            let e = process(&format!("```svgbob,stroke-width={}\n-->\n```", width)).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!(
+                    "option `stroke-width` must be a positive number of pixels, not `{}`",
+                    width
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn size_limit_error() {
+        let art = "-".repeat(1000);
+        let e = process(&format!(
+            "```svgbob,size-limit=1,size-limit-error\n{}\n```",
+            art
+        ))
+        .unwrap_err();
+        assert!(
+            e.to_string()
+                .starts_with(&format!("the diagram starting with `{}` takes ", art)),
+            "{}",
+            e
+        );
+        assert!(
+            e.to_string()
+                .contains(" KB when embedded, exceeding the limit of 1 KB;"),
+            "{}",
+            e
+        );
+
+        let output = process(&format!(
+            "```svgbob,size-limit=1,size-limit-error,allow-large\n{}\n```",
+            art
+        ))
+        .unwrap();
+        assert!(output.contains("](data:"), "{}", output);
+    }
+
+    #[test]
+    fn reference_mode() {
+        let mut state = TextProcState::new(Options {
+            mode: Some(OutputMode::Reference),
+            ..Options::default()
+        });
+        let input = "a\n```svgbob\n-->\n```\nb\n```svgbob\n<--\n```\n```svgbob\n-->\n```\nc";
+        let output = state
+            .transform_document(
+                input
+                    .split('\n')
+                    .map(|l| Ok((l.to_owned(), vec![Span::call_site()]))),
+            )
+            .unwrap();
+        let lines: Vec<&str> = output.lines().filter(|l| !l.is_empty()).collect();
+        assert_eq!(
+            lines[..6],
+            [
+                "a",
+                "![\\-\\-\\>][svgbob-1]",
+                "b",
+                "![\\<\\-\\-][svgbob-2]",
+                "![\\-\\-\\>][svgbob-1]",
+                "c",
+            ],
+            "{}",
+            output
+        );
+        assert_eq!(lines.len(), 8, "{}", output);
+        assert!(output.contains("\nc\n\n[svgbob-1]: data:"), "{}", output);
+        assert!(output.contains("\n\n[svgbob-2]: data:"), "{}", output);
+
+        // The numbering restarts in the next document
+        let output = state
+            .transform_document([Ok((
+                "```svgbob\n<--\n```".to_owned(),
+                vec![Span::call_site()],
+            ))])
+            .unwrap();
+        assert!(
+            output.starts_with("![\\<\\-\\-][svgbob-1]\n\n\n[svgbob-1]: data:"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn process_wide_cache() {
+        let input = "```svgbob\n+-- process_wide_cache --+\n```";
+        let count = crate::stats::render_count();
+        let first = process(input).unwrap();
+        // Another macro invocation
+        let second = process(input).unwrap();
+        assert_eq!(crate::stats::render_count() - count, 1);
+        assert_eq!(first, second);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn prerender() {
+        let transform = |doc: &str| {
+            TextProcState::new(Options::default())
+                .transform_document(
+                    doc.split('\n')
+                        .map(|l| Ok((l.to_owned(), vec![Span::call_site()]))),
+                )
+                .unwrap()
+        };
+        let diagrams: Vec<String> = (0..4)
+            .map(|i| format!("```svgbob\n+-- prerender {} --+\n```", i))
+            .collect();
+
+        let count = crate::stats::render_count();
+        let output = transform(&diagrams.join("\n\n"));
+        assert_eq!(crate::stats::render_count() - count, 4);
+
+        // The images are in the original order
+        let images: Vec<&str> = output.lines().filter(|l| l.starts_with("![")).collect();
+        assert_eq!(images.len(), 4, "{}", output);
+        for (diagram, image) in diagrams.iter().zip(images) {
+            assert_eq!(transform(diagram).trim(), image);
+        }
+        assert_eq!(crate::stats::render_count() - count, 4);
+    }
+
+    /// Compare the time taken to process a document with many large diagrams
+    /// serially and in parallel. Run it by `cargo test --release --features
+    /// enable,parallel -- --ignored --nocapture bench_prerender`.
+    #[cfg(feature = "parallel")]
+    #[test]
+    #[ignore]
+    fn bench_prerender() {
+        let doc = |run: &str| -> Vec<Result<(String, Vec<Span>)>> {
+            let mut lines = Vec::new();
+            for i in 0..8 {
+                lines.push("```svgbob".to_owned());
+                for row in 0..20 {
+                    lines.push(format!(
+                        "{}-{}-{} +--------+ .----. *----> o  {}",
+                        run,
+                        i,
+                        row,
+                        "|".repeat(row % 5)
+                    ));
+                }
+                lines.push("```".to_owned());
+            }
+            lines
+                .into_iter()
+                .map(|l| Ok((l, vec![Span::call_site()])))
+                .collect()
+        };
+
+        let start = std::time::Instant::now();
+        let mut state = TextProcState::new(Options::default());
+        for fragment in doc("serial") {
+            let (st, spans) = fragment.unwrap();
+            state.step(&st, &spans).unwrap();
+        }
+        state.finalize().unwrap();
+        let serial = start.elapsed();
+
+        let start = std::time::Instant::now();
+        TextProcState::new(Options::default())
+            .transform_document(doc("parallel"))
+            .unwrap();
+        let parallel = start.elapsed();
+
+        println!(
+            "serial: {} ms, parallel: {} ms ({} threads)",
+            serial.as_millis(),
+            parallel.as_millis(),
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Running renderers with a time limit
+use std::{
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    time::Duration,
+};
+
+/// The maximum number of timed-out workers that may be left running at the
+/// same time. Threads can't be killed, so a timed-out worker keeps running in
+/// the background until it finishes on its own.
+const MAX_ABANDONED_WORKERS: usize = 4;
+
+/// The reason [`Workers::run`] failed.
+#[derive(Debug, PartialEq)]
+pub enum TimeoutError {
+    /// The function didn't finish within the time limit.
+    TimedOut,
+    /// The function wasn't run because too many workers that timed out
+    /// earlier are still running.
+    TooManyAbandoned(usize),
+}
+
+/// Keeps track of the worker threads abandoned by [`Workers::run`].
+pub struct Workers {
+    abandoned: AtomicUsize,
+}
+
+/// The worker threads shared by all macro invocations in a compiler process.
+pub static WORKERS: Workers = Workers::new();
+
+const RUNNING: u8 = 0;
+const FINISHED: u8 = 1;
+const ABANDONED: u8 = 2;
+
+impl Workers {
+    pub const fn new() -> Self {
+        Self {
+            abandoned: AtomicUsize::new(0),
+        }
+    }
+
+    /// Run `f` on a worker thread and wait for it to finish for at most
+    /// `timeout`. If it doesn't finish in time, the worker thread is
+    /// abandoned.
+    pub fn run<T: Send + 'static>(
+        &'static self,
+        timeout: Duration,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, TimeoutError> {
+        let abandoned = self.abandoned.load(Ordering::SeqCst);
+        if abandoned >= MAX_ABANDONED_WORKERS {
+            return Err(TimeoutError::TooManyAbandoned(abandoned));
+        }
+
+        let state = Arc::new(AtomicU8::new(RUNNING));
+        let (send, recv) = mpsc::sync_channel(1);
+
+        {
+            let state = Arc::clone(&state);
+            let abandoned = &self.abandoned;
+            std::thread::spawn(move || {
+                let output = f();
+                if state
+                    .compare_exchange(RUNNING, FINISHED, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    let _ = send.send(output);
+                } else {
+                    // The caller gave up on us and counted us as abandoned
+                    abandoned.fetch_sub(1, Ordering::SeqCst);
+                }
+            });
+        }
+
+        match recv.recv_timeout(timeout) {
+            Ok(output) => return Ok(output),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => panic!("the worker thread panicked"),
+        }
+
+        // Count the worker as abandoned before letting it know so that it
+        // never decrements the counter first
+        self.abandoned.fetch_add(1, Ordering::SeqCst);
+        if state
+            .compare_exchange(RUNNING, ABANDONED, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+        {
+            Err(TimeoutError::TimedOut)
+        } else {
+            // It finished just now
+            self.abandoned.fetch_sub(1, Ordering::SeqCst);
+            Ok(recv.recv().unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn finishes_in_time() {
+        static WORKERS: Workers = Workers::new();
+        assert_eq!(WORKERS.run(Duration::from_secs(10), || 42), Ok(42));
+        assert_eq!(WORKERS.abandoned.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn times_out() {
+        static WORKERS: Workers = Workers::new();
+        let start = Instant::now();
+        let result = WORKERS.run(Duration::from_millis(10), || {
+            std::thread::sleep(Duration::from_millis(500))
+        });
+        assert_eq!(result, Err(TimeoutError::TimedOut));
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(WORKERS.abandoned.load(Ordering::SeqCst), 1);
+
+        // The abandoned worker is forgotten when it finishes
+        std::thread::sleep(Duration::from_millis(1000));
+        assert_eq!(WORKERS.abandoned.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn limits_abandoned_workers() {
+        static WORKERS: Workers = Workers::new();
+        let (send, recv) = mpsc::channel::<()>();
+        let recv = Arc::new(std::sync::Mutex::new(recv));
+        for _ in 0..MAX_ABANDONED_WORKERS {
+            let recv = Arc::clone(&recv);
+            let result = WORKERS.run(Duration::from_millis(10), move || {
+                let _ = recv.lock().unwrap().recv();
+            });
+            assert_eq!(result, Err(TimeoutError::TimedOut));
+        }
+
+        // No more threads are spawned
+        let result = WORKERS.run(Duration::from_secs(10), || unreachable!());
+        assert_eq!(
+            result,
+            Err(TimeoutError::TooManyAbandoned(MAX_ABANDONED_WORKERS))
+        );
+
+        // Let the workers finish
+        drop(send);
+        let start = Instant::now();
+        while WORKERS.abandoned.load(Ordering::SeqCst) > 0 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(WORKERS.run(Duration::from_secs(10), || 42), Ok(42));
+    }
+}
@@ -0,0 +1,112 @@
+use svgbobdoc_core::{
+    batch::{transform_paths, FileResult},
+    Problem, TransformOptions,
+};
+
+#[test]
+fn mixed_files() {
+    let dir = std::env::temp_dir().join(format!("svgbobdoc-batch-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut paths = Vec::new();
+    for i in 0..20 {
+        let (name, content) = match i % 4 {
+            0 => ("good", format!("# {}\n\n```svgbob\n--> {}\n```\n", i, i)),
+            1 => ("bad", format!("# {}\n\n```svgbob,scale=0\n-->\n```\n", i)),
+            2 => ("text", format!("# {}\n", i)),
+            _ => ("unclosed", format!("# {}\n\nText.\n\n~~~svgbob\n-->\n", i)),
+        };
+        let path = dir.join(format!("{:02}-{}.md", i, name));
+        std::fs::write(&path, content).unwrap();
+        paths.push(path);
+    }
+    paths.push(dir.join("missing.md"));
+
+    let options = TransformOptions {
+        defaults: "mode=img".to_owned(),
+    };
+    let results = transform_paths(&paths, &options);
+    assert_eq!(
+        results.iter().map(|r| &r.path).collect::<Vec<_>>(),
+        paths.iter().collect::<Vec<_>>()
+    );
+
+    let problems = |result: &FileResult| result.output.as_ref().unwrap_err().problems().to_vec();
+    for (i, result) in results[..20].iter().enumerate() {
+        match i % 4 {
+            0 => {
+                let output = result.output.as_ref().unwrap();
+                assert!(output.starts_with(&format!("# {}\n\n", i)), "{}", output);
+                assert!(
+                    output.contains("<img src=\"data:image/svg+xml;base64,"),
+                    "{}",
+                    output
+                );
+            }
+            1 => assert_eq!(
+                problems(result),
+                [Problem {
+                    line: Some(3),
+                    message: "option `scale` must be a positive number, not `0`".to_owned(),
+                }]
+            ),
+            2 => assert_eq!(result.output.as_ref().unwrap(), &format!("# {}\n", i)),
+            _ => assert_eq!(
+                problems(result),
+                [Problem {
+                    line: Some(5),
+                    message: "unclosed code block".to_owned(),
+                }]
+            ),
+        }
+    }
+
+    let problems = problems(&results[20]);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].line, None);
+    assert!(
+        problems[0].message.starts_with(&format!(
+            "couldn't read `{}`: ",
+            dir.join("missing.md").display()
+        )),
+        "{}",
+        problems[0].message
+    );
+
+    // Invalid options are reported for each file
+    let options = TransformOptions {
+        defaults: "scale=big".to_owned(),
+    };
+    let results = transform_paths(&paths[..2], &options);
+    assert_eq!(results.len(), 2);
+    for result in &results {
+        assert_eq!(
+            result.output.as_ref().unwrap_err().to_string(),
+            "invalid default options: option `scale` must be a positive number, not `big`"
+        );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "parallel"))]
+fn shared_cache() {
+    let dir = std::env::temp_dir().join(format!("svgbobdoc-batch-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths: Vec<_> = (0..3)
+        .map(|i| {
+            let path = dir.join(format!("{}.md", i));
+            std::fs::write(&path, "```svgbob\n<-- shared -->\n```\n").unwrap();
+            path
+        })
+        .collect();
+
+    let count = svgbobdoc_core::stats::render_count();
+    let results = transform_paths(paths, &TransformOptions::default());
+    assert_eq!(svgbobdoc_core::stats::render_count() - count, 1);
+    assert!(results[1..]
+        .iter()
+        .all(|r| r.output.as_ref().unwrap() == results[0].output.as_ref().unwrap()));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,107 @@
+#![cfg(feature = "mdbook")]
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn run(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mdbook-svgbob"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn chapter(path: &str, content: &str, sub_items: &str) -> String {
+    format!(
+        r#"{{"Chapter": {{"name": "x", "content": {:?}, "number": null, "sub_items": [{}], "path": "{}", "source_path": "{}", "parent_names": []}}}}"#,
+        content, sub_items, path, path
+    )
+}
+
+fn book(renderer: &str, options: &str, sections: &[String]) -> String {
+    format!(
+        r#"[{{"root": "/book", "renderer": "{}", "mdbook_version": "0.4.40", "config": {{"book": {{}}, "preprocessor": {{"svgbob": {{"options": "{}"}}}}}}}}, {{"sections": [{}], "__non_exhaustive": null}}]"#,
+        renderer,
+        options,
+        sections.join(", ")
+    )
+}
+
+#[test]
+fn supports() {
+    assert!(run(&["supports", "html"], "").status.success());
+    assert_eq!(run(&["supports", "latex"], "").status.code(), Some(1));
+}
+
+#[test]
+fn transforms_chapters() {
+    let nested = chapter("b.md", "```svgbob\n<--\n```\n", "");
+    let input = book(
+        "html",
+        "mode=img",
+        &[
+            chapter("a.md", "# A\n\n```svgbob\n-->\n```\n", &nested),
+            r#""Separator""#.to_owned(),
+            r#"{"PartTitle": "Part"}"#.to_owned(),
+        ],
+    );
+    let output = run(&[], &input);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout
+            .matches("<img src=\\\"data:image/svg+xml;base64,")
+            .count(),
+        2,
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains(r##""content":"# A\n\n"##), "{}", stdout);
+    assert!(stdout.contains(r#""Separator""#), "{}", stdout);
+    assert!(stdout.contains(r#"{"PartTitle":"Part"}"#), "{}", stdout);
+}
+
+#[test]
+fn passes_through_other_renderers() {
+    let input = book(
+        "latex",
+        "",
+        &[chapter("a.md", "```svgbob,scale=0\n-->\n```\n", "")],
+    );
+    let output = run(&[], &input);
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(r#""content":"```svgbob,scale=0\n-->\n```\n""#),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn reports_problems() {
+    let nested = chapter("b.md", "Text.\n\n~~~svgbob\n", "");
+    let input = book(
+        "html",
+        "",
+        &[chapter("a.md", "```svgbob,scale=0\n-->\n```\n", &nested)],
+    );
+    let output = run(&[], &input);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "a.md:1: option `scale` must be a positive number, not `0`\n\
+         b.md:3: unclosed code block\n"
+    );
+}
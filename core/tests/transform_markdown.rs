@@ -0,0 +1,60 @@
+use svgbobdoc_core::{transform_markdown, transform_markdown_with, Problem, TransformOptions};
+
+#[test]
+fn renders_diagrams() {
+    let output = transform_markdown("# Title\n\n```svgbob\n-->\n```\n\nText.\n").unwrap();
+    assert!(output.starts_with("# Title\n\n"), "{}", output);
+    assert!(
+        output.contains("![\\-\\-\\>](data:image/svg+xml;base64,"),
+        "{}",
+        output
+    );
+    assert!(output.ends_with("\n\nText.\n"), "{}", output);
+
+    // The other text is left as-is, including the lack of a trailing newline
+    for input in ["", "\n", "Text.", "```rust\nlet x;\n```"] {
+        assert_eq!(transform_markdown(input).unwrap(), input);
+    }
+}
+
+#[test]
+fn reports_lines() {
+    let e = transform_markdown("Text.\n\n```svgbob,scale=0\n-->\n```\n\n~~~svgbob\n").unwrap_err();
+    assert_eq!(
+        e.problems(),
+        [Problem {
+            line: Some(3),
+            message: "option `scale` must be a positive number, not `0`".to_owned(),
+        }]
+    );
+    assert_eq!(
+        e.to_string(),
+        "line 3: option `scale` must be a positive number, not `0`"
+    );
+
+    let e = transform_markdown("Text.\n\n~~~svgbob\n-->\n").unwrap_err();
+    assert_eq!(e.to_string(), "line 3: unclosed code block");
+}
+
+#[test]
+fn uses_defaults() {
+    let options = TransformOptions {
+        defaults: "mode=img".to_owned(),
+    };
+    let output = transform_markdown_with("```svgbob\n-->\n```\n", &options).unwrap();
+    assert!(output.contains("<img src=\"data:"), "{}", output);
+
+    // A code fence header takes precedence
+    let output = transform_markdown_with("```svgbob,mode=markdown\n-->\n```\n", &options).unwrap();
+    assert!(output.contains("![\\-\\-\\>](data:"), "{}", output);
+    assert!(!output.contains("<img"), "{}", output);
+
+    let options = TransformOptions {
+        defaults: "scale=0".to_owned(),
+    };
+    let e = transform_markdown_with("Text.\n", &options).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "invalid default options: option `scale` must be a positive number, not `0`"
+    );
+}
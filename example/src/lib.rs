@@ -3,7 +3,7 @@
 //! This figure is referenced by a label (`![diagram]`): ![diagram]
 //!
 #![doc = transform!(
-//! ```svgbob,[diagram]
+//! ```svgbob,[diagram],export
 //!  .----------------------.
 //!  | Another diagram here |
 //!  `----------------------'
@@ -55,6 +55,66 @@ pub mod module {
 )]
 pub fn test_function() {}
 
+#[doc = transform!(
+/// Some function with a diagram emitted as an HTML `<img>` element.
+///
+/// ```svgbob,mode=img,class=diagram
+///  .--------------------.
+///  | Lazily loaded      |
+///  `--------------------'
+/// ```
+)]
+pub fn test_function_img() {}
+
+#[doc = transform!(
+/// Some function with a diagram using a custom font.
+///
+/// ```svgbob,font="'JetBrains Mono', monospace"
+///  .--------------------.
+///  | Custom font        |
+///  `--------------------'
+/// ```
+)]
+pub fn test_function_font() {}
+
+#[doc = transform!(
+/// Some function with a captioned diagram.
+///
+/// ```svgbob,caption="The \"overall\" architecture"
+///  .--------.     .--------.
+///  | Client +---->| Server |
+///  `--------'     `--------'
+/// ```
+)]
+pub fn test_function_caption() {}
+
+#[doc = transform!(
+/// Some function with a diagram adapting to the color scheme.
+///
+/// ```svgbob,dark-variant
+///  .--------------------.
+///  | Light or dark      |
+///  `--------------------'
+/// ```
+)]
+pub fn test_function_dark_variant() {}
+
+/// Some function with a documented helper item in its body.
+pub fn test_function_nested_item() {
+    #[doc = transform!(
+    /// Some helper structure.
+    ///
+    /// ```svgbob,
+    ///  .--------------------.
+    ///  | Diagrams here      |
+    ///  `--------------------'
+    /// ```
+    )]
+    struct Helper;
+
+    let _ = Helper;
+}
+
 #[doc = transform!(
 /// Some structure.
 ///
@@ -96,3 +156,83 @@ impl TestStruct {
     )]
     pub fn test_method() {}
 }
+
+/// Some trait.
+pub trait TestTrait {
+    /// Some associated constant.
+    const TABLE: [u8; 4];
+
+    /// Some associated type.
+    type Output;
+}
+
+macro_rules! test_method {
+    () => {
+        /// Some method generated by a macro.
+        pub fn test_generated_method() {}
+    };
+}
+
+impl TestStruct {
+    test_method! {}
+}
+
+impl TestTrait for TestStruct {
+    #[doc = transform!(
+    /// Some associated constant.
+    ///
+    /// ```svgbob,
+    ///  .---+---+---+---.
+    ///  | 0 | 1 | 2 | 3 |
+    ///  `---+---+---+---'
+    /// ```
+    )]
+    const TABLE: [u8; 4] = [0, 1, 2, 3];
+
+    #[doc = transform!(
+    /// Some associated type.
+    ///
+    /// ```svgbob,
+    ///  .--------------------.
+    ///  | Diagrams here      |
+    ///  `--------------------'
+    /// ```
+    )]
+    type Output = Vec<u8>;
+}
+
+/// Some trait implemented for many types.
+pub trait TestWidth {
+    /// The width in bytes.
+    const WIDTH: usize;
+}
+
+svgbobdoc::transform_trait_impls! {
+    /// Some implementation for `{Type}`.
+    ///
+    /// ```svgbob
+    ///  .------.     .-------.
+    ///  | {Type} |--->| bytes |
+    ///  `------'     `-------'
+    /// ```
+    impl TestWidth for [u8, u16, u32] {
+        const WIDTH: usize = std::mem::size_of::<Self>();
+    }
+}
+
+#[doc = transform!(
+/// Some function with a wide diagram, which is scaled down to fit in the
+/// content column instead of overflowing.
+///
+/// ```svgbob,mode=img
+///  .----------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------.
+///  | Wide diagram                                                                                                                                                                                        |
+///  `----------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------'
+/// ```
+)]
+pub fn test_function_wide() {}
+
+/// Some function with a diagram stored in a separate file.
+///
+#[doc = svgbobdoc::transform_file!("diagrams/pipeline.bob")]
+pub fn test_function_file() {}
@@ -0,0 +1,65 @@
+//! Checks that every diagram in the documentation of this crate has an
+//! accessible name, by documenting it with rustdoc.
+use std::{fs, path::Path, process::Command};
+
+#[test]
+fn images_have_accessible_names() {
+    // A separate target directory keeps this from waiting for the lock held
+    // by the running `cargo test`
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("accessible_names");
+    let status = Command::new(env!("CARGO"))
+        .args([
+            "doc",
+            "--no-deps",
+            "--offline",
+            "-p",
+            "example",
+            "--target-dir",
+        ])
+        .arg(&target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut count = 0;
+    for path in html_files(&target_dir.join("doc/example")) {
+        let html = fs::read_to_string(&path).unwrap();
+        for img in html.split("<img ").skip(1) {
+            let img = &img[..img.find('>').unwrap()];
+            if !img.contains(r#"src="data:image/svg+xml"#) {
+                // rustdoc's own images
+                continue;
+            }
+            count += 1;
+            let name = attr(img, "aria-label").or_else(|| attr(img, "alt"));
+            assert!(
+                name.map_or(false, |name| !name.trim().is_empty()),
+                "{}: <img {}>",
+                path.display(),
+                img
+            );
+        }
+    }
+    assert!(count > 0);
+}
+
+/// Get the value of an HTML attribute, which is taken verbatim.
+fn attr<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let start = element.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let len = element[start..].find('"')?;
+    Some(&element[start..start + len])
+}
+
+fn html_files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(html_files(&path));
+        } else if path.extension().map_or(false, |ext| ext == "html") {
+            files.push(path);
+        }
+    }
+    files
+}
@@ -0,0 +1,36 @@
+//! Checks that the files read by `file=...` are tracked. This is a separate
+//! test crate so that `transform_file!` doesn't track the file instead.
+
+const PIPELINE: &str = svgbobdoc::transform!(
+    r#"```svgbob,file="diagrams/pipeline.bob"
+```"#
+);
+
+pub trait Diagram {}
+
+svgbobdoc::transform_trait_impls! {
+    /// ```svgbob,file="diagrams/stages.bob"
+    /// ```
+    impl Diagram for [u8] {}
+}
+
+#[test]
+fn tracks_file() {
+    assert!(
+        PIPELINE.ends_with("\n\n[svgbobdoc-tracked-file-0]: example \"\"\n"),
+        "{}",
+        PIPELINE
+    );
+}
+
+#[test]
+fn dep_info_lists_files() {
+    // rustc writes the files this test crate depends on next to its
+    // executable
+    let exe = std::env::current_exe().unwrap();
+    let dep_info = std::fs::read_to_string(exe.with_extension("d")).unwrap();
+    for file in ["diagrams/pipeline.bob", "diagrams/stages.bob"] {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(file);
+        assert!(dep_info.contains(path.to_str().unwrap()), "{}", dep_info);
+    }
+}
@@ -0,0 +1,75 @@
+//! Checks the options specified as arguments of `transform!`.
+
+#[test]
+fn string_literal() {
+    let output = svgbobdoc::transform!(mode = "img", "```svgbob\n-->\n```");
+    assert!(output.starts_with("<img src=\"data:"), "{}", output);
+
+    // A code fence header takes precedence
+    let output = svgbobdoc::transform!(mode = "img", "```svgbob,mode=markdown\n-->\n```");
+    assert!(output.starts_with("![\\-\\-\\>](data:"), "{}", output);
+}
+
+#[test]
+fn doc_attrs() {
+    #[doc = svgbobdoc::transform!(
+        center,
+        keep_source = "before",
+        alt = "arrow",
+        size_limit = 100,
+        /// ```svgbob
+        /// -->
+        /// ```
+    )]
+    struct Documented;
+    let _ = Documented;
+
+    let output = svgbobdoc::transform!(
+        center,
+        keep_source = "before",
+        alt = "arrow",
+        size_limit = 100,
+        /// ```svgbob
+        /// -->
+        /// ```
+    );
+    assert!(
+        output.contains(" ```text\n -->\n ```\n\n <div style=\"text-align:center\"><img "),
+        "{}",
+        output
+    );
+    assert!(output.contains(" alt=\"arrow\" "), "{}", output);
+}
+
+#[test]
+fn shared_labels() {
+    let definition = svgbobdoc::transform!("```svgbob,[shared arrow],export\n-->\n```");
+    let output = svgbobdoc::transform!(shared_labels, "See ![shared arrow].");
+    assert!(
+        output.ends_with(&format!("\n\n{}\n", definition.trim())),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn shared_label_overrides() {
+    svgbobdoc::transform!("```svgbob,[shared box],export\n+--+\n|  |\n+--+\n```");
+    let output = svgbobdoc::transform!(shared_labels, "See ![shared box]{scale=0.6, class=small}.");
+    assert!(output.starts_with("See <img src=\"data:"), "{}", output);
+    assert!(output.contains(" class=\"svgbob small\">."), "{}", output);
+}
+
+#[test]
+fn number_figures() {
+    let figure = svgbobdoc::transform!(number_figures, "```svgbob,caption=\"An arrow\"\n-->\n```");
+    assert!(
+        figure.contains("<figure id=\"figure-1\">") && figure.contains("Figure 1: An arrow"),
+        "{}",
+        figure
+    );
+    assert_eq!(
+        svgbobdoc::figure_list!(),
+        "- [Figure 1: An arrow](#figure-1)\n"
+    );
+}
@@ -0,0 +1,569 @@
+//! Checks that the emitted diagrams don't corrupt the structure of the
+//! surrounding Markdown document even if the diagrams contain texts that are
+//! meaningful in Markdown or HTML.
+
+/// Assert that `output` contains the diagram as an isolated block between the
+/// paragraphs `before` and `after *emphasis*`, and that none of the diagram's
+/// tags leak into the document.
+#[track_caller]
+fn check_isolated(output: &str, element_prefix: &str) {
+    let lines: Vec<&str> = output.lines().collect();
+    let i = lines
+        .iter()
+        .position(|line| line.starts_with(element_prefix))
+        .unwrap_or_else(|| panic!("no line starting with `{}`:\n{}", element_prefix, output));
+
+    assert_eq!(lines.iter().filter(|l| l.contains("data:")).count(), 1);
+    assert_eq!(lines[..i].last(), Some(&""), "{}", output);
+    assert_eq!(lines.get(i + 1), Some(&""), "{}", output);
+    assert!(lines[..i].contains(&"before"), "{}", output);
+    assert!(lines[i..].contains(&"after *emphasis*"), "{}", output);
+
+    assert!(!output.contains("<b>"), "{}", output);
+}
+
+#[test]
+fn markdown_image() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "![");
+    assert!(
+        output.contains(r"\| \`\`\`svgbob \| \| \*not emphasis\* \| \| \<b\>not bold\<\/b\> \|")
+    );
+}
+
+#[test]
+fn img_element() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,mode=img,class=diagram
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "<img ");
+    assert!(output.contains(r#" class="svgbob diagram">"#), "{}", output);
+    assert!(output.contains(r#" alt=".-----------------. | ```svgbob | | *not emphasis* | | &lt;b&gt;not bold&lt;/b&gt; | '-----------------'""#), "{}", output);
+}
+
+#[test]
+fn explicit_alt() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,alt="[a *state* machine](x)"
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "![");
+    assert!(output.contains(r"![\[a \*state\* machine\]\(x\)](data:"));
+}
+
+#[test]
+fn caption() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,caption="A `caption` with <tags>"
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "<figure>");
+    assert!(output.contains("<figcaption>A `caption` with &lt;tags&gt;</figcaption>"));
+}
+
+#[test]
+fn center() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,center
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, r#"<div style="text-align:center"><img "#);
+    assert!(output.contains("></div>\n"), "{}", output);
+
+    // With a caption, in a list item
+    let output = svgbobdoc::transform!(
+        r#"before
+
+ - item
+
+   ```svgbob,center,caption=Caption
+   +--+
+   ```
+
+   continued
+
+after *emphasis*"#
+    );
+    check_isolated(output, r#"   <div style="text-align:center"><figure><img "#);
+    assert!(
+        output.contains("<figcaption>Caption</figcaption></figure></div>\n"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn scroll() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,scroll
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, r#"<div style="overflow-x:auto"><img "#);
+    assert!(output.contains(r#" style="max-width:none""#), "{}", output);
+    assert!(!output.contains("max-width:100%"), "{}", output);
+
+    // With centering and a caption
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,scroll,center,caption=Caption
++--+
+````
+after *emphasis*"#
+    );
+    check_isolated(
+        output,
+        r#"<div style="overflow-x:auto;text-align:center"><figure><img "#,
+    );
+    assert!(
+        output.contains("<figcaption>Caption</figcaption></figure></div>\n"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn size() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,width=20em
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "<img ");
+    assert!(
+        output.contains(r#" style="width:20em;max-width:100%;height:auto""#),
+        "{}",
+        output
+    );
+
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,mode=reference,width=50%,height=10ex
++--+
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "<img ");
+    assert!(
+        output.contains(r#" style="width:50%;height:10ex""#),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn link() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,link
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "[![");
+    let line = output.lines().find(|l| l.starts_with("[![")).unwrap();
+    let (image, target) = line.split_once("](data:").unwrap();
+    let (src, target) = target.split_once(")](").unwrap();
+    assert!(!image.contains("]("), "{}", output);
+    assert_eq!(target, format!("data:{})", src), "{}", output);
+
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,link,mode=img
++--+
+````
+after *emphasis*"#
+    );
+    check_isolated(output, r#"<a href="data:"#);
+    assert!(output.contains(r#"" target="_blank"><img "#), "{}", output);
+    assert!(output.contains("></a>\n"), "{}", output);
+}
+
+#[test]
+fn inline_svg() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,mode=svg
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    let lines: Vec<&str> = output.lines().collect();
+    let i = lines
+        .iter()
+        .position(|line| line.starts_with("<div><svg "))
+        .unwrap_or_else(|| panic!("{}", output));
+    assert_eq!(lines[..i].last(), Some(&""), "{}", output);
+    assert_eq!(lines.get(i + 1), Some(&""), "{}", output);
+    assert!(lines[i].ends_with("</svg></div>"), "{}", output);
+    assert!(lines[..i].contains(&"before"), "{}", output);
+    assert!(lines[i..].contains(&"after *emphasis*"), "{}", output);
+    assert!(!output.contains("<b>"), "{}", output);
+    assert!(!output.contains("data:"), "{}", output);
+
+    let svg = lines[i];
+    assert!(svg.contains(r#" role="img""#), "{}", svg);
+    assert!(
+        svg.contains("<title>.-----------------.</title>"),
+        "{}",
+        svg
+    );
+    assert!(
+        svg.contains("&#10;| &lt;b&gt;not bold&lt;/b&gt; |&#10;"),
+        "{}",
+        svg
+    );
+
+    // A caption labels the image instead of `<title>`
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,mode=svg,caption=Caption
++--+
+````
+after *emphasis*"#
+    );
+    let svg = output
+        .lines()
+        .find(|line| line.starts_with("<div><figure><svg "))
+        .unwrap_or_else(|| panic!("{}", output));
+    assert!(!svg.contains("<title>"), "{}", svg);
+    let id = svg
+        .split(r#" aria-labelledby=""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap_or_else(|| panic!("{}", svg));
+    assert!(
+        svg.ends_with(&format!(
+            r#"</svg><figcaption id="{}">Caption</figcaption></figure></div>"#,
+            id
+        )),
+        "{}",
+        svg
+    );
+}
+
+#[test]
+fn dark_variant() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,dark-variant
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    // The two variants share the line
+    let output = output.replacen("data:", "", 1);
+    check_isolated(&output, "<picture>");
+}
+
+#[test]
+fn labeled() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,[figure],export
+.-----------------.
+| ```svgbob       |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "[figure]: data:");
+}
+
+#[test]
+fn labeled_multiple_references() {
+    let output = svgbobdoc::transform!(
+        r#"Before the definition: ![figure]
+
+ - In a list: ![The figure][figure]
+
+```svgbob,[figure]
++--+
+```
+
+After the definition: ![figure]"#
+    );
+    // The diagram is rendered once, and the references are left intact
+    assert_eq!(output.matches("data:").count(), 1, "{}", output);
+    assert!(output.contains("\n[figure]: data:"), "{}", output);
+    for line in [
+        "Before the definition: ![figure]\n",
+        " - In a list: ![The figure][figure]\n",
+        "After the definition: ![figure]",
+    ] {
+        assert!(output.contains(line), "{}", output);
+    }
+}
+
+#[test]
+fn doc_attributes() {
+    let output = svgbobdoc::transform!(
+        #[doc = "before"]
+        #[doc = "````svgbob,mode=img"]
+        #[doc = ".-----------------."]
+        #[doc = "| ```svgbob       |"]
+        #[doc = "| *not emphasis*  |"]
+        #[doc = "| <b>not bold</b> |"]
+        #[doc = "'-----------------'"]
+        #[doc = "````"]
+        #[doc = "after *emphasis*"]
+    );
+    check_isolated(output, "<img ");
+}
+
+#[test]
+fn block_doc_comment() {
+    // rustdoc removes the stars only from doc comments, not from the
+    // `#[doc = ...]` attribute replacing them
+    let output = svgbobdoc::transform!(
+        /**
+         * before
+         *
+         * ```svgbob,mode=img
+         * .-----------------.
+         * | *not emphasis*  |
+         * '-----------------'
+         * ```
+         *
+         * after *emphasis*
+         */
+    );
+    assert!(output.starts_with("\n before\n\n"), "{}", output);
+    assert!(output.contains("\n\n <img "), "{}", output);
+    assert!(output.contains(">\n\n\n after *emphasis*\n"), "{}", output);
+    assert!(!output.contains('`'), "{}", output);
+}
+
+#[test]
+fn list_item() {
+    let output = svgbobdoc::transform!(
+        r#" - item
+
+   ````svgbob,mode=img
+   .-----------.
+   | ```svgbob |
+   '-----------'
+   ````
+   continued"#
+    );
+    // The element and the following paragraph must stay in the list item
+    assert!(output.contains("\n   <img "), "{}", output);
+    assert!(output.contains("\n\n   continued"), "{}", output);
+}
+
+#[test]
+fn blockquote() {
+    let output = svgbobdoc::transform!(
+        /// > quoted
+        /// > ````svgbob,mode=img
+        /// > .-----------.
+        /// > | ```svgbob |
+        /// > '-----------'
+        /// > ````
+        /// > continued
+    );
+    // The element and the following paragraph must stay in the blockquote
+    assert!(output.contains("\n >\n > <img "), "{}", output);
+    assert!(output.contains("\n >\n > continued"), "{}", output);
+    assert!(
+        output.lines().all(|line| line.starts_with(" >")),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn passthrough() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,passthrough
+| ```svgbob       |
+````
+after *emphasis*"#
+    );
+    assert!(
+        output.contains("\n````text\n| ```svgbob       |\n````\n"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn percent_encoding() {
+    let output = svgbobdoc::transform!(
+        r#"before
+````svgbob,encoding=percent,alt=""
+.-----------------.
+| ```svgbob (x)   |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````
+after *emphasis*"#
+    );
+    check_isolated(output, "![](data:image/svg+xml;charset=utf-8,");
+
+    // The image is the same as the base64-encoded one
+    let base64_output = svgbobdoc::transform!(
+        r#"
+````svgbob,encoding=base64,alt=""
+.-----------------.
+| ```svgbob (x)   |
+| *not emphasis*  |
+| <b>not bold</b> |
+'-----------------'
+````"#
+    );
+    let uri = output.lines().find(|l| l.starts_with("![](")).unwrap();
+    let encoded = &uri[uri.find(',').unwrap() + 1..uri.len() - 1];
+    assert!(!encoded.contains([' ', '(', ')', '\n']), "{}", encoded);
+    let mut decoded = Vec::new();
+    let mut bytes = encoded.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex: String = bytes.by_ref().take(2).map(char::from).collect();
+            decoded.push(u8::from_str_radix(&hex, 16).unwrap());
+        } else {
+            decoded.push(b);
+        }
+    }
+    let uri = base64_output
+        .lines()
+        .find(|l| l.starts_with("![]("))
+        .unwrap();
+    let base64_encoded = &uri[uri.find(',').unwrap() + 1..uri.len() - 1];
+    assert_eq!(
+        decoded,
+        base64::Engine::decode(&base64::engine::general_purpose::STANDARD, base64_encoded).unwrap()
+    );
+}
+
+#[test]
+fn accessible_name() {
+    let output = svgbobdoc::transform!("```svgbob,mode=img\n+--+\n```");
+    assert!(
+        output.contains(r#" role="img" aria-label="diagram""#),
+        "{}",
+        output
+    );
+
+    let output = svgbobdoc::transform!(
+        r#"```svgbob,mode=img,alt="A <box>"
++--+
+```"#
+    );
+    // The alternative text names the image by itself
+    assert!(
+        output.contains(r#" alt="A &lt;box&gt;""#) && output.contains(r#" role="img" class"#),
+        "{}",
+        output
+    );
+    assert!(!output.contains("aria-label"), "{}", output);
+
+    let output = svgbobdoc::transform!(
+        r#"```svgbob,caption="A box"
++--+
+```"#
+    );
+    assert!(
+        output.contains(r#" role="img" aria-label="A box""#),
+        "{}",
+        output
+    );
+
+    // Opted out
+    let output = svgbobdoc::transform!(
+        r#"```svgbob,caption="A box",aria=false
++--+
+```"#
+    );
+    assert!(!output.contains("aria-label"), "{}", output);
+
+    // Decorative
+    let output = svgbobdoc::transform!(
+        r#"```svgbob,mode=img,alt=""
++--+
+```"#
+    );
+    assert!(!output.contains("aria-label"), "{}", output);
+}
+
+#[test]
+fn show_source() {
+    let output = svgbobdoc::transform!(
+        " - item\n\n   ```svgbob,show-source\n   +--+  \n\n     | <a> & |\n   ```\n   continued"
+    );
+    assert!(
+        output.contains(
+            "\n\n   <details><summary>Diagram source</summary>\
+             <pre>+--+  &#10;&#10;  | &lt;a&gt; &amp; |</pre></details>\n\n   continued"
+        ),
+        "{}",
+        output
+    );
+}
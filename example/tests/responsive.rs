@@ -0,0 +1,180 @@
+//! Checks the size and the style of the images, including that they can be
+//! scaled down to fit in the content column.
+use base64::{engine::general_purpose, Engine as _};
+
+/// Extract the SVG image from the output of an HTML `<img>` element.
+fn decode_svg(output: &str) -> String {
+    let start = output
+        .find(r#"src="data:image/svg+xml;base64,"#)
+        .expect(output)
+        + 31;
+    let len = output[start..].find('"').unwrap();
+    let svg_code = general_purpose::STANDARD
+        .decode(&output[start..start + len])
+        .unwrap();
+    String::from_utf8(svg_code).unwrap()
+}
+
+/// Get the value of an attribute of the root `<svg>` element.
+fn root_attr<'a>(svg_code: &'a str, name: &str) -> &'a str {
+    let root = &svg_code[svg_code.find("<svg").unwrap()..];
+    let root = &root[..root.find('>').unwrap()];
+    let pattern = format!(" {}=\"", name);
+    let start = root.find(&pattern).expect(root) + pattern.len();
+    &root[start..start + root[start..].find('"').unwrap()]
+}
+
+#[test]
+fn wide_diagram() {
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img
+.------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------.
+| Wide diagram                                                                                                                                                                                         |
+`------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------------'
+```"#
+    );
+
+    // Shrinks to the column width, but isn't enlarged beyond its natural size
+    assert!(
+        output.contains(r#" style="max-width:100%;height:auto""#),
+        "{}",
+        output
+    );
+
+    // The `viewBox` has the same aspect ratio as the image
+    let svg_code = decode_svg(output);
+    let width: f32 = root_attr(&svg_code, "width").parse().unwrap();
+    let height: f32 = root_attr(&svg_code, "height").parse().unwrap();
+    assert_eq!(
+        root_attr(&svg_code, "viewBox"),
+        format!("0 0 {} {}", width, height)
+    );
+    assert!(width > 1500.0, "{}", width);
+}
+
+#[test]
+fn scale() {
+    let normal = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img
++--+ text
+```"#
+    ));
+    let scaled = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img,scale=1.5
++--+ text
+```"#
+    ));
+
+    for name in ["width", "height"] {
+        let normal: f32 = root_attr(&normal, name).parse().unwrap();
+        let scaled: f32 = root_attr(&scaled, name).parse().unwrap();
+        assert_eq!(scaled, normal * 1.5, "{}", name);
+    }
+
+    // The texts are laid out in the scaled cells
+    let text_length = |svg_code: &str| -> f32 {
+        let start = svg_code.find(r#"textLength=""#).expect(svg_code) + 12;
+        let len = svg_code[start..].find('"').unwrap();
+        svg_code[start..start + len].parse().unwrap()
+    };
+    assert_eq!(text_length(&scaled), text_length(&normal) * 1.5);
+}
+
+#[test]
+fn font_size() {
+    let svg_code = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img,font-size=26
++--+ text
+```"#
+    ));
+    assert!(
+        svg_code.contains("font-size:26px") || svg_code.contains("font-size: 26px"),
+        "{}",
+        svg_code
+    );
+
+    // The cells are twice as large as the default (8 by 16 pixels)
+    assert_eq!(root_attr(&svg_code, "height"), "32");
+    let start = svg_code.find(r#"textLength=""#).expect(&svg_code) + 12;
+    let len = svg_code[start..].find('"').unwrap();
+    assert_eq!(&svg_code[start..start + len], "64");
+}
+
+#[test]
+fn stroke_width() {
+    let stroke_widths = |svg_code: &str| -> Vec<String> {
+        svg_code
+            .match_indices("stroke-width:")
+            .map(|(i, m)| {
+                let value = &svg_code[i + m.len()..];
+                value[..value.find(';').unwrap()].trim().to_owned()
+            })
+            .collect()
+    };
+    let normal = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img
++--+
+```"#
+    ));
+    let thick = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img,stroke-width=1.75
++--+
+```"#
+    ));
+    assert_eq!(stroke_widths(&normal)[0], "1");
+    assert_eq!(stroke_widths(&thick)[0], "1.75");
+}
+
+#[test]
+fn legend() {
+    let diagram = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img
++------+
+|      |
++------+
+```"#
+    ));
+    let legend = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img
+* key
+```"#
+    ));
+    let combined = decode_svg(svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img,legend,legend-gap=10,legend-align=bottom
++------+
+|      |
++------+
+#legend
+* key
+```"#
+    ));
+
+    let size = |svg_code: &str, name| -> f32 { root_attr(svg_code, name).parse().unwrap() };
+    assert_eq!(
+        size(&combined, "width"),
+        size(&diagram, "width") + 10.0 + size(&legend, "width")
+    );
+    assert_eq!(size(&combined, "height"), size(&diagram, "height"));
+
+    // Each image is nested as is
+    assert_eq!(combined.matches("<svg").count(), 3, "{}", combined);
+    let legend_y = size(&diagram, "height") - size(&legend, "height");
+    assert!(
+        combined.contains(&format!(
+            " x=\"{}\" y=\"{}\"",
+            size(&diagram, "width") + 10.0,
+            legend_y
+        )),
+        "{}",
+        combined
+    );
+}
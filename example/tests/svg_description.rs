@@ -0,0 +1,152 @@
+//! Checks the `<title>` and `<desc>` elements embedded in SVG images.
+use base64::{engine::general_purpose, Engine as _};
+
+/// Extract the SVG image from the output of a Markdown image.
+fn decode_svg(output: &str) -> String {
+    let start = output.find("](data:image/svg+xml;base64,").expect(output) + 28;
+    // The URL may be followed by a title
+    let len = output[start..].find([' ', ')']).unwrap();
+    let svg_code = general_purpose::STANDARD
+        .decode(&output[start..start + len])
+        .unwrap();
+    String::from_utf8(svg_code).unwrap()
+}
+
+/// Get the children of the root `<svg>` element up to the first child not
+/// created by the macro.
+fn leading_children(svg_code: &str) -> &str {
+    let root = svg_code.find("<svg").unwrap();
+    let start = root + svg_code[root..].find('>').unwrap() + 1;
+    let end = [
+        svg_code[start..].find("</desc>").map(|i| i + 7),
+        svg_code[start..].find("</title>").map(|i| i + 8),
+    ]
+    .iter()
+    .flatten()
+    .max()
+    .copied()
+    .unwrap_or(0);
+    &svg_code[start..start + end]
+}
+
+#[test]
+fn title_and_desc() {
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob,title="A <state> machine",desc
++----+  "go"  +----+
+| A  +------->| B  |
++----+        +----+
+```"#
+    );
+    let svg_code = decode_svg(output);
+    assert_eq!(
+        leading_children(&svg_code),
+        "<title>A &lt;state&gt; machine</title>\
+         <desc>+----+  &quot;go&quot;  +----+\n\
+         | A  +-------&gt;| B  |\n\
+         +----+        +----+</desc>",
+    );
+}
+
+#[test]
+fn title_from_alt() {
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob,alt="Boxes & arrows"
++----+
+```"#
+    );
+    let svg_code = decode_svg(output);
+    assert_eq!(
+        leading_children(&svg_code),
+        "<title>Boxes &amp; arrows</title>"
+    );
+}
+
+#[test]
+fn skipped() {
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob,alt="Boxes",title="",desc=false
++----+
+```"#
+    );
+    let svg_code = decode_svg(output);
+    assert_eq!(leading_children(&svg_code), "");
+    assert!(!svg_code.contains("<title>"));
+    assert!(!svg_code.contains("<desc>"));
+}
+
+#[test]
+fn none_by_default() {
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob
++----+
+```"#
+    );
+    let svg_code = decode_svg(output);
+    assert!(!svg_code.contains("<title>"));
+    assert!(!svg_code.contains("<desc>"));
+}
+
+#[test]
+fn exact_source() {
+    // The last row is shorter than the first, and the second row's trailing
+    // whitespace extends the diagram
+    let output = svgbobdoc::transform!("```svgbob,desc\n+------+\n| A    |    \n\n```");
+    let svg_code = decode_svg(output);
+    assert!(
+        leading_children(&svg_code).contains("<desc>+------+\n| A    |    \n</desc>"),
+        "{}",
+        svg_code
+    );
+
+    // 12 columns by 3 rows
+    let root = &svg_code[..svg_code.find('>').unwrap()];
+    let size = |name: &str| -> f32 {
+        let start = root.find(&format!(" {}=\"", name)).expect(root) + name.len() + 3;
+        root[start..start + root[start..].find('"').unwrap()]
+            .parse()
+            .unwrap()
+    };
+    assert!(size("width") >= 12.0 * 8.0, "{}", root);
+    assert_eq!(size("height"), 3.0 * 16.0, "{}", root);
+}
+
+#[test]
+fn tooltip() {
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob,title="Figure 3: \"boot\" \\ sequence"
+-->
+```"#
+    );
+    assert!(
+        output.contains(r#"= "Figure 3\: \"boot\" \\ sequence")"#),
+        "{}",
+        output
+    );
+
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob,mode=img,title="Figure 3: \"boot\" <sequence>"
+-->
+```"#
+    );
+    assert!(
+        output.contains(r#" title="Figure 3: &quot;boot&quot; &lt;sequence&gt;" "#),
+        "{}",
+        output
+    );
+
+    // Without a title
+    let output = svgbobdoc::transform!(
+        r#"
+```svgbob,alt=Arrow
+-->
+```"#
+    );
+    assert!(!output.contains(" \""), "{}", output);
+}
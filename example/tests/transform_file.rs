@@ -0,0 +1,76 @@
+//! Checks `transform_file!`.
+
+const PIPELINE: &str = svgbobdoc::transform_file!("diagrams/pipeline.bob");
+const PIPELINE_IMG: &str =
+    svgbobdoc::transform_file!("diagrams/pipeline.bob", "mode=img,[pipeline]");
+
+#[test]
+fn markdown_image() {
+    assert!(
+        PIPELINE.starts_with(r"![\.\-\-\-\-\-\-\-\-\-\. \.\-\-"),
+        "{}",
+        PIPELINE
+    );
+    assert!(PIPELINE.contains("](data:image/svg+xml;base64,"));
+}
+
+#[test]
+fn options() {
+    // A link label takes precedence over `mode`
+    assert!(
+        PIPELINE_IMG.starts_with("[pipeline]: data:image/svg+xml;base64,"),
+        "{}",
+        PIPELINE_IMG
+    );
+}
+
+#[test]
+fn tracks_file() {
+    // The file is tracked by an unused link reference definition, which
+    // doesn't include its content
+    assert!(
+        PIPELINE.ends_with(")\n\n[svgbobdoc-tracked-file-0]: example \"\"\n"),
+        "{}",
+        PIPELINE
+    );
+}
+
+#[test]
+fn dep_info_lists_file() {
+    // rustc writes the files this test crate depends on next to its
+    // executable
+    let exe = std::env::current_exe().unwrap();
+    let dep_info = std::fs::read_to_string(exe.with_extension("d")).unwrap();
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("diagrams/pipeline.bob");
+    assert!(dep_info.contains(path.to_str().unwrap()), "{}", dep_info);
+}
+
+#[test]
+fn file_option() {
+    let output = svgbobdoc::transform!(
+        r#"See ![pipeline].
+
+```svgbob,[pipeline],file="diagrams/pipeline.bob"
+```"#
+    );
+    assert!(output.starts_with("See ![pipeline].\n\n"), "{}", output);
+    // Rendered like the file's content
+    let definition = PIPELINE_IMG.lines().next().unwrap();
+    assert!(
+        definition.starts_with("[pipeline]: data:image/svg+xml;base64,"),
+        "{}",
+        PIPELINE_IMG
+    );
+    assert!(
+        output.contains(&format!("\n{}\n", definition)),
+        "{}",
+        output
+    );
+
+    // The file is tracked
+    assert!(
+        output.ends_with("\n\n[svgbobdoc-tracked-file-0]: example \"\"\n"),
+        "{}",
+        output
+    );
+}
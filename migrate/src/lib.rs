@@ -0,0 +1,248 @@
+//! Rewrites the legacy `#[svgbobdoc::transform]` attribute, which was removed
+//! in svgbobdoc 0.3, to the `#[doc = svgbobdoc::transform!(...)]` style.
+//!
+//! Each contiguous run of doc comments containing a `svgbob` code block in an
+//! item marked with the attribute (including the item's fields and variants)
+//! is wrapped with `#[doc = svgbobdoc::transform!(...)]` (or
+//! `#![doc = ...]` for inner doc comments, which keep documenting the
+//! enclosing item), and the attribute is removed. Everything else is
+//! preserved verbatim.
+#![warn(rust_2018_idioms)]
+use proc_macro2::LineColumn;
+use syn::{
+    spanned::Spanned,
+    visit::{self, Visit},
+    AttrStyle, Attribute, Expr, ExprLit, Fields, Item, Lit, Meta,
+};
+
+/// Rewrite the legacy attribute usage in a Rust source file. Returns the
+/// source unchanged if it doesn't use the attribute.
+pub fn migrate(source: &str) -> syn::Result<String> {
+    let file = syn::parse_file(source)?;
+
+    let mut migrator = Migrator {
+        source,
+        line_starts: std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect(),
+        edits: Vec::new(),
+    };
+    migrator.visit_file(&file);
+
+    // Apply the edits from the end so that the earlier offsets stay valid
+    let mut edits = migrator.edits;
+    edits.sort_by_key(|edit| (edit.start, edit.end));
+    let mut output = source.to_owned();
+    for edit in edits.iter().rev() {
+        output.replace_range(edit.start..edit.end, &edit.replacement);
+    }
+    Ok(output)
+}
+
+/// Replace `source[start..end]` with `replacement`.
+struct Edit {
+    start: usize,
+    end: usize,
+    replacement: String,
+}
+
+struct Migrator<'a> {
+    source: &'a str,
+    /// The byte offset of each line's start.
+    line_starts: Vec<usize>,
+    edits: Vec<Edit>,
+}
+
+impl Migrator<'_> {
+    /// Convert a line-column position to a byte offset.
+    fn offset(&self, lc: LineColumn) -> usize {
+        let line_start = self.line_starts[lc.line - 1];
+        self.source[line_start..]
+            .char_indices()
+            .nth(lc.column)
+            .map_or(self.source.len(), |(i, _)| line_start + i)
+    }
+
+    /// Get the indentation preceding the specified offset if nothing else
+    /// precedes it on the same line.
+    fn indent_before(&self, offset: usize) -> Option<&str> {
+        let line_start = self.source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let indent = &self.source[line_start..offset];
+        indent.trim().is_empty().then_some(indent)
+    }
+
+    /// Process the attributes of an item, a field, or a variant.
+    fn process_attrs(&mut self, attrs: &[Attribute]) {
+        let mut i = 0;
+        while i < attrs.len() {
+            // Find a run of doc comments of the same style
+            let inner = is_inner(&attrs[i]);
+            let run_len = attrs[i..]
+                .iter()
+                .take_while(|attr| is_inner(attr) == inner && doc_comment_text(attr).is_some())
+                .count();
+            if run_len == 0 {
+                i += 1;
+                continue;
+            }
+            let run = &attrs[i..i + run_len];
+            i += run_len;
+
+            let has_diagram = run
+                .iter()
+                .filter_map(doc_comment_text)
+                .any(|text| text.lines().any(is_svgbob_fence));
+            if !has_diagram {
+                continue;
+            }
+
+            let start = self.offset(run[0].span().start());
+            let end = self.offset(run[run_len - 1].span().end());
+            let indent = self.indent_before(start).unwrap_or_default().to_owned();
+            let bang = if inner { "!" } else { "" };
+            self.edits.push(Edit {
+                start,
+                end: start,
+                replacement: format!("#{}[doc = svgbobdoc::transform!(\n{}", bang, indent),
+            });
+            self.edits.push(Edit {
+                start: end,
+                end,
+                replacement: format!("\n{})]", indent),
+            });
+        }
+    }
+
+    /// Remove a legacy attribute, including its line if nothing else is on
+    /// it.
+    fn remove_attr(&mut self, attr: &Attribute) {
+        let mut start = self.offset(attr.span().start());
+        let mut end = self.offset(attr.span().end());
+
+        let rest = &self.source[end..];
+        let rest_of_line = &rest[..rest.find('\n').map_or(rest.len(), |i| i + 1)];
+        if let (Some(indent), true) = (self.indent_before(start), rest_of_line.trim().is_empty()) {
+            start -= indent.len();
+            end += rest_of_line.len();
+        } else {
+            end += rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        }
+
+        self.edits.push(Edit {
+            start,
+            end,
+            replacement: String::new(),
+        });
+    }
+
+    /// Process an item's attributes if it's marked with the legacy attribute.
+    fn process_item(
+        &mut self,
+        attrs: &[Attribute],
+        fields: &[&Fields],
+        variant_attrs: &[&[Attribute]],
+    ) {
+        let Some(legacy_attr) = attrs.iter().find(|attr| is_legacy_attr(attr)) else {
+            return;
+        };
+        self.remove_attr(legacy_attr);
+        self.process_attrs(attrs);
+        for fields in fields {
+            for field in fields.iter() {
+                self.process_attrs(&field.attrs);
+            }
+        }
+        for attrs in variant_attrs {
+            self.process_attrs(attrs);
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Migrator<'_> {
+    fn visit_item(&mut self, item: &'ast Item) {
+        match item {
+            Item::Struct(item) => self.process_item(&item.attrs, &[&item.fields], &[]),
+            Item::Enum(item) => {
+                let fields: Vec<_> = item.variants.iter().map(|v| &v.fields).collect();
+                let variant_attrs: Vec<_> = item.variants.iter().map(|v| &v.attrs[..]).collect();
+                self.process_item(&item.attrs, &fields, &variant_attrs);
+            }
+            Item::Union(item) => {
+                self.process_item(&item.attrs, &[&Fields::Named(item.fields.clone())], &[])
+            }
+            Item::Const(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::Fn(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::Impl(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::Macro(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::Mod(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::Static(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::Trait(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::TraitAlias(item) => self.process_item(&item.attrs, &[], &[]),
+            Item::Type(item) => self.process_item(&item.attrs, &[], &[]),
+            _ => {}
+        }
+        visit::visit_item(self, item);
+    }
+
+    fn visit_impl_item(&mut self, item: &'ast syn::ImplItem) {
+        match item {
+            syn::ImplItem::Const(item) => self.process_item(&item.attrs, &[], &[]),
+            syn::ImplItem::Fn(item) => self.process_item(&item.attrs, &[], &[]),
+            syn::ImplItem::Type(item) => self.process_item(&item.attrs, &[], &[]),
+            syn::ImplItem::Macro(item) => self.process_item(&item.attrs, &[], &[]),
+            _ => {}
+        }
+        visit::visit_impl_item(self, item);
+    }
+
+    fn visit_trait_item(&mut self, item: &'ast syn::TraitItem) {
+        match item {
+            syn::TraitItem::Const(item) => self.process_item(&item.attrs, &[], &[]),
+            syn::TraitItem::Fn(item) => self.process_item(&item.attrs, &[], &[]),
+            syn::TraitItem::Type(item) => self.process_item(&item.attrs, &[], &[]),
+            syn::TraitItem::Macro(item) => self.process_item(&item.attrs, &[], &[]),
+            _ => {}
+        }
+        visit::visit_trait_item(self, item);
+    }
+}
+
+/// Check if an attribute is `#[svgbobdoc::transform]`.
+fn is_legacy_attr(attr: &Attribute) -> bool {
+    let Meta::Path(path) = &attr.meta else {
+        return false;
+    };
+    let segments: Vec<_> = path.segments.iter().map(|s| s.ident.to_string()).collect();
+    segments == ["svgbobdoc", "transform"]
+}
+
+/// Check if an attribute is an inner attribute (`#![...]` or `//!`).
+fn is_inner(attr: &Attribute) -> bool {
+    matches!(attr.style, AttrStyle::Inner(_))
+}
+
+/// Get the text of a doc comment.
+fn doc_comment_text(attr: &Attribute) -> Option<String> {
+    if !attr.path().is_ident("doc") {
+        return None;
+    }
+    match &attr.meta {
+        Meta::NameValue(nv) => match &nv.value {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(s), ..
+            }) => Some(s.value()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Check if a line opens a `svgbob` code block.
+fn is_svgbob_fence(line: &str) -> bool {
+    let line = line.trim_start();
+    let Some(fence_ch) = line.chars().next().filter(|c| matches!(c, '`' | '~')) else {
+        return false;
+    };
+    let rest = line.trim_start_matches(fence_ch);
+    line.len() - rest.len() >= 3 && rest.trim_start().starts_with("svgbob")
+}
@@ -0,0 +1,64 @@
+//! Rewrites the legacy `#[svgbobdoc::transform]` attribute in the specified
+//! Rust source files.
+//!
+//! ```text
+//! svgbobdoc-migrate [--check] FILE...
+//! ```
+//!
+//! With `--check`, the files aren't modified, and the exit status is `1` if
+//! any of them would be rewritten.
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args_os().skip(1) {
+        if arg == "--check" {
+            check = true;
+        } else {
+            paths.push(std::path::PathBuf::from(arg));
+        }
+    }
+
+    if paths.is_empty() {
+        eprintln!("usage: svgbobdoc-migrate [--check] FILE...");
+        return ExitCode::from(2);
+    }
+
+    let mut failed = false;
+    let mut changed = false;
+    for path in paths {
+        let result = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| {
+                let output = svgbobdoc_migrate::migrate(&source).map_err(|e| {
+                    let start = e.span().start();
+                    format!("{}:{}: {}", start.line, start.column + 1, e)
+                })?;
+                if output == source {
+                    return Ok(());
+                }
+                changed = true;
+                if check {
+                    println!("{}: would be rewritten", path.display());
+                    Ok(())
+                } else {
+                    println!("{}: rewritten", path.display());
+                    std::fs::write(&path, output).map_err(|e| e.to_string())
+                }
+            });
+
+        if let Err(e) = result {
+            eprintln!("{}: {}", path.display(), e);
+            failed = true;
+        }
+    }
+
+    if failed {
+        ExitCode::from(2)
+    } else if check && changed {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,224 @@
+use svgbobdoc_migrate::migrate;
+
+#[track_caller]
+fn check(input: &str, expected: &str) {
+    let output = migrate(input).unwrap();
+    assert_eq!(output, expected);
+    // Running it twice changes nothing
+    assert_eq!(migrate(&output).unwrap(), expected);
+}
+
+#[test]
+fn struct_with_fields() {
+    check(
+        r#"
+/// Not a diagram.
+#[svgbobdoc::transform]
+/// A structure.
+///
+/// ```svgbob
+///  .---.
+///  | A |
+///  `---'
+/// ```
+#[derive(Debug)] // keep this
+pub struct TestStruct {
+    /// Not a diagram.
+    pub plain: u32,
+    /** A field.
+
+    ```svgbob
+     .---.
+    ```
+     */
+    /// The rest.
+    pub field: u32,
+}
+"#,
+        r#"
+/// Not a diagram.
+#[doc = svgbobdoc::transform!(
+/// A structure.
+///
+/// ```svgbob
+///  .---.
+///  | A |
+///  `---'
+/// ```
+)]
+#[derive(Debug)] // keep this
+pub struct TestStruct {
+    /// Not a diagram.
+    pub plain: u32,
+    #[doc = svgbobdoc::transform!(
+    /** A field.
+
+    ```svgbob
+     .---.
+    ```
+     */
+    /// The rest.
+    )]
+    pub field: u32,
+}
+"#,
+    );
+}
+
+#[test]
+fn enum_and_methods() {
+    check(
+        r#"
+mod m {
+    #[svgbobdoc::transform] pub enum E {
+        /// ~~~~svgbob
+        /// -->
+        /// ~~~~
+        A { /// ```svgbob
+            /// -->
+            /// ```
+            x: u32 },
+    }
+
+    impl E {
+        #[svgbobdoc::transform]
+        /// ```svgbob
+        /// -->
+        /// ```
+        fn f() {}
+    }
+}
+"#,
+        r#"
+mod m {
+    pub enum E {
+        #[doc = svgbobdoc::transform!(
+        /// ~~~~svgbob
+        /// -->
+        /// ~~~~
+        )]
+        A { #[doc = svgbobdoc::transform!(
+/// ```svgbob
+            /// -->
+            /// ```
+)]
+            x: u32 },
+    }
+
+    impl E {
+        #[doc = svgbobdoc::transform!(
+        /// ```svgbob
+        /// -->
+        /// ```
+        )]
+        fn f() {}
+    }
+}
+"#,
+    );
+}
+
+#[test]
+fn tuple_fields() {
+    check(
+        r#"
+#[svgbobdoc::transform]
+pub struct Tuple(
+    /// ```svgbob
+    /// -->
+    /// ```
+    pub u32,
+);
+
+#[svgbobdoc::transform]
+pub enum E {
+    A(
+        /// ```svgbob
+        /// -->
+        /// ```
+        u32,
+    ),
+}
+"#,
+        r#"
+pub struct Tuple(
+    #[doc = svgbobdoc::transform!(
+    /// ```svgbob
+    /// -->
+    /// ```
+    )]
+    pub u32,
+);
+
+pub enum E {
+    A(
+        #[doc = svgbobdoc::transform!(
+        /// ```svgbob
+        /// -->
+        /// ```
+        )]
+        u32,
+    ),
+}
+"#,
+    );
+}
+
+#[test]
+fn inner_doc_comments() {
+    check(
+        r#"
+/// ```svgbob
+/// outer
+/// ```
+#[svgbobdoc::transform]
+mod m {
+    //! ```svgbob
+    //! inner
+    //! ```
+    /*! Not a diagram. */
+
+    /// ```svgbob
+    /// -->
+    /// ```
+    pub struct S;
+}
+"#,
+        r#"
+#[doc = svgbobdoc::transform!(
+/// ```svgbob
+/// outer
+/// ```
+)]
+mod m {
+    #![doc = svgbobdoc::transform!(
+    //! ```svgbob
+    //! inner
+    //! ```
+    /*! Not a diagram. */
+    )]
+
+    /// ```svgbob
+    /// -->
+    /// ```
+    pub struct S;
+}
+"#,
+    );
+}
+
+#[test]
+fn unmarked_items_are_untouched() {
+    let input = r#"
+/// ```svgbob
+/// -->
+/// ```
+#[doc = svgbobdoc::transform!(
+/// ```svgbob
+/// -->
+/// ```
+)]
+pub struct S;
+"#;
+    assert_eq!(migrate(input).unwrap(), input);
+}
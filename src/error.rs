@@ -0,0 +1,113 @@
+//! Compile errors and warnings reported without syn
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::{fmt, iter::FromIterator};
+
+use svgbobdoc_core::diag;
+
+/// One or more compile errors, each covering a range of tokens.
+#[derive(Debug)]
+pub struct Error {
+    /// The messages and the spans of the first and last tokens they cover
+    messages: Vec<(Span, Span, String)>,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn new(span: Span, message: impl fmt::Display) -> Self {
+        Self::spanned(span, span, message)
+    }
+
+    /// Create an error covering the tokens from `first` to `last`.
+    pub fn spanned(first: Span, last: Span, message: impl fmt::Display) -> Self {
+        Self {
+            messages: vec![(first, last, message.to_string())],
+        }
+    }
+
+    /// Convert an error reported by the text processor, whose spans are
+    /// indices into `spans`.
+    pub fn from_diag(error: diag::Error, spans: &[Span]) -> Self {
+        Self {
+            messages: error
+                .into_iter()
+                .map(|(span, message)| {
+                    let span = span.index().map_or_else(Span::call_site, |i| spans[i]);
+                    (span, span, message)
+                })
+                .collect(),
+        }
+    }
+
+    /// Produce a `compile_error!` invocation for each message. They're
+    /// wrapped in a block to form a single string expression.
+    pub fn into_compile_error(self) -> TokenStream {
+        if let [(first, last, message)] = &self.messages[..] {
+            return compile_error(*first, *last, message);
+        }
+        let mut errors: TokenStream = self
+            .messages
+            .iter()
+            .flat_map(|(first, last, message)| compile_error(*first, *last, message))
+            .collect();
+        errors.extend([TokenTree::from(Literal::string(""))]);
+        TokenTree::from(Group::new(Delimiter::Brace, errors)).into()
+    }
+}
+
+/// Report a warning at `span`. Stable Rust doesn't provide a way for proc
+/// macros to emit warnings, so it's written to the standard error output
+/// unless the crate is built with `--cfg svgbobdoc_unstable` on nightly.
+pub fn warn(span: Span, message: &str) {
+    #[cfg(svgbobdoc_unstable)]
+    span.warning(message).emit();
+    #[cfg(not(svgbobdoc_unstable))]
+    {
+        let _ = span;
+        diag::warn(message);
+    }
+}
+
+/// Report the warnings found by the text processor, whose spans are indices
+/// into `spans`.
+pub fn warn_diag(warnings: Vec<(diag::Span, String)>, spans: &[Span]) {
+    for (span, message) in warnings {
+        warn(
+            span.index().map_or_else(Span::call_site, |i| spans[i]),
+            &message,
+        );
+    }
+}
+
+/// An error not relating to specific tokens, such as invalid crate-wide
+/// options.
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self::new(Span::call_site(), message)
+    }
+}
+
+/// `::core::compile_error! { "message" }`, where the path and the `!` have
+/// the span `first` and the braces have `last` so that the error covers
+/// the range between them.
+fn compile_error(first: Span, last: Span, message: &str) -> TokenStream {
+    let punct = |ch, spacing| {
+        let mut punct = Punct::new(ch, spacing);
+        punct.set_span(first);
+        TokenTree::from(punct)
+    };
+    let mut message = Literal::string(message);
+    message.set_span(last);
+    let mut group = Group::new(Delimiter::Brace, TokenTree::from(message).into());
+    group.set_span(last);
+    TokenStream::from_iter([
+        punct(':', Spacing::Joint),
+        punct(':', Spacing::Alone),
+        Ident::new("core", first).into(),
+        punct(':', Spacing::Joint),
+        punct(':', Spacing::Alone),
+        Ident::new("compile_error", first).into(),
+        punct('!', Spacing::Alone),
+        group.into(),
+    ])
+}
@@ -0,0 +1,197 @@
+//! Parsing the macros' input without syn
+use proc_macro::{Delimiter, Ident, Literal, Span, TokenStream, TokenTree};
+use std::iter::FromIterator;
+
+use crate::{
+    error::{Error, Result},
+    literal::str_value,
+};
+
+/// A string literal and its value.
+pub struct Str {
+    pub lit: Literal,
+    pub value: String,
+}
+
+impl Str {
+    pub fn span(&self) -> Span {
+        self.lit.span()
+    }
+}
+
+/// A string literal or the values of zero or more `#[doc = ...]` attributes.
+pub enum StrOrDocAttrs {
+    Str(Str),
+    Attrs(Vec<Str>),
+}
+
+/// Parse the input of `transform!`, which is a string literal or zero or more
+/// `#[doc = ...]` attributes.
+pub fn parse_docs(input: TokenStream) -> Result<StrOrDocAttrs> {
+    let mut tokens = flatten(input).into_iter().peekable();
+    if let Some(TokenTree::Literal(_)) = tokens.peek() {
+        let s = expect_str(tokens.next().unwrap())?;
+        expect_end(tokens)?;
+        return Ok(StrOrDocAttrs::Str(s));
+    }
+
+    let mut docs = Vec::new();
+    while let Some(pound) = tokens.next() {
+        if !matches!(&pound, TokenTree::Punct(p) if p.as_char() == '#') {
+            return Err(Error::new(
+                pound.span(),
+                "expected a string literal or `#[doc = ...]` attributes",
+            ));
+        }
+        // `#![...]` is an inner attribute
+        let mut last = tokens.next();
+        if matches!(&last, Some(TokenTree::Punct(p)) if p.as_char() == '!') {
+            last = tokens.next();
+        }
+        let group = match last {
+            Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Bracket => group,
+            Some(token) => return Err(Error::new(token.span(), "expected `[`")),
+            None => return Err(Error::new(pound.span(), "expected `[` after `#`")),
+        };
+
+        let content = flatten(group.stream());
+        match &content[..] {
+            [TokenTree::Ident(ident), TokenTree::Punct(eq), value @ ..]
+                if ident.to_string() == "doc" && eq.as_char() == '=' && !value.is_empty() =>
+            {
+                docs.push(match value {
+                    [value] => expect_str(value.clone())
+                        .map_err(|_| Error::new(value.span(), "doc comment must be a string"))?,
+                    [first, .., last] => {
+                        return Err(Error::spanned(
+                            first.span(),
+                            last.span(),
+                            "doc comment must be a string",
+                        ))
+                    }
+                    [] => unreachable!(),
+                });
+            }
+            _ => {
+                return Err(Error::spanned(
+                    pound.span(),
+                    group.span(),
+                    "only `#[doc = ...]` attributes or a string literal are allowed here",
+                ))
+            }
+        }
+    }
+    Ok(StrOrDocAttrs::Attrs(docs))
+}
+
+/// An option specified as a leading argument of `transform!` (`key = value`
+/// or `key`).
+pub struct LeadingOption {
+    pub key: Ident,
+    pub value: Option<String>,
+}
+
+/// Parse the input of `transform!`, which is zero or more options, each
+/// followed by a comma, followed by the input accepted by [`parse_docs`].
+pub fn parse_transform(input: TokenStream) -> Result<(Vec<LeadingOption>, StrOrDocAttrs)> {
+    let mut tokens = flatten(input).into_iter().peekable();
+    let mut options = Vec::new();
+    while let Some(TokenTree::Ident(_)) = tokens.peek() {
+        let key = match tokens.next() {
+            Some(TokenTree::Ident(key)) => key,
+            _ => unreachable!(),
+        };
+        let mut value = None;
+        let mut sep = tokens.next();
+        if matches!(&sep, Some(TokenTree::Punct(p)) if p.as_char() == '=') {
+            let token = match tokens.next() {
+                Some(token) => token,
+                None => return Err(Error::new(key.span(), "expected a value after `=`")),
+            };
+            value = Some(option_value(&token).ok_or_else(|| {
+                Error::new(token.span(), "expected a string, number, or boolean")
+            })?);
+            sep = tokens.next();
+        }
+        match sep {
+            Some(TokenTree::Punct(p)) if p.as_char() == ',' => {}
+            Some(token) => return Err(Error::new(token.span(), "expected `,`")),
+            None => return Err(Error::new(key.span(), "expected `,` after the option")),
+        }
+        options.push(LeadingOption { key, value });
+    }
+    let docs = parse_docs(TokenStream::from_iter(tokens))?;
+    Ok((options, docs))
+}
+
+/// Get the value of an option from a string, number, or boolean literal.
+fn option_value(token: &TokenTree) -> Option<String> {
+    match token {
+        TokenTree::Literal(lit) => str_value(lit).or_else(|| {
+            let source = lit.to_string();
+            source
+                .starts_with(|ch: char| ch.is_ascii_digit())
+                .then(|| source.replace('_', ""))
+        }),
+        TokenTree::Ident(ident) => {
+            let name = ident.to_string();
+            (name == "true" || name == "false").then_some(name)
+        }
+        _ => None,
+    }
+}
+
+/// Parse comma-separated string literals, which may be followed by a comma.
+pub fn parse_strs(input: TokenStream) -> Result<Vec<Str>> {
+    let mut tokens = flatten(input).into_iter();
+    let mut strs = Vec::new();
+    while let Some(token) = tokens.next() {
+        strs.push(expect_str(token)?);
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == ',' => {}
+            Some(token) => return Err(Error::new(token.span(), "expected `,`")),
+            None => break,
+        }
+    }
+    Ok(strs)
+}
+
+/// Check that there's no input.
+pub fn parse_nothing(input: TokenStream) -> Result<()> {
+    expect_end(flatten(input).into_iter())
+}
+
+fn expect_str(token: TokenTree) -> Result<Str> {
+    if let TokenTree::Literal(lit) = &token {
+        if let Some(value) = str_value(lit) {
+            return Ok(Str {
+                lit: lit.clone(),
+                value,
+            });
+        }
+    }
+    Err(Error::new(token.span(), "expected a string literal"))
+}
+
+fn expect_end(mut tokens: impl Iterator<Item = TokenTree>) -> Result<()> {
+    match tokens.next() {
+        Some(token) => Err(Error::new(token.span(), "unexpected token")),
+        None => Ok(()),
+    }
+}
+
+/// Get the tokens of `stream`, replacing groups without delimiters with their
+/// contents. Such groups wrap the fragments passed through `macro_rules!`
+/// (e.g., `$doc:literal`).
+fn flatten(stream: TokenStream) -> Vec<TokenTree> {
+    let mut tokens = Vec::new();
+    for token in stream {
+        match token {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::None => {
+                tokens.extend(flatten(group.stream()))
+            }
+            token => tokens.push(token),
+        }
+    }
+    tokens
+}
@@ -1,165 +1,296 @@
 #![doc = include_str!("../README.md")]
 #![warn(rust_2018_idioms)]
-use proc_macro2::{Span, TokenStream};
-use quote::ToTokens;
-use syn::{
-    self,
-    parse::{Parse, ParseStream},
-    parse_macro_input,
-    spanned::Spanned,
-    AttrStyle, Attribute, Error, Expr, ExprLit, Lit, LitStr, Meta, MetaNameValue, Result,
-};
-
-mod textproc;
-
-/// An `Attribute`, recognized as a doc comment or not.
-#[derive(Clone)]
-enum MaybeDocAttr {
-    /// A doc comment attribute.
-    ///
-    /// The first `Attribute` only specifies the surround tokens.
-    ///
-    /// `MetaNameValue::value` must be a `Expr::Lit(_)`.
-    Doc(Attribute, MetaNameValue),
-    /// An unrecognized attribute that we don't care.
-    Other(Attribute),
-}
+#![cfg_attr(
+    svgbobdoc_unstable,
+    feature(proc_macro_diagnostic, proc_macro_span, proc_macro_tracked_env)
+)]
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+use std::{iter::FromIterator, path::PathBuf};
 
-impl MaybeDocAttr {
-    fn from_attribute(attr: Attribute) -> Result<Self> {
-        if attr.path().is_ident("doc") {
-            let meta = attr.meta.clone();
-
-            if let Meta::NameValue(nv) = meta {
-                if let Expr::Lit(expr) = &nv.value {
-                    if let Lit::Str(_) = expr.lit {
-                        Ok(MaybeDocAttr::Doc(attr, nv.clone()))
-                    } else {
-                        Err(Error::new(expr.lit.span(), "doc comment must be a string"))
-                    }
-                } else {
-                    Err(Error::new(nv.value.span(), "doc comment must be a string"))
-                }
-            } else {
-                // Ignore unrecognized form
-                Ok(MaybeDocAttr::Other(attr))
-            }
-        } else {
-            Ok(MaybeDocAttr::Other(attr))
-        }
-    }
-}
+use svgbobdoc_core::{diag, options, textproc};
 
-impl ToTokens for MaybeDocAttr {
-    fn to_tokens(&self, tokens: &mut TokenStream) {
-        match self {
-            MaybeDocAttr::Doc(attr, nv) => {
-                attr.pound_token.to_tokens(tokens);
-                if let AttrStyle::Inner(ref b) = attr.style {
-                    b.to_tokens(tokens);
-                }
-                attr.bracket_token.surround(tokens, |tokens| {
-                    nv.to_tokens(tokens);
-                });
-            }
-            MaybeDocAttr::Other(attr) => attr.to_tokens(tokens),
+mod error;
+mod input;
+mod literal;
+#[cfg(feature = "trait-impls")]
+mod trait_impls;
+
+use error::{Error, Result};
+
+/// Render ASCII-diagram code blocks in a Markdown-formatted string literal or
+/// zero or more `#[doc = ...]` attributes as SVG images.
+///
+/// They can be preceded by options applied to all code blocks in the
+/// invocation, which are written with underscores in place of hyphens:
+///
+/// ```text
+/// #[doc = svgbobdoc::transform!(mode = "img", dark_variant,
+/// /// ```svgbob
+/// /// -->
+/// /// ```
+/// )]
+/// ```
+///
+/// See [the module-level documentation](../index.html) for more.
+#[proc_macro]
+pub fn transform(tokens: TokenStream) -> TokenStream {
+    handle_error(|| {
+        let (leading_options, docs) = input::parse_transform(tokens)?;
+        let mut options = options::Options::default();
+        for input::LeadingOption { key, value } in leading_options {
+            // Option names are written with underscores in place of hyphens
+            let name = key.to_string().replace('_', "-");
+            options
+                .apply(&name, value)
+                .map_err(|e| Error::new(key.span(), e))?;
         }
-    }
-}
 
-impl From<MaybeDocAttr> for Attribute {
-    /// The mostly-lossless conversion to `Attribute`.
-    fn from(val: MaybeDocAttr) -> Attribute {
-        match val {
-            MaybeDocAttr::Doc(mut attr, nv) => {
-                attr.meta = Meta::NameValue(nv);
-                attr
-            }
-            MaybeDocAttr::Other(attr) => attr,
+        let (docs, doc_attrs) = match docs {
+            input::StrOrDocAttrs::Str(s) => (vec![s], false),
+            input::StrOrDocAttrs::Attrs(docs) => (docs, true),
+        };
+        let mut spans = Vec::new();
+        let mut fragments = Vec::new();
+        for doc in docs {
+            let line_spans = literal::line_spans(&doc.lit, &doc.value)
+                .into_iter()
+                .map(|span| {
+                    spans.push(span);
+                    diag::Span::new(spans.len() - 1)
+                })
+                .collect();
+            // Strip the stars decorating a block doc comment like rustdoc
+            // does. They don't change the lines, so `line_spans` still apply.
+            // A string literal is left as is since `*` may be a list marker
+            // there.
+            let value = if doc_attrs {
+                textproc::strip_comment_stars(&doc.value)
+            } else {
+                doc.value
+            };
+            fragments.push(Ok((value, line_spans)));
         }
-    }
+        let mut text_proc = new_text_proc(options)?;
+        text_proc.set_sites(spans.iter().map(|&span| site(span)).collect());
+        let output = text_proc.transform_document(fragments);
+        error::warn_diag(text_proc.take_warnings(), &spans);
+        let output = output.map_err(|e| Error::from_diag(e, &spans))?;
+        Ok(tracking_files(
+            output,
+            &text_proc.take_files(),
+            Span::call_site(),
+        ))
+    })
 }
 
-enum StrOrDocAttrs {
-    Str(LitStr),
-    Attrs(Vec<syn::Attribute>),
+/// Implement a trait for each of the specified types, documenting each
+/// implementation with the same doc comments, in which `{Type}` is replaced
+/// with the type's name. The doc comments are processed like [`transform!`],
+/// and identical diagrams are rendered only once.
+///
+/// ```text
+/// svgbobdoc::transform_trait_impls! {
+///     /// Converts `{Type}` to bytes.
+///     ///
+///     /// ```svgbob
+///     /// {Type} --> bytes
+///     /// ```
+///     impl ToBytes for [u8, u16, u32] {
+///         fn to_bytes(&self) -> Vec<u8> {
+///             self.to_le_bytes().to_vec()
+///         }
+///     }
+/// }
+/// ```
+///
+/// This macro requires the `trait-impls` Cargo feature, which brings in syn
+/// to parse the trait and the types.
+#[cfg(feature = "trait-impls")]
+#[proc_macro]
+pub fn transform_trait_impls(tokens: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(tokens as trait_impls::TraitImpls);
+    new_text_proc(options::Options::default())
+        .map_err(|e| syn::Error::new(proc_macro2::Span::call_site(), e))
+        .and_then(|mut text_proc| trait_impls::expand(input, &mut text_proc))
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
 }
 
-impl Parse for StrOrDocAttrs {
-    fn parse(input: ParseStream<'_>) -> Result<Self> {
-        if let Ok(lit_str) = input.parse() {
-            Ok(Self::Str(lit_str))
-        } else {
-            // `#[doc = ...]` sequence
-            let mut attrs = Attribute::parse_inner(input)?;
-            attrs.extend(Attribute::parse_outer(input)?);
-            Ok(Self::Attrs(attrs))
-        }
-    }
+/// Produce a Markdown list of the figures numbered by `number-figures` so far
+/// in the crate, each linking to its figure. Figures are numbered in the order
+/// in which the macro invocations are expanded, so this must come after them.
+///
+/// ```text
+/// #![doc = svgbobdoc::transform!(number_figures,
+/// //! ```svgbob,caption="The boot sequence"
+/// //! -->
+/// //! ```
+/// )]
+/// #![doc = "# Figures"]
+/// #![doc = svgbobdoc::figure_list!()]
+/// ```
+///
+/// See [the module-level documentation](../index.html) for more.
+#[proc_macro]
+pub fn figure_list(tokens: TokenStream) -> TokenStream {
+    handle_error(|| {
+        input::parse_nothing(tokens)?;
+        let list = textproc::figure_list().unwrap_or_else(|| {
+            error::warn(
+                Span::call_site(),
+                "`figure_list!` found no figures; figures are numbered by `number-figures` \
+                 in macro invocations expanded before it",
+            );
+            String::new()
+        });
+        Ok(str_lit(&list))
+    })
 }
 
-/// Render ASCII-diagram code blocks in a Markdown-formatted string literal or
-/// zero or more `#[doc = ...]` attributes as SVG images.
+/// Render an ASCII diagram stored in a file as an SVG image. The path is
+/// relative to the crate's root directory (`CARGO_MANIFEST_DIR`). Code block
+/// options can be specified by the second parameter.
+///
+/// ```text
+/// #[doc = svgbobdoc::transform_file!("doc/state-machine.bob")]
+/// #[doc = svgbobdoc::transform_file!("doc/state-machine.bob", "mode=img")]
+/// ```
 ///
 /// See [the module-level documentation](../index.html) for more.
 #[proc_macro]
-pub fn transform(tokens: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let input: StrOrDocAttrs = parse_macro_input!(tokens);
-    let (mut iter1, mut iter2);
-    let iter: &mut dyn Iterator<Item = Result<LitStr>> =
-        match input {
-            StrOrDocAttrs::Str(s) => {
-                iter1 = std::iter::once(Ok(s));
-                &mut iter1
-            }
-            StrOrDocAttrs::Attrs(attrs) => {
-                iter2 =
-                    attrs.into_iter().map(|attr| {
-                        match MaybeDocAttr::from_attribute(attr)? {
-                    MaybeDocAttr::Doc(
-                        _,
-                        MetaNameValue {
-                            value: Expr::Lit (ExprLit { lit: Lit::Str(s), .. }),
-                            ..
-                        },
-                    ) => Ok(s),
-                    MaybeDocAttr::Doc(attr, _) | MaybeDocAttr::Other(attr) => {
-                        Err(Error::new_spanned(
-                            &attr,
-                            "only `#[doc = ...]` attributes or a string literal are allowed here",
-                        ))
-                    }
-                }
-                    });
-                &mut iter2
+pub fn transform_file(tokens: TokenStream) -> TokenStream {
+    handle_error(|| {
+        let args = input::parse_strs(tokens)?;
+        let (path, params) = match &args[..] {
+            [path] => (path, ""),
+            [path, params] => (path, &*params.value),
+            _ => {
+                return Err(Error::new(
+                    Span::call_site(),
+                    "expected a path and optionally code block options",
+                ))
             }
         };
+        let span = path.span();
+        let (file_path, art) = textproc::read_art_file(&path.value, diag::Span::new(0))
+            .map_err(|e| Error::from_diag(e, &[span]))?;
 
-    handle_error(|| {
-        let mut output = String::new();
-        use textproc::{TextProcOutput, TextProcState};
-        let mut text_proc = TextProcState::new();
-        for lit_str in iter {
-            let lit_str = lit_str?;
-            let st = lit_str.value();
-            match text_proc.step(&st, lit_str.span()) {
-                TextProcOutput::Passthrough => output.push_str(&st),
-                TextProcOutput::Fragment(fr) => output.push_str(&fr),
-                TextProcOutput::Empty => {}
-            }
-            output.push('\n');
-        }
-        text_proc.finalize()?;
+        let mut text_proc = new_text_proc(options::Options::default())?;
+        text_proc.set_sites(vec![site(span)]);
+        let output = text_proc.transform_diagram(&art, params, diag::Span::new(0));
+        error::warn_diag(text_proc.take_warnings(), &[span]);
+        let output = output.map_err(|e| Error::from_diag(e, &[span]))?;
 
-        Ok(LitStr::new(&output, Span::call_site())
-            .into_token_stream()
-            .into())
+        let mut files = text_proc.take_files();
+        files.insert(0, file_path);
+        Ok(tracking_files(output, &files, span))
     })
 }
 
-fn handle_error(cb: impl FnOnce() -> Result<proc_macro::TokenStream>) -> proc_macro::TokenStream {
-    match cb() {
-        Ok(tokens) => tokens,
-        Err(e) => e.to_compile_error().into(),
+/// Produce an expression evaluating to `output` that makes rustc track
+/// `files` so that a change to them triggers recompilation, without changing
+/// the rendered page.
+///
+/// `env!` expands its error message eagerly, so an `include_str!` there makes
+/// rustc track the file even though its content is discarded. The variable
+/// (`CARGO_PKG_NAME`) is always set by Cargo, and its value is appended as the
+/// destination of a link reference definition, which rustdoc doesn't render
+/// unless it's referenced.
+fn tracking_files(mut output: String, files: &[PathBuf], span: Span) -> TokenStream {
+    let paths: Vec<&str> = files.iter().filter_map(|path| path.to_str()).collect();
+    if paths.is_empty() || std::env::var_os("CARGO_PKG_NAME").is_none() {
+        return str_lit(&output);
+    }
+
+    // A link reference definition can't interrupt a paragraph
+    output.push_str(if output.ends_with('\n') { "\n" } else { "\n\n" });
+    let mut args = vec![str_lit(&output)];
+    for (i, path) in paths.into_iter().enumerate() {
+        let mut path_lit = Literal::string(path);
+        path_lit.set_span(span);
+        args.push(str_lit(&format!("[svgbobdoc-tracked-file-{}]: ", i)));
+        args.push(macro_call(
+            "env",
+            [
+                str_lit("CARGO_PKG_NAME"),
+                macro_call("include_str", [TokenTree::from(path_lit).into()]),
+            ],
+        ));
+        // The empty title keeps the next line from being taken as the title
+        args.push(str_lit(" \"\"\n"));
+    }
+    macro_call("concat", args)
+}
+
+/// `name!(args, ...)`
+fn macro_call(name: &str, args: impl IntoIterator<Item = TokenStream>) -> TokenStream {
+    let mut arg_tokens = TokenStream::new();
+    for arg in args {
+        if !arg_tokens.is_empty() {
+            arg_tokens.extend([TokenTree::from(Punct::new(',', Spacing::Alone))]);
+        }
+        arg_tokens.extend(arg);
     }
+    TokenStream::from_iter([
+        TokenTree::from(Ident::new(name, Span::call_site())),
+        Punct::new('!', Spacing::Alone).into(),
+        Group::new(Delimiter::Parenthesis, arg_tokens).into(),
+    ])
+}
+
+/// A string literal expression.
+fn str_lit(value: &str) -> TokenStream {
+    TokenTree::from(Literal::string(value)).into()
+}
+
+/// Describe where `span` is for the text processor, which only knows the
+/// indices of the spans.
+fn site(span: Span) -> textproc::Site {
+    textproc::Site {
+        // Distinct for each position in the crate, but the same when the
+        // macro invocation is expanded again
+        id: format!("{:?}", span),
+        location: location(span),
+    }
+}
+
+#[cfg(svgbobdoc_unstable)]
+fn location(span: Span) -> Option<String> {
+    Some(format!(
+        "{}:{}:{}",
+        span.source_file().path().display(),
+        span.start().line(),
+        span.start().column()
+    ))
+}
+
+#[cfg(not(svgbobdoc_unstable))]
+fn location(_: Span) -> Option<String> {
+    None
+}
+
+/// Create a `TextProcState` using `options` and, for the options unspecified
+/// by it, the crate-wide options. The warnings are collected to be reported
+/// at their spans.
+fn new_text_proc(options: options::Options) -> Result<textproc::TextProcState, String> {
+    let (defaults, manifest) = options::Options::from_vars(env_var)?;
+    let mut text_proc = textproc::TextProcState::new(options.or(&defaults));
+    text_proc.collect_warnings();
+    if let Some(manifest) = manifest {
+        text_proc.track_file(manifest);
+    }
+    Ok(text_proc)
+}
+
+/// Get an environment variable. On nightly, rustc is told to track it so
+/// that a change to it triggers recompilation.
+fn env_var(name: &str) -> Option<String> {
+    #[cfg(svgbobdoc_unstable)]
+    return proc_macro::tracked_env::var(name).ok();
+    #[cfg(not(svgbobdoc_unstable))]
+    std::env::var(name).ok()
+}
+
+fn handle_error(cb: impl FnOnce() -> Result<TokenStream>) -> TokenStream {
+    cb().unwrap_or_else(Error::into_compile_error)
 }
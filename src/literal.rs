@@ -0,0 +1,181 @@
+//! Decoding string literals and locating their lines in the source code
+use proc_macro::{Literal, Span};
+
+/// Get the value of a string literal, or `None` if `lit` is another kind of
+/// literal.
+pub fn str_value(lit: &Literal) -> Option<String> {
+    decode(&lit.to_string()).map(|(value, _)| value)
+}
+
+/// Get the spans of the lines of a string literal's value, so that an error
+/// in a multi-line literal or block doc comment points at the offending line.
+///
+/// The lines can only be located if the crate is built with
+/// `--cfg svgbobdoc_unstable` on nightly, which provides `Literal::subspan`.
+/// Otherwise, only the literal's span is returned.
+pub fn line_spans(lit: &Literal, value: &str) -> Vec<Span> {
+    #[cfg(svgbobdoc_unstable)]
+    if let Some(spans) = located_line_spans(lit, value) {
+        return spans;
+    }
+    #[cfg(not(svgbobdoc_unstable))]
+    let _ = value;
+    vec![lit.span()]
+}
+
+#[cfg(svgbobdoc_unstable)]
+fn located_line_spans(lit: &Literal, value: &str) -> Option<Vec<Span>> {
+    let (decoded, offsets) = decode(&lit.span().source_text()?)?;
+    if decoded != value {
+        return None;
+    }
+    let mut start = 0;
+    let spans = value
+        .split('\n')
+        .map(|line| {
+            let end = start + line.len();
+            // `subspan` rejects empty ranges, so an empty line gets the
+            // literal's span
+            let span = lit.subspan(offsets[start]..offsets[end]);
+            start = end + 1;
+            span.unwrap_or_else(|| lit.span())
+        })
+        .collect();
+    Some(spans)
+}
+
+/// Decode the source code of a string literal or a doc comment. Returns its
+/// value and the byte offset in the source code of each byte offset in the
+/// value, up to and including its length, or `None` if the source code isn't
+/// recognized.
+fn decode(source: &str) -> Option<(String, Vec<usize>)> {
+    let mut offsets = Vec::with_capacity(source.len() + 1);
+    let mut value = String::with_capacity(source.len());
+    let mut push = |ch: char, offset: usize| {
+        offsets.extend(std::iter::repeat(offset).take(ch.len_utf8()));
+        value.push(ch);
+    };
+
+    let end = if let Some(content) = ["///", "//!"]
+        .iter()
+        .find_map(|prefix| source.strip_prefix(prefix))
+    {
+        // Doc comments are taken verbatim
+        content.char_indices().for_each(|(i, ch)| push(ch, 3 + i));
+        source.len()
+    } else if source.starts_with("/**") || source.starts_with("/*!") {
+        let content = source.get(3..)?.strip_suffix("*/")?;
+        content.char_indices().for_each(|(i, ch)| push(ch, 3 + i));
+        source.len() - 2
+    } else if let Some(rest) = source.strip_prefix('r') {
+        // A raw string literal (`r#"..."#`)
+        let hashes = &rest[..rest.len() - rest.trim_start_matches('#').len()];
+        let content = rest[hashes.len()..]
+            .strip_prefix('"')?
+            .strip_suffix(hashes)?
+            .strip_suffix('"')?;
+        let start = 1 + hashes.len() + 1;
+        content
+            .char_indices()
+            .for_each(|(i, ch)| push(ch, start + i));
+        start + content.len()
+    } else {
+        let content = source.strip_prefix('"')?.strip_suffix('"')?;
+        let mut chars = content.char_indices().peekable();
+        while let Some((i, ch)) = chars.next() {
+            if ch != '\\' {
+                push(ch, 1 + i);
+                continue;
+            }
+            let ch = match chars.next()?.1 {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                ch @ ('\\' | '\'' | '"') => ch,
+                'x' => {
+                    let digits: String = (0..2)
+                        .map(|_| chars.next().map(|(_, ch)| ch))
+                        .collect::<Option<_>>()?;
+                    char::from(u8::from_str_radix(&digits, 16).ok()?)
+                }
+                'u' => {
+                    chars.next().filter(|&(_, ch)| ch == '{')?;
+                    let mut digits = String::new();
+                    loop {
+                        match chars.next()?.1 {
+                            '}' => break,
+                            '_' => {}
+                            ch => digits.push(ch),
+                        }
+                    }
+                    char::from_u32(u32::from_str_radix(&digits, 16).ok()?)?
+                }
+                '\n' => {
+                    // A line continuation, which skips the following
+                    // whitespace
+                    while chars.next_if(|&(_, ch)| ch.is_whitespace()).is_some() {}
+                    continue;
+                }
+                _ => return None,
+            };
+            push(ch, 1 + i);
+        }
+        source.len() - 1
+    };
+
+    offsets.push(end);
+    Some((value, offsets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decode `source` and get the source text of each line of the value.
+    fn decode_lines(source: &str) -> (String, Vec<&str>) {
+        let (value, offsets) = decode(source).unwrap();
+        let mut start = 0;
+        let lines = value
+            .split('\n')
+            .map(|line| {
+                let end = start + line.len();
+                let range = offsets[start]..offsets[end];
+                start = end + 1;
+                &source[range]
+            })
+            .collect();
+        (value, lines)
+    }
+
+    #[test]
+    fn string_literal() {
+        let (value, lines) = decode_lines("\"a\\n\\u{2500}\\x41\\\"\\\n    b\nc\"");
+        assert_eq!(value, "a\n─A\"b\nc");
+        assert_eq!(lines, ["a", "\\u{2500}\\x41\\\"\\\n    b", "c"]);
+    }
+
+    #[test]
+    fn raw_string_literal() {
+        let (value, lines) = decode_lines("r#\"a\\n\n\"b\"\"#");
+        assert_eq!(value, "a\\n\n\"b\"");
+        assert_eq!(lines, ["a\\n", "\"b\""]);
+        assert_eq!(decode("r\"a\"").unwrap().0, "a");
+    }
+
+    #[test]
+    fn doc_comments() {
+        assert_eq!(decode_lines("/// a"), (" a".to_owned(), vec![" a"]));
+        assert_eq!(
+            decode_lines("/** a\n * b\n */"),
+            (" a\n * b\n ".to_owned(), vec![" a", " * b", " "])
+        );
+    }
+
+    #[test]
+    fn other_literals() {
+        for source in ["b\"a\"", "\"a\"x", "'a'", "1", "\"\\q\"", "r#\"a\""] {
+            assert!(decode(source).is_none(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! The implementation of `transform_trait_impls!`, enabled by the
+//! `trait-impls` Cargo feature
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, ToTokens};
+use syn::{
+    braced, bracketed,
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Attribute, Error, Expr, ExprLit, Lit, LitStr, Meta, MetaNameValue, Path, Result, Token, Type,
+};
+
+use svgbobdoc_core::{
+    diag,
+    textproc::{strip_comment_stars, TextProcState},
+};
+
+/// The placeholder replaced with each type's name.
+const PLACEHOLDER: &str = "{Type}";
+
+/// An `Attribute`, recognized as a doc comment or not.
+enum MaybeDocAttr {
+    /// A doc comment attribute.
+    ///
+    /// `MetaNameValue::value` must be a `Expr::Lit(_)`.
+    Doc(MetaNameValue),
+    /// An unrecognized attribute that we don't care.
+    Other(Attribute),
+}
+
+impl MaybeDocAttr {
+    fn from_attribute(attr: Attribute) -> Result<Self> {
+        if attr.path().is_ident("doc") {
+            let meta = attr.meta.clone();
+
+            if let Meta::NameValue(nv) = meta {
+                if let Expr::Lit(expr) = &nv.value {
+                    if let Lit::Str(_) = expr.lit {
+                        Ok(MaybeDocAttr::Doc(nv.clone()))
+                    } else {
+                        Err(Error::new(expr.lit.span(), "doc comment must be a string"))
+                    }
+                } else {
+                    Err(Error::new(nv.value.span(), "doc comment must be a string"))
+                }
+            } else {
+                // Ignore unrecognized form
+                Ok(MaybeDocAttr::Other(attr))
+            }
+        } else {
+            Ok(MaybeDocAttr::Other(attr))
+        }
+    }
+}
+
+/// `#[doc = "..."]... impl Trait for [Type, ...] { ... }`
+pub struct TraitImpls {
+    attrs: Vec<Attribute>,
+    trait_path: Path,
+    types: Punctuated<Type, Token![,]>,
+    body: TokenStream,
+}
+
+impl Parse for TraitImpls {
+    fn parse(input: ParseStream<'_>) -> Result<Self> {
+        let attrs = Attribute::parse_outer(input)?;
+        input.parse::<Token![impl]>()?;
+        let trait_path = input.parse()?;
+        input.parse::<Token![for]>()?;
+        let types;
+        bracketed!(types in input);
+        let types = types.parse_terminated(Type::parse, Token![,])?;
+        let body;
+        braced!(body in input);
+        let body = body.parse()?;
+        Ok(Self {
+            attrs,
+            trait_path,
+            types,
+            body,
+        })
+    }
+}
+
+pub fn expand(input: TraitImpls, text_proc: &mut TextProcState) -> Result<TokenStream> {
+    let mut docs = Vec::new();
+    let mut other_attrs = Vec::new();
+    for attr in input.attrs {
+        match MaybeDocAttr::from_attribute(attr)? {
+            MaybeDocAttr::Doc(nv) => match nv.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => docs.push(s),
+                _ => unreachable!(),
+            },
+            MaybeDocAttr::Other(attr) => other_attrs.push(attr),
+        }
+    }
+
+    let types: Vec<&Type> = input.types.iter().collect();
+    // The spans can only be converted in a procedural macro
+    if proc_macro::is_available() {
+        text_proc.set_sites(
+            docs.iter()
+                .map(|doc| crate::site(doc.span().unwrap()))
+                .collect(),
+        );
+    }
+    let rendered = render_docs(&docs, &types, text_proc);
+    for (span, message) in text_proc.take_warnings() {
+        let span = span
+            .index()
+            .map_or_else(proc_macro::Span::call_site, |i| docs[i].span().unwrap());
+        crate::error::warn(span, &message);
+    }
+    let docs = rendered?;
+    let files = text_proc.take_files();
+
+    let (trait_path, body) = (&input.trait_path, &input.body);
+    Ok(types
+        .iter()
+        .zip(docs)
+        .map(|(ty, doc)| {
+            let doc = if files.is_empty() {
+                doc.into_token_stream()
+            } else {
+                crate::tracking_files(doc, &files, proc_macro::Span::call_site()).into()
+            };
+            quote! {
+                #[doc = #doc]
+                #(#other_attrs)*
+                impl #trait_path for #ty {
+                    #body
+                }
+            }
+        })
+        .collect())
+}
+
+/// Substitute each type's name into the doc comments and process them.
+fn render_docs(
+    docs: &[LitStr],
+    types: &[&Type],
+    text_proc: &mut TextProcState,
+) -> Result<Vec<String>> {
+    types
+        .iter()
+        .map(|ty| {
+            let name = type_name(ty);
+            text_proc
+                .transform_document(docs.iter().enumerate().map(|(i, doc)| {
+                    Ok((
+                        strip_comment_stars(&doc.value()).replace(PLACEHOLDER, &name),
+                        vec![diag::Span::new(i)],
+                    ))
+                }))
+                .map_err(|e| to_syn_error(e, docs))
+        })
+        .collect()
+}
+
+/// Convert an error reported by the text processor for `docs`.
+fn to_syn_error(error: diag::Error, docs: &[LitStr]) -> Error {
+    error
+        .into_iter()
+        .map(|(span, message)| {
+            let span = span
+                .index()
+                .map_or_else(Span::call_site, |i| docs[i].span());
+            Error::new(span, message)
+        })
+        .reduce(|mut error, e| {
+            error.combine(e);
+            error
+        })
+        .unwrap()
+}
+
+/// Format a type as it would be written by hand, e.g., `Vec<u8>` instead of
+/// `Vec < u8 >`.
+fn type_name(ty: &Type) -> String {
+    let mut name = ty.to_token_stream().to_string();
+    for (from, to) in [
+        (" <", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+        (" ::", "::"),
+        (":: ", "::"),
+        (" ,", ","),
+        (" ;", ";"),
+        ("& ", "&"),
+    ] {
+        name = name.replace(from, to);
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use svgbobdoc_core::{options::Options, stats::render_count};
+
+    #[test]
+    fn per_type_docs() {
+        let input: TraitImpls = syn::parse_str(
+            r#"
+            /// Converts `{Type}` to bytes.
+            ///
+            /// ```svgbob
+            /// {Type} --> bytes
+            /// ```
+            ///
+            /// ```svgbob
+            /// bytes --> wire
+            /// ```
+            impl ToBytes for [u8, Vec<u8>, u8] {}
+            "#,
+        )
+        .unwrap();
+        let docs: Vec<LitStr> = input
+            .attrs
+            .iter()
+            .map(|attr| match &attr.meta {
+                syn::Meta::NameValue(nv) => syn::parse2(nv.value.to_token_stream()).unwrap(),
+                _ => unreachable!(),
+            })
+            .collect();
+        let types: Vec<&Type> = input.types.iter().collect();
+
+        let mut text_proc = TextProcState::new(Options::default());
+        let count = render_count();
+        let docs = render_docs(&docs, &types, &mut text_proc).unwrap();
+        // `u8 --> bytes`, `Vec<u8> --> bytes`, and `bytes --> wire`
+        assert_eq!(render_count() - count, 3);
+
+        assert_eq!(docs.len(), 3);
+        assert!(
+            docs[0].starts_with(" Converts `u8` to bytes."),
+            "{}",
+            docs[0]
+        );
+        assert!(
+            docs[0].contains("![u8 \\-\\-\\> bytes](data:"),
+            "{}",
+            docs[0]
+        );
+        assert!(
+            docs[0].contains("![bytes \\-\\-\\> wire](data:"),
+            "{}",
+            docs[0]
+        );
+        assert!(
+            docs[1].contains("![Vec\\<u8\\> \\-\\-\\> bytes](data:"),
+            "{}",
+            docs[1]
+        );
+        assert_eq!(docs[2], docs[0]);
+    }
+
+    #[test]
+    fn expand_impls() {
+        let input: TraitImpls = syn::parse_str(
+            r#"
+            /// `{Type}`
+            #[inline]
+            impl a::Trait for [u8, &'static str] { fn f() {} }
+            "#,
+        )
+        .unwrap();
+        let output = expand(input, &mut TextProcState::new(Options::default()))
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            output,
+            quote! {
+                #[doc = " `u8`\n"]
+                #[inline]
+                impl a::Trait for u8 { fn f() {} }
+                #[doc = " `&'static str`\n"]
+                #[inline]
+                impl a::Trait for &'static str { fn f() {} }
+            }
+            .to_string()
+        );
+    }
+}